@@ -5,6 +5,7 @@ use octahack::{
         synth::{freq, output::Specifier as Out, params::Specifier as Params, Synth},
         OctahackComponent,
     },
+    output::device::Driver,
     rack::{AsParam, Param, Settable},
     Rack, Value, WireDst, WireSrc,
 };
@@ -21,7 +22,7 @@ impl Default for self::any::Params {
     }
 }
 
-use rodio::Source;
+use std::{thread, time::Duration};
 
 fn main() {
     let mut rack = Rack::<OctahackComponent, any::Specifier, any::Specifier>::new();
@@ -35,7 +36,8 @@ fn main() {
     main.wire(
         WireSrc::component_output(carrier, Out::Sine),
         WireDst::func_output(any::Specifier::OneChannel),
-    );
+    )
+    .unwrap();
     main.set_param(modulator, Params::Freq, freq(440));
 
     {
@@ -53,10 +55,14 @@ fn main() {
 
     // println!("{}", rack);
 
-    let streamer =
-        octahack::output::AudioStreamer::new_convert(None, rack, rodio::source::SineWave::new(440))
-            .convert_samples::<f32>();
+    let driver = Driver::new(rack, Default::default()).unwrap();
+    driver.start();
 
-    rodio::play_raw(&rodio::default_output_device().unwrap(), streamer);
-    loop {}
+    loop {
+        thread::sleep(Duration::from_secs(1));
+
+        if driver.late_callbacks() > 0 {
+            eprintln!("{} late callbacks", driver.late_callbacks());
+        }
+    }
 }
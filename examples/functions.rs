@@ -5,6 +5,7 @@ use octahack::{
         synth::{freq, output::Specifier as Out, params::Specifier as Params, Synth},
         OctahackComponent,
     },
+    output::device::Driver,
     Rack, Value, WireDst, WireSrc,
 };
 
@@ -20,7 +21,7 @@ impl Default for self::any::Params {
     }
 }
 
-use rodio::Source;
+use std::{thread, time::Duration};
 
 fn main() {
     let mut rack = Rack::<OctahackComponent, any::Specifier, any::Specifier>::new();
@@ -32,7 +33,8 @@ fn main() {
     func.wire(
         WireSrc::component_output(modulator_modulator, Out::Saw),
         WireDst::func_output(any::Specifier::OneChannel),
-    );
+    )
+    .unwrap();
     func.set_param::<_, Value>(modulator_modulator, Params::Freq, freq(0.5));
 
     let modulator_func_id = rack.new_func();
@@ -42,11 +44,13 @@ fn main() {
     func.wire(
         WireSrc::func_input(any::Specifier::OneChannel),
         WireDst::component_param(modulator, Params::Freq, freq(55)),
-    );
+    )
+    .unwrap();
     func.wire(
         WireSrc::component_output(modulator, Out::Sine),
         WireDst::func_output(any::Specifier::OneChannel),
-    );
+    )
+    .unwrap();
     func.set_param::<_, Value>(modulator, Params::Freq, freq(220));
 
     let out_func_id = rack.new_func();
@@ -58,15 +62,18 @@ fn main() {
     func.wire(
         WireSrc::component_output(modulator_modulator, any::Specifier::OneChannel),
         WireDst::component_input(modulator, any::Specifier::OneChannel),
-    );
+    )
+    .unwrap();
     func.wire(
         WireSrc::component_output(modulator, any::Specifier::OneChannel),
         WireDst::component_param(carrier, Params::Freq, freq(880 * 2)),
-    );
+    )
+    .unwrap();
     func.wire(
         WireSrc::component_output(carrier, Out::Square),
         WireDst::func_output(any::Specifier::OneChannel),
-    );
+    )
+    .unwrap();
     func.set_param::<_, Value>(carrier, Params::Freq, freq(440));
 
     let mut main = rack.main_mut();
@@ -75,14 +82,19 @@ fn main() {
     main.wire(
         WireSrc::component_output(fcall, any::Specifier::OneChannel),
         WireDst::func_output(any::Specifier::OneChannel),
-    );
+    )
+    .unwrap();
 
     println!("{}", rack);
 
-    let streamer =
-        octahack::output::AudioStreamer::new_convert(None, rack, rodio::source::SineWave::new(440))
-            .convert_samples::<f32>();
+    let driver = Driver::new(rack, Default::default()).unwrap();
+    driver.start();
 
-    rodio::play_raw(&rodio::default_output_device().unwrap(), streamer);
-    loop {}
+    loop {
+        thread::sleep(Duration::from_secs(1));
+
+        if driver.late_callbacks() > 0 {
+            eprintln!("{} late callbacks", driver.late_callbacks());
+        }
+    }
 }
@@ -0,0 +1,261 @@
+//! Buffers for running a rack a block of frames at a time. Instead of walking the graph from each
+//! output once per frame, `Rack::process_block` runs each component for the whole block before
+//! moving on to the next one, and components read their inputs from the buffers of the
+//! components before them.
+
+use crate::{
+    components::{AnyIter, PossiblyIter},
+    MidiValue, RefRuntimeSpecifier, SpecId, Value, ValueKind, ValueType,
+};
+use std::{iter, slice};
+
+/// Every frame of one output over a block, where each frame can have a different number of
+/// channels.
+#[derive(Debug, Clone)]
+pub struct Frames<T> {
+    samples: Vec<T>,
+    /// The index in `samples` of the end of each frame.
+    ends: Vec<usize>,
+}
+
+impl<T> Default for Frames<T> {
+    fn default() -> Self {
+        Frames {
+            samples: vec![],
+            ends: vec![],
+        }
+    }
+}
+
+impl<T> Frames<T> {
+    /// The number of frames
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    /// The channels of frame `i`.
+    pub fn frame(&self, i: usize) -> &[T] {
+        let start = if i == 0 { 0 } else { self.ends[i - 1] };
+
+        &self.samples[start..self.ends[i]]
+    }
+
+    pub fn push_frame(&mut self, frame: impl IntoIterator<Item = T>) {
+        self.samples.extend(frame);
+        self.ends.push(self.samples.len());
+    }
+
+    /// Remove every frame, keeping the memory for the next block.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.ends.clear();
+    }
+}
+
+/// The values of one frame of a `Block`, in the same form as an output read for a single frame.
+pub type BlockIter<'a> =
+    AnyIter<iter::Copied<slice::Iter<'a, MidiValue>>, iter::Copied<slice::Iter<'a, Value>>>;
+
+/// Every frame of one output over a block.
+#[derive(Debug, Clone)]
+pub enum Block {
+    Continuous(Frames<Value>),
+    Midi(Frames<MidiValue>),
+}
+
+impl Block {
+    pub fn new(ty: ValueType) -> Self {
+        match ty.kind {
+            ValueKind::Midi => Block::Midi(Default::default()),
+            _ => Block::Continuous(Default::default()),
+        }
+    }
+
+    /// Whether this block can hold the values of an output of type `ty`.
+    fn holds(&self, ty: ValueType) -> bool {
+        match self {
+            Block::Continuous(_) => ty.kind != ValueKind::Midi,
+            Block::Midi(_) => ty.kind == ValueKind::Midi,
+        }
+    }
+
+    /// The number of frames
+    #[inline]
+    pub fn len(&self) -> usize {
+        match self {
+            Block::Continuous(frames) => frames.len(),
+            Block::Midi(frames) => frames.len(),
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        match self {
+            Block::Continuous(frames) => frames.clear(),
+            Block::Midi(frames) => frames.clear(),
+        }
+    }
+
+    /// The values of frame `i`.
+    pub fn read(&self, i: usize) -> BlockIter<'_> {
+        match self {
+            Block::Continuous(frames) => AnyIter::analog(frames.frame(i).iter().copied()),
+            Block::Midi(frames) => AnyIter::midi(frames.frame(i).iter().copied()),
+        }
+    }
+}
+
+/// Every output of a component, or of a rack, over a block, indexed by the ID of the output.
+#[derive(Debug, Clone, Default)]
+pub struct BlockOutputs {
+    blocks: Vec<Block>,
+}
+
+impl BlockOutputs {
+    /// Empty buffers for outputs of the types `types`, in order of ID.
+    pub fn new(types: impl IntoIterator<Item = ValueType>) -> Self {
+        BlockOutputs {
+            blocks: types.into_iter().map(Block::new).collect(),
+        }
+    }
+
+    /// Remove every frame and make sure that there's a buffer for each of `types`, keeping the
+    /// memory of any buffers that are still the right kind.
+    pub fn reset(&mut self, types: impl ExactSizeIterator<Item = ValueType>) {
+        self.blocks.truncate(types.len());
+
+        for (i, ty) in types.enumerate() {
+            match self.blocks.get_mut(i) {
+                Some(block) if block.holds(ty) => block.clear(),
+                Some(block) => *block = Block::new(ty),
+                None => self.blocks.push(Block::new(ty)),
+            }
+        }
+    }
+
+    /// The number of outputs
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    #[inline]
+    pub fn get(&self, id: SpecId) -> Option<&Block> {
+        self.blocks.get(id)
+    }
+
+    /// The values of output `id` at frame `i`.
+    #[inline]
+    pub fn read(&self, id: SpecId, i: usize) -> BlockIter<'_> {
+        self.blocks[id].read(i)
+    }
+
+    /// Add a frame to a continuous output.
+    ///
+    /// # Panics
+    ///
+    /// If `spec` is a MIDI output.
+    pub fn push_values(
+        &mut self,
+        spec: impl RefRuntimeSpecifier,
+        frame: impl IntoIterator<Item = Value>,
+    ) {
+        match &mut self.blocks[spec.id()] {
+            Block::Continuous(frames) => frames.push_frame(frame),
+            Block::Midi(_) => panic!("`{}` is a MIDI output", spec),
+        }
+    }
+
+    /// Add a frame to a MIDI output.
+    ///
+    /// # Panics
+    ///
+    /// If `spec` isn't a MIDI output.
+    pub fn push_midi(
+        &mut self,
+        spec: impl RefRuntimeSpecifier,
+        frame: impl IntoIterator<Item = MidiValue>,
+    ) {
+        match &mut self.blocks[spec.id()] {
+            Block::Midi(frames) => frames.push_frame(frame),
+            Block::Continuous(_) => panic!("`{}` isn't a MIDI output", spec),
+        }
+    }
+
+    /// Add a frame to output `id` from the value of the output for that frame, whichever kind of
+    /// output it is.
+    pub fn push<I>(&mut self, id: SpecId, iter: I)
+    where
+        I: PossiblyIter<Value> + PossiblyIter<MidiValue>,
+    {
+        match &mut self.blocks[id] {
+            Block::Continuous(frames) => frames.push_frame(
+                PossiblyIter::<Value>::try_iter(iter).unwrap_or_else(|_| unreachable!()),
+            ),
+            Block::Midi(frames) => frames.push_frame(
+                PossiblyIter::<MidiValue>::try_iter(iter).unwrap_or_else(|_| unreachable!()),
+            ),
+        }
+    }
+
+    /// Add a frame with no channels to output `id`, for when it isn't wired.
+    pub fn push_empty(&mut self, id: SpecId) {
+        match &mut self.blocks[id] {
+            Block::Continuous(frames) => frames.push_frame(None),
+            Block::Midi(frames) => frames.push_frame(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Block, BlockOutputs};
+    use crate::{components::PossiblyIter, Value, ValueType};
+
+    #[test]
+    fn frames_of_different_widths() {
+        let mut outputs = BlockOutputs::new(vec![ValueType::mono(), ValueType::midi()]);
+
+        let mut source = Block::new(ValueType::mono());
+        if let Block::Continuous(frames) = &mut source {
+            frames.push_frame(vec![1., 2.]);
+        }
+
+        outputs.push(0, source.read(0));
+        outputs.push_empty(0);
+        outputs.push_empty(1);
+
+        let read = |i| {
+            PossiblyIter::<Value>::try_iter(outputs.read(0, i))
+                .unwrap_or_else(|_| unreachable!())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(read(0), [1., 2.]);
+        assert_eq!(read(1), Vec::<Value>::new());
+        assert_eq!(outputs.get(1).unwrap().len(), 1);
+
+        outputs.reset(vec![ValueType::midi()].into_iter());
+        assert_eq!(outputs.len(), 1);
+        assert!(outputs.get(0).unwrap().is_empty());
+        assert!(match outputs.get(0).unwrap() {
+            Block::Midi(_) => true,
+            _ => false,
+        });
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{
+    block::BlockOutputs,
     components::PossiblyIter,
     context::ContextMeta,
     params::{ParamStorage, StorageMut},
@@ -85,6 +86,26 @@ macro_rules! component_set {
                         )*
                     }
                 }
+
+                fn serialize(&self, spec: &Self::Specifier, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    match self {
+                        $(
+                            Self::$t(inner) => {
+                                $crate::params::ParamStorage::serialize(inner, &$crate::RuntimeSpecifier::from_id(spec.0), f)
+                            },
+                        )*
+                    }
+                }
+
+                fn parse(&mut self, spec: &Self::Specifier, text: &str) -> bool {
+                    match self {
+                        $(
+                            Self::$t(inner) => {
+                                $crate::params::ParamStorage::parse(inner, &$crate::RuntimeSpecifier::from_id(spec.0), text)
+                            },
+                        )*
+                    }
+                }
             }
 
             #[derive(Clone)]
@@ -202,6 +223,16 @@ macro_rules! component_set {
                 }
             }
 
+            impl $crate::components::anycomponent::AllComponents for Component
+            where $( super::$t: Default ),*
+            {
+                type Iter = std::vec::IntoIter<Self>;
+
+                fn all_components() -> Self::Iter {
+                    vec![$( Component::$t(Default::default()) ),*].into_iter()
+                }
+            }
+
             impl<'a> $crate::components::anycomponent::AnyUiElementDisplayParamValue<'a> for &'a Component {
                 type ParamStorage = ParamStorage;
                 type Display = impl std::fmt::Display;
@@ -344,6 +375,61 @@ macro_rules! component_set {
                         )*
                     }
                 }
+
+                #[allow(unreachable_code)]
+                fn process_block<Ctx>(&self, ctx: &Ctx, outputs: &mut $crate::block::BlockOutputs) -> Self
+                where
+                    Ctx: $crate::components::anycomponent::AnyBlockContext<ParamStorage = Self::ParamStorage, InputStorage = Self::InputStorage>
+                {
+                    match self {
+                        $(
+                            Self::$t(val) => {
+                                use $crate::{
+                                    components::EnumerateValues,
+                                    context::ContextForComponent,
+                                    params::Output,
+                                    RefRuntimeSpecifier,
+                                    RuntimeSpecifier,
+                                };
+
+                                outputs.reset(
+                                    <
+                                        <super::$t as $crate::Component>::OutputSpecifier as
+                                            EnumerateValues
+                                    >::values().map(|v| v.value_type())
+                                );
+
+                                if let Some(new) = val.process_block(
+                                    &$crate::context::BlockContextForComponent::<_, super::$t>::new(ctx),
+                                    outputs,
+                                ) {
+                                    return Self::$t(new);
+                                }
+
+                                let mut val = val.clone();
+
+                                for i in 0..ctx.frames() {
+                                    let frame = ContextForComponent::<_, super::$t>::new(ctx.frame(i));
+
+                                    val = val.update(&frame);
+
+                                    for spec in <
+                                        <super::$t as $crate::Component>::OutputSpecifier as
+                                            EnumerateValues
+                                    >::values() {
+                                        outputs.push(
+                                            spec.id(),
+                                            <super::$t as $crate::Component>::OutputSpecifier::from_id(spec.id())
+                                                .get_output(&val, &frame),
+                                        );
+                                    }
+                                }
+
+                                Self::$t(val)
+                            },
+                        )*
+                    }
+                }
             }
         }
     }
@@ -411,6 +497,14 @@ pub trait AnyUiElement<'a> {
     fn param_names(self) -> Self::ParamNames;
 }
 
+/// A set of components which can list a default instance of every kind of component it contains,
+/// used to create components by name (for example, when loading a saved rack).
+pub trait AllComponents: Sized {
+    type Iter: Iterator<Item = Self>;
+
+    fn all_components() -> Self::Iter;
+}
+
 pub trait AnyUiElementDisplayParamValue<'a>: AnyUiElement<'a> {
     type ParamStorage;
     type Display: fmt::Display;
@@ -430,6 +524,52 @@ pub trait AnyContext: AnyMeta {
     type Iter: PossiblyIter<Value> + PossiblyIter<MidiEventType>;
 
     fn read_wire(&self, wire: Wire<marker::Output>) -> Option<Self::Iter>;
+    /// The value that replaces the natural value of `param` of the current component, either
+    /// from a function param that forwards to it or from the active scenes, or `None` if the
+    /// natural value should be used as-is.
+    fn natural_value_override(&self, param: AnyParamSpec) -> Option<Value>;
+}
+
+impl<T> AnyMeta for &'_ T
+where
+    T: AnyMeta + ?Sized,
+{
+    type ParamStorage = T::ParamStorage;
+    type InputStorage = T::InputStorage;
+
+    fn params(&self) -> &Self::ParamStorage {
+        (**self).params()
+    }
+
+    fn inputs(&self) -> &Self::InputStorage {
+        (**self).inputs()
+    }
+}
+
+impl<T> AnyContext for &'_ T
+where
+    T: AnyContext + ?Sized,
+{
+    type Iter = T::Iter;
+
+    fn read_wire(&self, wire: Wire<marker::Output>) -> Option<Self::Iter> {
+        (**self).read_wire(wire)
+    }
+
+    fn natural_value_override(&self, param: AnyParamSpec) -> Option<Value> {
+        (**self).natural_value_override(param)
+    }
+}
+
+/// The context of a component that's processing a block of frames, where each frame has the
+/// same context that the component would get if it were run one frame at a time.
+pub trait AnyBlockContext: AnyMeta + ContextMeta {
+    type Frame: AnyContext<ParamStorage = Self::ParamStorage, InputStorage = Self::InputStorage>
+        + ContextMeta;
+
+    /// The number of frames in this block
+    fn frames(&self) -> usize;
+    fn frame(&self, i: usize) -> Self::Frame;
 }
 
 pub trait AnyComponent: Sized {
@@ -453,4 +593,11 @@ pub trait AnyComponent: Sized {
     where
         Ctx: AnyContext<ParamStorage = Self::ParamStorage, InputStorage = Self::InputStorage>
             + ContextMeta;
+
+    /// Run this component for every frame of a block, writing each of its outputs for each frame
+    /// to `outputs`, and return its state after the last frame. Components that don't process
+    /// blocks themselves are updated and have every output read once per frame.
+    fn process_block<Ctx>(&self, ctx: &Ctx, outputs: &mut BlockOutputs) -> Self
+    where
+        Ctx: AnyBlockContext<ParamStorage = Self::ParamStorage, InputStorage = Self::InputStorage>;
 }
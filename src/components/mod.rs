@@ -3,6 +3,8 @@ pub mod anycomponent;
 pub use anycomponent::{AnyComponent, AnyInputSpec, AnyOutputSpec, AnyParamSpec, Types};
 
 use crate::{
+    block::BlockOutputs,
+    context::BlockContext,
     params::{HasParamStorage, Key},
     Context, Value, ValueType,
 };
@@ -18,6 +20,23 @@ pub trait RefRuntimeSpecifier: fmt::Display {
     fn value_type(&self) -> ValueType;
 }
 
+/// The type of an input, output or parameter whose values are of type `Self`.
+pub trait HasValueType {
+    const VALUE_TYPE: ValueType;
+}
+
+impl HasValueType for Value {
+    const VALUE_TYPE: ValueType = ValueType::mono();
+}
+
+impl HasValueType for MidiEventType {
+    const VALUE_TYPE: ValueType = ValueType::midi();
+}
+
+impl<Kind> HasValueType for Option<crate::context::FileId<Kind>> {
+    const VALUE_TYPE: ValueType = ValueType::file();
+}
+
 pub trait RuntimeSpecifier: RefRuntimeSpecifier + Sized {
     fn from_id(id: SpecId) -> Self;
 }
@@ -60,6 +79,17 @@ pub trait Component: Clone + Sized {
     fn update<Ctx>(&self, _ctx: &Ctx) -> Self
     where
         Ctx: Context<Self>;
+
+    /// Run this component for every frame of a block at once, writing every output for each
+    /// frame to `outputs` and returning the state after the last frame. This must give the same
+    /// results as calling `update` and then reading every output once per frame, which is what
+    /// happens for components that return `None` here, as the default implementation does.
+    fn process_block<Ctx>(&self, _ctx: &Ctx, _outputs: &mut BlockOutputs) -> Option<Self>
+    where
+        Ctx: BlockContext<Self>,
+    {
+        None
+    }
 }
 
 pub trait GetOutput<Spec: Key>: Component {
@@ -160,6 +190,18 @@ where
 
 pub struct AnyIter<A, B>(AnyIterInner<A, B>);
 
+impl<A, B> AnyIter<A, B> {
+    #[inline]
+    pub fn midi(iter: A) -> Self {
+        AnyIter(AnyIterInner::Midi(iter))
+    }
+
+    #[inline]
+    pub fn analog(iter: B) -> Self {
+        AnyIter(AnyIterInner::Analog(iter))
+    }
+}
+
 impl<A> Default for AnyIter<A, NoIter<Value>>
 where
     A: Default,
@@ -1,11 +1,15 @@
 use crate::{
-    components::{anycomponent::AnyContext, PossiblyIter},
+    components::{
+        anycomponent::{AnyBlockContext, AnyContext},
+        PossiblyIter,
+    },
+    files::{Files, NO_FILES},
     params::{HasParamStorage, HasStorage, Key, Param, ParamStorageGet, StorageGet},
     rack::InternalWire,
-    Component, Value,
+    AnyParamSpec, Component, Value,
 };
 use nom_midi::MidiEventType;
-use std::{convert::TryInto, marker::PhantomData, time::Duration};
+use std::{convert::TryInto, fmt, marker::PhantomData, num::NonZeroU8, time::Duration};
 
 pub struct FileId<Kind> {
     index: usize,
@@ -23,41 +27,61 @@ impl<Kind> Clone for FileId<Kind> {
 
 impl<Kind> Copy for FileId<Kind> {}
 
-pub trait File<Kind> {
-    type SamplesAt: ExactSizeIterator<Item = Kind>;
-    type SamplesBetween: ExactSizeIterator<Item = Self::SamplesAt>;
+impl<Kind> FileId<Kind> {
+    pub(crate) fn new(index: usize) -> Self {
+        FileId {
+            index,
+            _marker: PhantomData,
+        }
+    }
 
-    fn at(&self, dur: Duration) -> Self::SamplesAt;
-    fn between(&self, last: Duration, dur: Duration) -> Self::SamplesBetween;
+    pub(crate) fn index(self) -> usize {
+        self.index
+    }
 }
 
-impl<Kind> File<Kind> for ! {
-    type SamplesAt = std::iter::Empty<Kind>;
-    type SamplesBetween = std::iter::Empty<Self::SamplesAt>;
+/// Displays the value of a file param, for `DisplayParam`.
+pub(crate) struct DisplayFile<Kind>(pub Option<FileId<Kind>>);
 
-    fn at(&self, dur: Duration) -> Self::SamplesAt {
-        todo!()
+impl<Kind> fmt::Display for DisplayFile<Kind> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(id) => write!(f, "File {}", id.index()),
+            None => write!(f, "None"),
+        }
     }
+}
 
-    fn between(&self, last: Duration, dur: Duration) -> Self::SamplesBetween {
-        todo!()
-    }
+/// Something that files can hold, along with how a position in that kind of file is given.
+pub trait FileKind {
+    type Position: Copy;
 }
 
-pub trait FileAccess<Kind> {
-    type ReadFile: File<Kind>;
+/// Audio files are timed in seconds.
+impl FileKind for Value {
+    type Position = Duration;
+}
 
-    // Will always read the file from the start
-    fn read(&self, id: FileId<Kind>) -> Self::ReadFile;
+/// MIDI files are timed in beats, so that they follow the tempo of the transport.
+impl FileKind for MidiEventType {
+    type Position = Value;
 }
 
-impl<T, Kind> FileAccess<Kind> for T {
-    type ReadFile = !;
+pub trait File<Kind: FileKind> {
+    type SamplesAt: ExactSizeIterator<Item = Kind>;
+    type SamplesBetween: ExactSizeIterator<Item = Self::SamplesAt>;
+
+    /// The position of the end of the file.
+    fn duration(&self) -> Kind::Position;
+    fn at(&self, pos: Kind::Position) -> Self::SamplesAt;
+    fn between(&self, last: Kind::Position, pos: Kind::Position) -> Self::SamplesBetween;
+}
+
+pub trait FileAccess<Kind: FileKind> {
+    type ReadFile: File<Kind>;
 
     // Will always read the file from the start
-    fn read(&self, id: FileId<Kind>) -> Self::ReadFile {
-        todo!()
-    }
+    fn read(&self, id: FileId<Kind>) -> Self::ReadFile;
 }
 
 pub trait GetFunctionParam {
@@ -86,13 +110,115 @@ pub trait GetParam<Spec: HasParamStorage> {
         T::Value: Param;
 }
 
+/// A time signature, such as 3/4 for `beats: 3, unit: 4`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimeSignature {
+    /// Beats per bar
+    pub beats: NonZeroU8,
+    /// The note value of one beat, where 4 is a quarter note
+    pub unit: NonZeroU8,
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        let four = NonZeroU8::new(4).unwrap();
+
+        TimeSignature {
+            beats: four,
+            unit: four,
+        }
+    }
+}
+
+/// The musical time that a rack is being played at, shared by every component in the rack so
+/// that they can sync to it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transport {
+    /// Beats per minute, where a beat is one `time_signature.unit` note
+    pub tempo: Value,
+    pub time_signature: TimeSignature,
+    /// Whether the transport is moving. While it's stopped `position` and `beat` stay the same,
+    /// although the rack is still updated.
+    pub playing: bool,
+    /// The number of samples played since the start
+    pub position: u64,
+    /// The number of beats played since the start, including the fraction of the current beat.
+    /// This is counted up as the transport moves instead of being worked out from `position`, so
+    /// that changing the tempo doesn't make it jump.
+    pub beat: Value,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Transport {
+            tempo: 120.,
+            time_signature: Default::default(),
+            playing: true,
+            position: 0,
+            beat: 0.,
+        }
+    }
+}
+
+impl Transport {
+    /// Move on by one sample at `sample_rate`, if the transport is playing.
+    #[inline]
+    pub fn advance(&mut self, sample_rate: u32) {
+        if self.playing {
+            self.position += 1;
+            self.beat += self.tempo / (60. * Value::from(sample_rate));
+        }
+    }
+
+    /// Move to `position` samples since the start, taking the beat to be where it would be if
+    /// the whole of that time had been played at the current tempo.
+    #[inline]
+    pub fn seek(&mut self, position: u64, sample_rate: u32) {
+        self.position = position;
+        self.beat = position as Value * self.tempo / (60. * Value::from(sample_rate));
+    }
+}
+
 pub trait ContextMeta {
     /// Samples per second
     fn sample_rate(&self) -> u32;
+    fn transport(&self) -> Transport;
+
+    /// The files that components can read with `FileAccess`.
+    fn files(&self) -> &Files {
+        &NO_FILES
+    }
+}
+
+impl<T> ContextMeta for &'_ T
+where
+    T: ContextMeta + ?Sized,
+{
+    fn sample_rate(&self) -> u32 {
+        (**self).sample_rate()
+    }
+
+    fn transport(&self) -> Transport {
+        (**self).transport()
+    }
+
+    fn files(&self) -> &Files {
+        (**self).files()
+    }
 }
 
 pub trait ContextMetaExt: ContextMeta {
     fn sample_duration(&self) -> Duration;
+    /// The number of samples in one beat at the current tempo.
+    fn samples_per_beat(&self) -> Value;
+    /// The number of beats since the start, including the fraction of the current beat.
+    fn beat_position(&self) -> Value;
+    /// How far through the current beat we are, from 0 to 1.
+    fn beat_phase(&self) -> Value;
+    /// The number of bars since the start, including the fraction of the current bar.
+    fn bar_position(&self) -> Value;
+    /// How far through the current bar we are, from 0 to 1.
+    fn bar_phase(&self) -> Value;
 }
 
 impl<T> ContextMetaExt for T
@@ -102,29 +228,72 @@ where
     fn sample_duration(&self) -> Duration {
         Duration::from_secs(1) / self.sample_rate()
     }
+
+    fn samples_per_beat(&self) -> Value {
+        self.sample_rate() as Value * 60. / self.transport().tempo
+    }
+
+    fn beat_position(&self) -> Value {
+        self.transport().beat
+    }
+
+    fn beat_phase(&self) -> Value {
+        self.beat_position().fract()
+    }
+
+    fn bar_position(&self) -> Value {
+        self.beat_position() / self.transport().time_signature.beats.get() as Value
+    }
+
+    fn bar_phase(&self) -> Value {
+        self.bar_position().fract()
+    }
 }
 
 pub trait Context<C: Component>:
     // TODO: Can we bound in such a way that will be `FileAccess` for any `T` for which we have a `FileId<T>`
     //       parameter?
-    GetInput<C::InputSpecifier> + GetParam<C::ParamSpecifier> + ContextMeta + FileAccess<Value>
+    GetInput<C::InputSpecifier>
+        + GetParam<C::ParamSpecifier>
+        + ContextMeta
+        + FileAccess<Value>
+        + FileAccess<MidiEventType>
 {
 }
 
 impl<T, C> Context<C> for T
 where
     C: Component,
-    T: GetInput<C::InputSpecifier> + GetParam<C::ParamSpecifier> + ContextMeta + FileAccess<Value>,
+    T: GetInput<C::InputSpecifier>
+        + GetParam<C::ParamSpecifier>
+        + ContextMeta
+        + FileAccess<Value>
+        + FileAccess<MidiEventType>,
 {
 }
 
-pub struct ContextForComponent<'a, Ctx, C> {
-    ctx: &'a Ctx,
+/// The context of a component that's processing a whole block of frames at once, with
+/// `Component::process_block`.
+pub trait BlockContext<C: Component>: ContextMeta {
+    type Frame: Context<C>;
+
+    /// The number of frames in this block
+    fn frames(&self) -> usize;
+    /// The context of frame `i` of this block, which is the same as the context that `update` and
+    /// `output` would get for that frame if the component were run one frame at a time.
+    fn frame(&self, i: usize) -> Self::Frame;
+}
+
+/// The context of a single component of type `C`, from the context of a component of any type.
+/// `Ctx` is usually a reference to that context, but a context can also be owned, such as the
+/// context of one frame of a block.
+pub struct ContextForComponent<Ctx, C> {
+    ctx: Ctx,
     _marker: PhantomData<C>,
 }
 
-impl<'a, Ctx, C> ContextForComponent<'a, Ctx, C> {
-    pub fn new(ctx: &'a Ctx) -> Self {
+impl<Ctx, C> ContextForComponent<Ctx, C> {
+    pub fn new(ctx: Ctx) -> Self {
         Self {
             ctx,
             _marker: PhantomData,
@@ -132,16 +301,24 @@ impl<'a, Ctx, C> ContextForComponent<'a, Ctx, C> {
     }
 }
 
-impl<'a, Ctx, C> ContextMeta for ContextForComponent<'a, Ctx, C>
+impl<Ctx, C> ContextMeta for ContextForComponent<Ctx, C>
 where
     Ctx: ContextMeta,
 {
     fn sample_rate(&self) -> u32 {
         self.ctx.sample_rate()
     }
+
+    fn transport(&self) -> Transport {
+        self.ctx.transport()
+    }
+
+    fn files(&self) -> &Files {
+        self.ctx.files()
+    }
 }
 
-impl<'a, Ctx, C> GetInput<C::InputSpecifier> for ContextForComponent<'a, Ctx, C>
+impl<Ctx, C> GetInput<C::InputSpecifier> for ContextForComponent<Ctx, C>
 where
     C: Component,
     C::InputSpecifier: HasStorage<InternalWire>,
@@ -173,7 +350,7 @@ where
     }
 }
 
-impl<'a, Ctx, C> GetParam<C::ParamSpecifier> for ContextForComponent<'a, Ctx, C>
+impl<Ctx, C> GetParam<C::ParamSpecifier> for ContextForComponent<Ctx, C>
 where
     C: Component,
     Ctx: AnyContext,
@@ -186,13 +363,67 @@ where
         <C::ParamSpecifier as HasParamStorage>::Storage: ParamStorageGet<T>,
         T::Value: Param,
     {
-        let (nat_val, wire) = self
+        let storage: &<C::ParamSpecifier as HasParamStorage>::Storage = self
             .ctx
             .params()
             .try_into()
-            .unwrap_or_else(|_| unreachable!())
-            .get();
+            .unwrap_or_else(|_| unreachable!());
+        let (nat_val, wire) = ParamStorageGet::<T>::get(storage);
+        let id = <<C::ParamSpecifier as HasParamStorage>::Storage as ParamStorageGet<T>>::id();
+
+        match self.ctx.natural_value_override(AnyParamSpec(id)) {
+            Some(natural) => nat_val.access_with_natural(natural, wire, &self.ctx),
+            None => nat_val.access(wire, &self.ctx),
+        }
+    }
+}
+
+/// The block context of a single component of type `C`, from the block context of a component of
+/// any type.
+pub struct BlockContextForComponent<'a, Ctx, C> {
+    ctx: &'a Ctx,
+    _marker: PhantomData<C>,
+}
+
+impl<'a, Ctx, C> BlockContextForComponent<'a, Ctx, C> {
+    pub fn new(ctx: &'a Ctx) -> Self {
+        Self {
+            ctx,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, Ctx, C> ContextMeta for BlockContextForComponent<'a, Ctx, C>
+where
+    Ctx: ContextMeta,
+{
+    fn sample_rate(&self) -> u32 {
+        self.ctx.sample_rate()
+    }
+
+    fn transport(&self) -> Transport {
+        self.ctx.transport()
+    }
+
+    fn files(&self) -> &Files {
+        self.ctx.files()
+    }
+}
+
+impl<'a, Ctx, C> BlockContext<C> for BlockContextForComponent<'a, Ctx, C>
+where
+    C: Component,
+    Ctx: AnyBlockContext,
+    ContextForComponent<Ctx::Frame, C>: Context<C>,
+{
+    type Frame = ContextForComponent<Ctx::Frame, C>;
+
+    fn frames(&self) -> usize {
+        self.ctx.frames()
+    }
 
-        nat_val.access(wire, self.ctx)
+    fn frame(&self, i: usize) -> Self::Frame {
+        ContextForComponent::new(self.ctx.frame(i))
     }
 }
@@ -5,15 +5,16 @@ use crate::{
     },
     params::{HasStorage, ParamStorage, Storage},
     rack::{
-        DefsAndFuncHelper, ElementSpecifier, FuncDef, FuncId, FuncInstanceRef, GenericWire,
-        InternalParamWire, InternalWire, MapWithPath, Meta, Wire, WireSrc,
+        ComponentId, DefsAndFuncHelper, ElementSpecifier, FuncDef, FuncId, FuncInstanceRef,
+        GenericWire, InternalParamWire, InternalWire, MapWithPath, Meta, ParamWire, Wire, WireSrc,
     },
+    scene::SceneId,
     AnyComponent, AnyInputSpec, AnyOutputSpec, AnyParamSpec, Rack, RefRuntimeSpecifier,
     RuntimeSpecifier, SpecId, Uid, UidGen, UidMap, Value, XOrHasher,
 };
 use std::{fmt, marker::PhantomData};
 
-struct FnDisplay<F: Fn(&mut fmt::Formatter) -> fmt::Result>(F);
+pub(crate) struct FnDisplay<F: Fn(&mut fmt::Formatter) -> fmt::Result>(pub(crate) F);
 
 impl<F> fmt::Display for FnDisplay<F>
 where
@@ -30,6 +31,29 @@ fn print_spec<ISpec: RefRuntimeSpecifier + ?Sized>(i: &ISpec) -> impl fmt::Displ
     FnDisplay(move |f| write!(f, "{}", i))
 }
 
+struct DisplayAnySpec<S>(S);
+
+impl<S> fmt::Display for DisplayAnySpec<S>
+where
+    S: Iterator + Clone,
+    S::Item: RefRuntimeSpecifier,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{ ")?;
+        let mut ispeciter = self.0.clone();
+
+        if let Some(i) = ispeciter.next() {
+            write!(f, "{}", print_spec(&i))?;
+        }
+
+        for i in ispeciter {
+            write!(f, ", {}", print_spec(&i))?;
+        }
+
+        write!(f, " }}")
+    }
+}
+
 struct DisplayFunc<'a, C, Def, I, O, N>
 where
     C: AnyComponent,
@@ -55,7 +79,7 @@ where
 {
     #[inline]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let (print_wire, print_opt_wire) = {
+        let (print_opt_wire, print_param_wire) = {
             #[inline]
             fn print_wire_inner<'a, ISpec, C>(
                 wire: &'a WireSrc,
@@ -111,16 +135,57 @@ where
                 })
             }
 
+            #[inline]
+            fn print_param_wire_inner<'a, ISpec, C>(
+                wire: &'a ParamWire,
+                meta: &'a UidMap<Meta<C>>,
+                components: MapWithPath<'a, C>,
+            ) -> impl fmt::Display + 'a
+            where
+                ISpec: RuntimeSpecifier,
+                C: AnyComponent,
+                for<'any> &'any C: AnyUiElement<'any>,
+            {
+                FnDisplay(move |f| {
+                    write!(
+                        f,
+                        " + {} * ",
+                        print_wire_inner::<ISpec, _>(&wire.src, meta, components.as_ref())
+                    )?;
+
+                    // CV can itself be wired, which we write as nested brackets:
+                    // `value + wire * (cv + wire * cv)`
+                    let mut cv = &wire.cv;
+                    let mut depth = 0;
+                    while let Some(wire) = &cv.wire {
+                        write!(
+                            f,
+                            "({:?} + {} * ",
+                            cv.natural_value,
+                            print_wire_inner::<ISpec, _>(&wire.src, meta, components.as_ref())
+                        )?;
+                        cv = &wire.cv;
+                        depth += 1;
+                    }
+                    write!(f, "{:?}", cv.natural_value)?;
+                    for _ in 0..depth {
+                        write!(f, ")")?;
+                    }
+
+                    Ok(())
+                })
+            }
+
             (
                 |wire| {
-                    print_wire_inner::<InputSpec, _>(
+                    print_opt_wire_inner::<InputSpec, _>(
                         wire,
                         &self.func.meta_storage,
                         self.func.state_storage.as_ref(),
                     )
                 },
                 |wire| {
-                    print_opt_wire_inner::<InputSpec, _>(
+                    print_param_wire_inner::<InputSpec, _>(
                         wire,
                         &self.func.meta_storage,
                         self.func.state_storage.as_ref(),
@@ -131,6 +196,58 @@ where
 
         writeln!(f, "def {} {} -> {}:", self.name, self.inputs, self.outputs)?;
 
+        let param_name = |id: ComponentId, spec: AnyParamSpec| {
+            FnDisplay(move |f| match &self.func.meta_storage[id.0] {
+                Meta::Component(_) => write!(
+                    f,
+                    "{}.${}",
+                    id,
+                    self.func.state_storage[&id.0]
+                        .param_names()
+                        .nth(spec.0)
+                        .unwrap()
+                ),
+                Meta::Function { func_id, .. } => write!(
+                    f,
+                    "{}.${}",
+                    id,
+                    self.func.defs_and_func.get(*func_id).params[spec.0].name()
+                ),
+            })
+        };
+
+        // Function params and links are declared before the statements, as
+        // `$Name -> id.$Param, ..` and `link id.$Param, id.$Param, ..`
+        for param in &self.func.def().params {
+            write!(f, "    ${} ->", param.name())?;
+
+            for (i, (id, spec)) in param.targets().enumerate() {
+                write!(
+                    f,
+                    "{} {}",
+                    if i == 0 { "" } else { "," },
+                    param_name(id, spec)
+                )?;
+            }
+
+            writeln!(f)?;
+        }
+
+        for link in &self.func.def().links {
+            write!(f, "    link")?;
+
+            for (i, (id, spec)) in link.params().enumerate() {
+                write!(
+                    f,
+                    "{} {}",
+                    if i == 0 { "" } else { "," },
+                    param_name(id, spec)
+                )?;
+            }
+
+            writeln!(f)?;
+        }
+
         for i in &self.func.def().statements {
             match &self.func.meta_storage[i.0] {
                 Meta::Component(meta) => {
@@ -163,21 +280,19 @@ where
                             write!(
                                 f,
                                 "{}",
-                                component.display_param_value(AnyParamSpec(i), value)
+                                FnDisplay(|f| meta.params.serialize(&AnyParamSpec(i), f))
                             )?;
-                            if let Some(wire) =
-                                param_wire.downcast_ref::<InternalParamWire>().unwrap()
+                            // Params that can't be wired don't store a `ParamWire`
+                            if let Some(Some(wire)) = param_wire.downcast_ref::<InternalParamWire>()
                             {
-                                write!(
-                                    f,
-                                    " + {} * {}",
-                                    print_wire(&wire.src),
-                                    &wire.cv.natural_value
-                                )?;
-                                assert!(wire.cv.wire.is_none());
+                                write!(f, "{}", print_param_wire(wire))?;
                             }
 
-                            writeln!(f, ",")?;
+                            writeln!(
+                                f,
+                                ", # {}",
+                                component.display_param_value(AnyParamSpec(i), value)
+                            )?;
                         }
 
                         if inputs.len() > 0 {
@@ -191,9 +306,15 @@ where
                         }
 
                         writeln!(f, "    }}")?;
+                    } else {
+                        writeln!(f)?;
                     }
                 }
-                Meta::Function { func_id, inputs } => {
+                Meta::Function {
+                    func_id,
+                    params,
+                    inputs,
+                } => {
                     write!(f, "    {}: {{ ", i)?;
 
                     let mut iter = (&self.func.defs_and_func.get(*func_id).out_wires).into_iter();
@@ -206,13 +327,37 @@ where
                     write!(f, " }} = {}", func_id)?;
 
                     let mut any = false;
+                    let func_params = &self.func.defs_and_func.get(*func_id).params;
+
+                    for (func_param, param) in func_params.iter().zip(params) {
+                        if !any {
+                            writeln!(f, " {{")?;
+                        }
+
+                        write!(
+                            f,
+                            "        ${} = {:?}",
+                            func_param.name(),
+                            param.natural_value
+                        )?;
+                        if let Some(wire) = &param.wire {
+                            write!(f, "{}", print_param_wire(wire))?;
+                        }
+                        writeln!(f, ",")?;
+                        any = true;
+                    }
+
+                    let mut inputs = inputs.into_iter().peekable();
+                    if any && inputs.peek().is_some() {
+                        writeln!(f)?;
+                    }
 
                     for (name, wire) in inputs {
                         if !any {
                             writeln!(f, " {{")?;
                         }
 
-                        writeln!(f, "        {} = {}", name, print_opt_wire(wire))?;
+                        writeln!(f, "        {} = {},", name, print_opt_wire(wire))?;
                         any = true;
                     }
 
@@ -238,6 +383,28 @@ where
     }
 }
 
+/// Add `id` to `order` after every function that it calls, unless it's already there.
+fn callees_first<C>(
+    funcs: &UidMap<FuncDef<AnyInputSpec, AnyOutputSpec>>,
+    meta: &UidMap<Meta<C>>,
+    id: FuncId,
+    order: &mut Vec<FuncId>,
+) where
+    C: AnyComponent,
+{
+    if order.contains(&id) {
+        return;
+    }
+
+    for statement in &funcs[id.0].statements {
+        if let Meta::Function { func_id, .. } = &meta[statement.0] {
+            callees_first(funcs, meta, *func_id, order);
+        }
+    }
+
+    order.push(id);
+}
+
 impl<C, InputSpec, OutputSpec> fmt::Display for Rack<C, InputSpec, OutputSpec>
 where
     C: AnyComponent,
@@ -271,31 +438,30 @@ where
             }
         }
 
-        struct DisplayAnySpec<S>(S);
+        // Functions are written after the functions they call, so that the text can be read back
+        // in one pass, and in the same order every time the same rack is written
+        let mut order = vec![];
 
-        impl<S> fmt::Display for DisplayAnySpec<S>
-        where
-            S: Iterator + Clone,
-            S::Item: RefRuntimeSpecifier,
-        {
-            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                write!(f, "{{ ")?;
-                let mut ispeciter = self.0.clone();
+        for statement in &self.main.statements {
+            if let Meta::Function { func_id, .. } = &self.meta_storage[statement.0] {
+                callees_first(&self.funcs, &self.meta_storage, *func_id, &mut order);
+            }
+        }
 
-                if let Some(i) = ispeciter.next() {
-                    write!(f, "{}", print_spec(&i))?;
-                }
+        let mut uncalled = (&self.funcs)
+            .into_iter()
+            .map(|(id, _)| FuncId(id))
+            .filter(|id| !order.contains(id))
+            .collect::<Vec<_>>();
 
-                for i in ispeciter {
-                    write!(f, ", {}", print_spec(&i))?;
-                }
+        uncalled.sort_by_key(|id| id.0);
 
-                write!(f, " }}")
-            }
+        for id in uncalled {
+            callees_first(&self.funcs, &self.meta_storage, id, &mut order);
         }
 
-        for (id, func) in &self.funcs {
-            let id = FuncId(id);
+        for id in order {
+            let func = &self.funcs[id.0];
 
             writeln!(
                 f,
@@ -319,6 +485,88 @@ where
                 outputs: DisplaySpec::<OutputSpec>(PhantomData),
                 name: "Main"
             }
-        )
+        )?;
+
+        // Scenes and the crossfader are written after `Main`, as
+        // `scene id { id.$Param = value, .. }` and `crossfader { A = id, B = id, .. }`
+        let param_name = |id: ComponentId, param: SpecId| {
+            FnDisplay(move |f| match &self.meta_storage[id.0] {
+                Meta::Component(_) => write!(
+                    f,
+                    "{}.${}",
+                    id,
+                    self.state_storage[&id.0].param_names().nth(param).unwrap()
+                ),
+                Meta::Function { func_id, .. } => {
+                    write!(f, "{}.${}", id, self.funcs[func_id.0].params[param].name())
+                }
+            })
+        };
+        let scene_name = |scene: Option<SceneId>| {
+            FnDisplay(move |f| match scene {
+                Some(scene) => write!(f, "{}", scene),
+                None => write!(f, "NONE"),
+            })
+        };
+
+        for (id, scene) in self.scenes.sorted() {
+            writeln!(f)?;
+            writeln!(f, "scene {} {{", id)?;
+
+            for (component, param, value) in scene.sorted_values() {
+                writeln!(f, "    {} = {:?},", param_name(component, param), value)?;
+            }
+
+            writeln!(f, "}}")?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "crossfader {{")?;
+        writeln!(f, "    A = {},", scene_name(self.scene_a()))?;
+        writeln!(f, "    B = {},", scene_name(self.scene_b()))?;
+        writeln!(f, "    $Position = {:?},", self.crossfader())?;
+        match self.crossfader_input() {
+            Some(input) => writeln!(f, "    Input = {},", input)?,
+            None => writeln!(f, "    Input = NONE,")?,
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl<C, InputSpec, OutputSpec> Rack<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent,
+    OutputSpec: HasStorage<InternalWire>,
+    for<'any> &'any C:
+        AnyUiElement<'any> + AnyUiElementDisplayParamValue<'any, ParamStorage = C::ParamStorage>,
+{
+    /// Write `id` along with every function that it calls, in the format read by
+    /// `Rack::import_func`. This is how a group is saved to be loaded into another project.
+    pub fn export_func(&self, id: FuncId) -> impl fmt::Display + '_ {
+        FnDisplay(move |f| {
+            let mut order = vec![];
+            callees_first(&self.funcs, &self.meta_storage, id, &mut order);
+
+            for (i, id) in order.into_iter().enumerate() {
+                if i != 0 {
+                    writeln!(f)?;
+                }
+
+                writeln!(
+                    f,
+                    "{}",
+                    DisplayFunc {
+                        func: self.func(id),
+                        inputs: "{ .. }",
+                        outputs: DisplayAnySpec(
+                            (&self.funcs[id.0].out_wires).into_iter().map(|(s, _)| s)
+                        ),
+                        name: id,
+                    }
+                )?;
+            }
+
+            Ok(())
+        })
     }
 }
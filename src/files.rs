@@ -0,0 +1,633 @@
+//! The files that components can read with `FileAccess`, such as the audio played by
+//! `FilePlayer`. Files are loaded into memory when they're added so that reading them while the
+//! rack is playing never waits on the disk, and they're never removed so that a `FileId` stays
+//! valid for as long as the registry does. Files can also be written while the rack is playing,
+//! which is how recording works: a recording buffer is just a file that a recorder component
+//! writes into, so anything that can read a file can play it back straight away.
+
+use crate::{
+    context::{ContextMeta, File, FileAccess, FileId},
+    midi_file::MidiFile,
+    params::HasStorage,
+    rack::InternalWire,
+    AnyComponent, MidiValue, Rack, Value,
+};
+use fixed::types::I1F15;
+use rodio::{decoder::DecoderError, Decoder, Source};
+use std::{
+    io::{self, Read, Seek, Write},
+    iter,
+    ops::Range,
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::Duration,
+};
+
+/// A file that can be read by `FileAccess` and written while it's being read.
+pub type SharedFile<T> = Arc<RwLock<T>>;
+
+// A panic while a file was being written leaves it holding whatever was written before that,
+// which is still a valid file, so a poisoned lock is used as normal rather than panicking the
+// audio thread too.
+
+pub(crate) fn read_file<T>(file: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    file.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) fn write_file<T>(file: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    file.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The most channels of an audio file that are read or recorded at once. Any channels after these
+/// are ignored, so that reading a frame never needs to allocate.
+pub const MAX_CHANNELS: usize = 16;
+
+/// The samples of one frame of an audio file, as read by `File::at`.
+#[derive(Debug, Clone)]
+pub struct FrameSamples {
+    samples: [Value; MAX_CHANNELS],
+    range: Range<usize>,
+}
+
+impl FrameSamples {
+    /// The samples that haven't been read yet.
+    pub fn as_slice(&self) -> &[Value] {
+        &self.samples[self.range.clone()]
+    }
+}
+
+impl Default for FrameSamples {
+    fn default() -> Self {
+        FrameSamples {
+            samples: [0.; MAX_CHANNELS],
+            range: 0..0,
+        }
+    }
+}
+
+impl iter::FromIterator<Value> for FrameSamples {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = Value>,
+    {
+        let mut out = Self::default();
+
+        for (slot, sample) in out.samples.iter_mut().zip(iter) {
+            *slot = sample;
+            out.range.end += 1;
+        }
+
+        out
+    }
+}
+
+impl Iterator for FrameSamples {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        self.range.next().map(|i| self.samples[i])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl ExactSizeIterator for FrameSamples {}
+
+/// The frames of a shared audio file from `File::between`. Each frame is read as it's reached,
+/// so this doesn't hold the lock on the file and sees anything recorded in the meantime.
+#[derive(Debug, Clone)]
+pub struct SharedFrames {
+    file: SharedFile<AudioFile>,
+    frames: Range<usize>,
+}
+
+impl Iterator for SharedFrames {
+    type Item = FrameSamples;
+
+    fn next(&mut self) -> Option<FrameSamples> {
+        let index = self.frames.next()?;
+
+        Some(read_file(&self.file).frame(index).iter().copied().collect())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.frames.size_hint()
+    }
+}
+
+impl ExactSizeIterator for SharedFrames {}
+
+/// The sample format of a WAV file written by `AudioFile::write_wav`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WavFormat {
+    /// 16-bit integer samples, as on a CD
+    Int16,
+    /// 24-bit integer samples
+    Int24,
+    /// 32-bit float samples, which unlike the integer formats aren't clipped
+    Float32,
+}
+
+impl WavFormat {
+    fn bits(self) -> u16 {
+        match self {
+            WavFormat::Int16 => 16,
+            WavFormat::Int24 => 24,
+            WavFormat::Float32 => 32,
+        }
+    }
+}
+
+/// Audio held in memory, with the samples of each frame next to one another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioFile {
+    sample_rate: u32,
+    channels: usize,
+    samples: Vec<Value>,
+    /// The most frames that can be recorded into this file, or `None` if it can grow until we
+    /// run out of memory.
+    max_frames: Option<usize>,
+}
+
+impl Default for AudioFile {
+    fn default() -> Self {
+        AudioFile {
+            sample_rate: 44100,
+            channels: 1,
+            samples: vec![],
+            max_frames: None,
+        }
+    }
+}
+
+impl AudioFile {
+    /// Create a file from interleaved samples, dropping any samples after the last whole frame.
+    ///
+    /// # Panics
+    ///
+    /// If `channels` or `sample_rate` is 0.
+    pub fn new(sample_rate: u32, channels: usize, mut samples: Vec<Value>) -> Self {
+        assert!(sample_rate > 0, "Audio files must have a sample rate");
+        assert!(channels > 0, "Audio files must have at least one channel");
+
+        samples.truncate(samples.len() - samples.len() % channels);
+
+        AudioFile {
+            sample_rate,
+            channels,
+            samples,
+            max_frames: None,
+        }
+    }
+
+    /// An empty file to record into, which can hold at most `max_frames` frames.
+    pub fn buffer(max_frames: Option<usize>) -> Self {
+        AudioFile {
+            max_frames,
+            ..Default::default()
+        }
+    }
+
+    /// Decode a WAV file, or any other format that `rodio` can decode.
+    pub fn decode<R>(reader: R) -> Result<Self, DecoderError>
+    where
+        R: Read + Seek + Send + 'static,
+    {
+        let decoder = Decoder::new(reader)?;
+        let (sample_rate, channels) = (decoder.sample_rate(), decoder.channels());
+
+        Ok(AudioFile::new(
+            sample_rate,
+            channels.into(),
+            decoder.map(|val| I1F15::from_bits(val).into()).collect(),
+        ))
+    }
+
+    /// Write this file as a WAV file. Integer formats clip samples outside of -1 to 1.
+    pub fn write_wav<W>(&self, mut writer: W, format: WavFormat) -> io::Result<()>
+    where
+        W: Write,
+    {
+        let bytes_per_sample = format.bits() / 8;
+        let block_align = self.channels as u16 * bytes_per_sample;
+        let data_len = self.samples.len() as u32 * u32::from(bytes_per_sample);
+        // Float files need the extension size in `fmt ` and a `fact` chunk
+        let (tag, fmt_len, fact_len) = match format {
+            WavFormat::Float32 => (3u16, 18u32, 12u32),
+            _ => (1, 16, 0),
+        };
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(4 + 8 + fmt_len + fact_len + 8 + data_len).to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&fmt_len.to_le_bytes())?;
+        writer.write_all(&tag.to_le_bytes())?;
+        writer.write_all(&(self.channels as u16).to_le_bytes())?;
+        writer.write_all(&self.sample_rate.to_le_bytes())?;
+        writer.write_all(&(self.sample_rate * u32::from(block_align)).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&format.bits().to_le_bytes())?;
+
+        if format == WavFormat::Float32 {
+            writer.write_all(&0u16.to_le_bytes())?;
+            writer.write_all(b"fact")?;
+            writer.write_all(&4u32.to_le_bytes())?;
+            writer.write_all(&(self.frames() as u32).to_le_bytes())?;
+        }
+
+        writer.write_all(b"data")?;
+        writer.write_all(&data_len.to_le_bytes())?;
+
+        let mut data = Vec::with_capacity(data_len as usize);
+
+        for &sample in &self.samples {
+            let clipped = sample.max(-1.).min(1.);
+
+            match format {
+                WavFormat::Int16 => {
+                    data.extend_from_slice(&((clipped * 32767.).round() as i16).to_le_bytes())
+                }
+                WavFormat::Int24 => data
+                    .extend_from_slice(&((clipped * 8_388_607.).round() as i32).to_le_bytes()[..3]),
+                WavFormat::Float32 => data.extend_from_slice(&(sample as f32).to_le_bytes()),
+            }
+        }
+
+        writer.write_all(&data)
+    }
+
+    #[inline]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    #[inline]
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// The number of frames, where each frame has one sample per channel.
+    #[inline]
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+
+    #[inline]
+    pub fn max_frames(&self) -> Option<usize> {
+        self.max_frames
+    }
+
+    /// Remove every frame so that the file can be recorded over, possibly with a different sample
+    /// rate and number of channels.
+    ///
+    /// # Panics
+    ///
+    /// If `channels` or `sample_rate` is 0.
+    pub fn clear(&mut self, sample_rate: u32, channels: usize) {
+        assert!(sample_rate > 0, "Audio files must have a sample rate");
+        assert!(channels > 0, "Audio files must have at least one channel");
+
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        self.samples.clear();
+    }
+
+    /// Add a frame to the end of the file, returning `false` if the file is already as long as
+    /// it can be. Missing channels are silent and extra channels are ignored.
+    pub fn push_frame(&mut self, frame: &[Value]) -> bool {
+        if self.max_frames.map_or(false, |max| self.frames() >= max) {
+            return false;
+        }
+
+        self.samples.extend(
+            frame
+                .iter()
+                .copied()
+                .chain(iter::repeat(0.))
+                .take(self.channels),
+        );
+
+        true
+    }
+
+    /// Mix a frame into the frame at `index`, which must already exist.
+    pub fn overdub_frame(&mut self, index: usize, frame: &[Value]) {
+        let channels = self.channels;
+
+        for (sample, new) in self.samples[index * channels..(index + 1) * channels]
+            .iter_mut()
+            .zip(frame)
+        {
+            *sample += new;
+        }
+    }
+
+    /// The samples of one frame, or an empty slice if the file is shorter than that.
+    pub fn frame(&self, index: usize) -> &[Value] {
+        self.samples
+            .get(index * self.channels..(index + 1) * self.channels)
+            .unwrap_or(&[])
+    }
+
+    /// The index of the frame playing at `time`.
+    fn frame_at(&self, time: Duration) -> usize {
+        (time.as_secs_f64() * self.sample_rate as f64) as usize
+    }
+
+    /// The indices of the frames from `last` up to but not including `time`.
+    fn frames_between(&self, last: Duration, time: Duration) -> Range<usize> {
+        let end = self.frame_at(time).min(self.frames());
+
+        self.frame_at(last).min(end)..end
+    }
+
+    /// The time of the end of the file.
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames() as f64 / self.sample_rate as f64)
+    }
+
+    /// The samples at `time`, interpolated between the frames either side of it.
+    pub fn at(&self, time: Duration) -> FrameSamples {
+        let position = time.as_secs_f64() * self.sample_rate as f64;
+        let (index, fract) = (position as usize, position.fract());
+        let (this, next) = (self.frame(index), self.frame(index + 1));
+
+        if fract == 0. || next.is_empty() {
+            this.iter().copied().collect()
+        } else {
+            this.iter()
+                .zip(next)
+                .map(|(this, next)| this + (next - this) * fract)
+                .collect()
+        }
+    }
+}
+
+impl File<Value> for SharedFile<AudioFile> {
+    type SamplesAt = FrameSamples;
+    type SamplesBetween = SharedFrames;
+
+    fn duration(&self) -> Duration {
+        read_file(self).duration()
+    }
+
+    fn at(&self, time: Duration) -> Self::SamplesAt {
+        read_file(self).at(time)
+    }
+
+    fn between(&self, last: Duration, time: Duration) -> Self::SamplesBetween {
+        SharedFrames {
+            file: self.clone(),
+            frames: read_file(self).frames_between(last, time),
+        }
+    }
+}
+
+/// Every file that's been added to a rack. Each file is behind a lock that components take
+/// whenever they read or write it, including from the audio callback while the rack is playing,
+/// so a thread that holds a file's lock, for example with `audio_mut`, blocks every component
+/// using that file until the lock is released. Files aren't saved with the rack, see
+/// `Rack::from_str`.
+#[derive(Debug, Default)]
+pub struct Files {
+    audio: Vec<SharedFile<AudioFile>>,
+    midi: Vec<SharedFile<MidiFile>>,
+}
+
+/// The files of contexts that don't have any.
+pub(crate) static NO_FILES: Files = Files::new();
+
+impl Files {
+    pub const fn new() -> Self {
+        Files {
+            audio: Vec::new(),
+            midi: Vec::new(),
+        }
+    }
+
+    pub fn add_audio(&mut self, file: AudioFile) -> FileId<Value> {
+        self.audio.push(Arc::new(RwLock::new(file)));
+
+        FileId::new(self.audio.len() - 1)
+    }
+
+    /// Decode a WAV file into memory, as with `AudioFile::decode`.
+    pub fn load_wav<R>(&mut self, reader: R) -> Result<FileId<Value>, DecoderError>
+    where
+        R: Read + Seek + Send + 'static,
+    {
+        AudioFile::decode(reader).map(|file| self.add_audio(file))
+    }
+
+    /// Add an empty file to record into, as with `AudioFile::buffer`.
+    pub fn add_buffer(&mut self, max_frames: Option<usize>) -> FileId<Value> {
+        self.add_audio(AudioFile::buffer(max_frames))
+    }
+
+    pub fn audio(&self, id: FileId<Value>) -> Option<RwLockReadGuard<'_, AudioFile>> {
+        self.audio.get(id.index()).map(|file| read_file(file))
+    }
+
+    /// Since this only needs a shared reference, components can write files through
+    /// `ContextMeta::files` while the rack is playing.
+    pub fn audio_mut(&self, id: FileId<Value>) -> Option<RwLockWriteGuard<'_, AudioFile>> {
+        self.audio.get(id.index()).map(|file| write_file(file))
+    }
+
+    pub fn add_midi(&mut self, file: MidiFile) -> FileId<MidiValue> {
+        self.midi.push(Arc::new(RwLock::new(file)));
+
+        FileId::new(self.midi.len() - 1)
+    }
+
+    /// Add an empty file to record MIDI into, as with `MidiFile::buffer`.
+    pub fn add_midi_buffer(&mut self, max_events: Option<usize>) -> FileId<MidiValue> {
+        self.add_midi(MidiFile::buffer(max_events))
+    }
+
+    pub fn midi(&self, id: FileId<MidiValue>) -> Option<RwLockReadGuard<'_, MidiFile>> {
+        self.midi.get(id.index()).map(|file| read_file(file))
+    }
+
+    /// As with `audio_mut`, this only needs a shared reference.
+    pub fn midi_mut(&self, id: FileId<MidiValue>) -> Option<RwLockWriteGuard<'_, MidiFile>> {
+        self.midi.get(id.index()).map(|file| write_file(file))
+    }
+}
+
+impl<C, InputSpec, OutputSpec> Rack<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent,
+    OutputSpec: HasStorage<InternalWire>,
+{
+    /// The files that the components of this rack can read.
+    #[inline]
+    pub fn files(&self) -> &Files {
+        &self.files
+    }
+
+    #[inline]
+    pub fn files_mut(&mut self) -> &mut Files {
+        &mut self.files
+    }
+}
+
+// Reading a file that doesn't exist, for example because the `FileId` was loaded from a scene
+// saved with a different set of files, gives an empty file rather than panicking.
+
+impl<T> FileAccess<Value> for T
+where
+    T: ContextMeta + ?Sized,
+{
+    type ReadFile = SharedFile<AudioFile>;
+
+    fn read(&self, id: FileId<Value>) -> Self::ReadFile {
+        self.files()
+            .audio
+            .get(id.index())
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+impl<T> FileAccess<MidiValue> for T
+where
+    T: ContextMeta + ?Sized,
+{
+    type ReadFile = SharedFile<MidiFile>;
+
+    fn read(&self, id: FileId<MidiValue>) -> Self::ReadFile {
+        self.files()
+            .midi
+            .get(id.index())
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AudioFile, Files, WavFormat};
+    use crate::context::{ContextMeta, File, FileAccess, Transport};
+    use std::time::Duration;
+
+    struct TestCtx(Files);
+
+    impl ContextMeta for TestCtx {
+        fn sample_rate(&self) -> u32 {
+            4
+        }
+
+        fn transport(&self) -> Transport {
+            Transport::default()
+        }
+
+        fn files(&self) -> &Files {
+            &self.0
+        }
+    }
+
+    #[test]
+    fn read_audio() {
+        let mut files = Files::new();
+        let stereo = files.add_audio(AudioFile::new(4, 2, vec![0., 0.1, 0.2, 0.3, 0.4, 0.5, 0.6]));
+        let ctx = TestCtx(files);
+        let file = ctx.read(stereo);
+        let secs = Duration::from_secs_f64;
+
+        assert_eq!(file.duration(), secs(0.75));
+        assert_eq!(file.at(secs(0.25)).collect::<Vec<_>>(), [0.2, 0.3]);
+        assert!(file.at(secs(1.)).is_empty());
+        assert_eq!(
+            file.between(secs(0.), secs(2.))
+                .map(|frame| frame.collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+            [vec![0., 0.1], vec![0.2, 0.3], vec![0.4, 0.5]]
+        );
+
+        let mono = AudioFile::new(4, 1, vec![0., 1., 0.5]);
+        assert_eq!(mono.at(secs(0.125)).collect::<Vec<_>>(), [0.5]);
+        assert_eq!(mono.at(secs(0.375)).collect::<Vec<_>>(), [0.75]);
+
+        // Files that don't exist are empty
+        let missing = TestCtx(Files::new()).read(stereo);
+        assert_eq!(missing.duration(), secs(0.));
+    }
+
+    #[test]
+    fn record_into_buffer() {
+        let mut files = Files::new();
+        let buffer = files.add_buffer(Some(3));
+        let ctx = TestCtx(files);
+        let file = ctx.read(buffer);
+
+        {
+            let mut buffer = ctx.files().audio_mut(buffer).unwrap();
+
+            buffer.clear(4, 2);
+            assert!(buffer.push_frame(&[0.5]));
+            assert!(buffer.push_frame(&[0.25, 0.5, 1.]));
+            buffer.overdub_frame(0, &[0.25, 0.25]);
+            assert!(buffer.push_frame(&[]));
+            assert!(!buffer.push_frame(&[1., 1.]));
+        }
+
+        // Readers see what's been recorded straight away
+        assert_eq!(
+            file.between(Duration::from_secs(0), Duration::from_secs(1))
+                .map(|frame| frame.collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+            [vec![0.75, 0.25], vec![0.25, 0.5], vec![0., 0.]]
+        );
+    }
+
+    #[test]
+    fn read_poisoned_file() {
+        let mut files = Files::new();
+        let buffer = files.add_buffer(None);
+        let ctx = TestCtx(files);
+        let file = ctx.read(buffer);
+        let writer = file.clone();
+
+        // A panic while recording keeps what was recorded before it
+        let _ = std::thread::spawn(move || {
+            let mut buffer = writer.write().unwrap();
+
+            buffer.clear(4, 1);
+            buffer.push_frame(&[0.5]);
+            panic!("Stopped recording");
+        })
+        .join();
+
+        assert!(file.is_poisoned());
+        assert_eq!(file.at(Duration::from_secs(0)).collect::<Vec<_>>(), [0.5]);
+        assert_eq!(ctx.files().audio(buffer).unwrap().frames(), 1);
+    }
+
+    #[test]
+    fn write_wav() {
+        let file = AudioFile::new(4, 2, vec![0., 0.5, -0.5, 2.]);
+        let mut wav = vec![];
+
+        file.write_wav(&mut wav, WavFormat::Int16).unwrap();
+        let decoded = AudioFile::decode(std::io::Cursor::new(wav)).unwrap();
+        assert_eq!(decoded.sample_rate(), 4);
+        assert_eq!(decoded.channels(), 2);
+        assert_eq!(decoded.frame(1), [-0.5, 32767. / 32768.]);
+
+        let mut wav = vec![];
+        file.write_wav(&mut wav, WavFormat::Int24).unwrap();
+        assert_eq!(wav.len(), 44 + 4 * 3);
+        assert_eq!(&wav[44..47], &[0, 0, 0]);
+        assert_eq!(&wav[47..50], &[0, 0, 0x40]);
+
+        let mut wav = vec![];
+        file.write_wav(&mut wav, WavFormat::Float32).unwrap();
+        assert_eq!(&wav[20..22], &[3, 0]);
+        // Floats aren't clipped
+        assert_eq!(&wav[wav.len() - 4..], &2f32.to_le_bytes());
+    }
+}
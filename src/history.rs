@@ -0,0 +1,337 @@
+//! Undo and redo for edits to a rack. Every edit records the previous contents of each part of
+//! the rack that it changes, so undoing an edit only swaps those parts back instead of keeping a
+//! copy of the whole rack around.
+
+use crate::{
+    params::{HasStorage, Storage, StorageMut},
+    rack::{
+        ComponentId, FuncDef, FuncId, FuncParam, InternalWire, Meta, ParamLink, ParamValue,
+        ParamWire,
+    },
+    scene::ComponentScenes,
+    AnyComponent, AnyInputSpec, AnyOutputSpec, AnyParamSpec, Rack, RuntimeSpecifier, SpecId, Uid,
+    UidMap,
+};
+use std::{any::Any, collections::VecDeque, mem};
+
+/// The number of edits that can be undone, unless it's changed with `set_history_limit`.
+const DEFAULT_LIMIT: usize = 100;
+
+/// A change to one part of a rack, holding what that part contained before the change. Applying
+/// a change swaps what it holds with what's in the rack, so applying it twice does nothing.
+pub(crate) enum Change<C>
+where
+    C: AnyComponent,
+{
+    /// The statements, params and links of a function, where `None` is `Main`.
+    Def {
+        func: Option<FuncId>,
+        statements: Vec<ComponentId>,
+        params: Vec<FuncParam>,
+        links: Vec<ParamLink>,
+    },
+    Func {
+        id: FuncId,
+        def: Option<FuncDef<AnyInputSpec, AnyOutputSpec>>,
+    },
+    Meta {
+        id: ComponentId,
+        meta: Option<Meta<C>>,
+    },
+    /// The state of a component in one call to its function. `key` is the component's ID hashed
+    /// with the path of calls leading to it.
+    State { key: Uid, state: Option<C> },
+    Input {
+        id: ComponentId,
+        spec: SpecId,
+        wire: InternalWire,
+    },
+    Output {
+        func: Option<FuncId>,
+        spec: SpecId,
+        wire: InternalWire,
+    },
+    /// The natural value and wire of a `V` param, stored as a `(V, Option<Option<ParamWire<V>>>)`
+    /// where the wire is `None` if the param can't be wired.
+    Param {
+        id: ComponentId,
+        spec: SpecId,
+        saved: Box<dyn Any>,
+        swap: fn((&mut dyn Any, &mut dyn Any), &mut dyn Any),
+    },
+    /// The values of the function params of a call.
+    CallParams {
+        id: ComponentId,
+        params: Vec<ParamValue>,
+    },
+    /// The values that the scenes store for the params of a statement.
+    Scenes {
+        id: ComponentId,
+        values: ComponentScenes,
+    },
+}
+
+impl<C> Change<C>
+where
+    C: AnyComponent,
+{
+    pub(crate) fn param<V>(
+        (id, spec): (ComponentId, SpecId),
+        value: V,
+        wire: Option<Option<ParamWire<V>>>,
+    ) -> Self
+    where
+        V: 'static,
+    {
+        Change::Param {
+            id,
+            spec,
+            saved: Box::new((value, wire)),
+            swap: swap_param::<V>,
+        }
+    }
+}
+
+fn swap_param<V>((value, wire): (&mut dyn Any, &mut dyn Any), saved: &mut dyn Any)
+where
+    V: 'static,
+{
+    let (saved_value, saved_wire) = saved
+        .downcast_mut::<(V, Option<Option<ParamWire<V>>>)>()
+        .unwrap();
+
+    mem::swap(value.downcast_mut::<V>().unwrap(), saved_value);
+    if let (Some(wire), Some(saved_wire)) = (wire.downcast_mut(), saved_wire) {
+        mem::swap::<Option<ParamWire<V>>>(wire, saved_wire);
+    }
+}
+
+fn swap_entry<T>(map: &mut UidMap<T>, uid: Uid, saved: &mut Option<T>) {
+    *saved = match saved.take() {
+        Some(val) => map.insert(uid, val),
+        None => map.remove(uid),
+    };
+}
+
+fn swap_wire<S>(storage: &mut S::Storage, spec: S, saved: &mut InternalWire)
+where
+    S: HasStorage<InternalWire>,
+{
+    let current = *storage.get(&spec);
+
+    storage.set(&spec, *saved);
+    *saved = current;
+}
+
+/// The edits that can be undone and redone. Edits can be nested, for example when loading a
+/// function adds a series of components, in which case the outermost edit is undone as a whole.
+pub struct History<C>
+where
+    C: AnyComponent,
+{
+    undo: VecDeque<Vec<Change<C>>>,
+    redo: Vec<Vec<Change<C>>>,
+    /// The changes made so far by the edit in progress.
+    current: Vec<Change<C>>,
+    /// How many edits are in progress, including nested ones.
+    depth: usize,
+    limit: usize,
+}
+
+impl<C> Default for History<C>
+where
+    C: AnyComponent,
+{
+    fn default() -> Self {
+        History {
+            undo: Default::default(),
+            redo: Default::default(),
+            current: Default::default(),
+            depth: 0,
+            limit: DEFAULT_LIMIT,
+        }
+    }
+}
+
+impl<C> History<C>
+where
+    C: AnyComponent,
+{
+    #[inline]
+    pub(crate) fn begin(&mut self) {
+        self.depth += 1;
+    }
+
+    #[inline]
+    pub(crate) fn record(&mut self, change: Change<C>) {
+        debug_assert!(self.depth > 0, "Change recorded outside of an edit");
+        self.current.push(change);
+    }
+
+    /// Finish an edit. Once the outermost edit is finished it can be undone, and anything that
+    /// had been undone can no longer be redone.
+    pub(crate) fn end(&mut self) {
+        self.depth -= 1;
+
+        if self.depth == 0 && !self.current.is_empty() {
+            let edit = mem::take(&mut self.current);
+
+            self.push_undo(edit);
+            self.redo.clear();
+        }
+    }
+
+    fn push_undo(&mut self, edit: Vec<Change<C>>) {
+        self.undo.push_back(edit);
+
+        while self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+    }
+}
+
+impl<C> Extend<Change<C>> for History<C>
+where
+    C: AnyComponent,
+{
+    fn extend<T: IntoIterator<Item = Change<C>>>(&mut self, changes: T) {
+        for change in changes {
+            self.record(change);
+        }
+    }
+}
+
+impl<C, InputSpec, OutputSpec> Rack<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent,
+    OutputSpec: RuntimeSpecifier + HasStorage<InternalWire>,
+{
+    /// Undo the last edit, returning `false` if there's nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        let mut edit = match self.history.undo.pop_back() {
+            Some(edit) => edit,
+            None => return false,
+        };
+
+        for change in edit.iter_mut().rev() {
+            self.apply(change);
+        }
+        self.history.redo.push(edit);
+
+        true
+    }
+
+    /// Redo the last edit that was undone, returning `false` if there's nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        let mut edit = match self.history.redo.pop() {
+            Some(edit) => edit,
+            None => return false,
+        };
+
+        for change in &mut edit {
+            self.apply(change);
+        }
+        self.history.push_undo(edit);
+
+        true
+    }
+
+    #[inline]
+    pub fn can_undo(&self) -> bool {
+        !self.history.undo.is_empty()
+    }
+
+    #[inline]
+    pub fn can_redo(&self) -> bool {
+        !self.history.redo.is_empty()
+    }
+
+    /// Set the number of edits that can be undone, forgetting the oldest edits if there are
+    /// already more than that.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        let history = &mut self.history;
+
+        history.limit = limit;
+        while history.undo.len() > limit {
+            history.undo.pop_front();
+        }
+    }
+
+    /// Forget every edit, so that none of them can be undone or redone.
+    pub fn clear_history(&mut self) {
+        self.history.undo.clear();
+        self.history.redo.clear();
+    }
+
+    /// Undo the changes made so far by the edit in progress and finish the edit, for when an edit
+    /// fails partway through.
+    pub(crate) fn abort_edit(&mut self) {
+        let mut edit = mem::take(&mut self.history.current);
+
+        for change in edit.iter_mut().rev() {
+            self.apply(change);
+        }
+        self.history.depth -= 1;
+    }
+
+    fn apply(&mut self, change: &mut Change<C>) {
+        match change {
+            Change::Def {
+                func,
+                statements,
+                params,
+                links,
+            } => {
+                let (cur_statements, cur_params, cur_links) = match func {
+                    Some(id) => {
+                        let def = &mut self.funcs[id.0];
+                        (&mut def.statements, &mut def.params, &mut def.links)
+                    }
+                    None => (
+                        &mut self.main.statements,
+                        &mut self.main.params,
+                        &mut self.main.links,
+                    ),
+                };
+
+                mem::swap(cur_statements, statements);
+                mem::swap(cur_params, params);
+                mem::swap(cur_links, links);
+            }
+            Change::Func { id, def } => swap_entry(&mut self.funcs, id.0, def),
+            Change::Meta { id, meta } => swap_entry(&mut self.meta_storage, id.0, meta),
+            Change::State { key, state } => swap_entry(&mut self.state_storage, *key, state),
+            Change::Input { id, spec, wire } => {
+                let mut inputs = self.meta_storage[id.0].inputs_mut();
+                let current = *inputs.get(&AnyInputSpec(*spec));
+
+                inputs.set(&AnyInputSpec(*spec), *wire);
+                *wire = current;
+            }
+            Change::Output { func, spec, wire } => match func {
+                Some(id) => swap_wire(&mut self.funcs[id.0].out_wires, AnyOutputSpec(*spec), wire),
+                None => swap_wire(&mut self.main.out_wires, OutputSpec::from_id(*spec), wire),
+            },
+            Change::Param {
+                id,
+                spec,
+                saved,
+                swap,
+            } => swap(
+                self.meta_storage[id.0]
+                    .param_mut(&AnyParamSpec(*spec))
+                    .unwrap(),
+                &mut **saved,
+            ),
+            Change::CallParams { id, params } => {
+                if let Meta::Function {
+                    params: current, ..
+                } = &mut self.meta_storage[id.0]
+                {
+                    mem::swap(current, params);
+                }
+            }
+            Change::Scenes { id, values } => self.scenes.swap_component(*id, values),
+        }
+    }
+}
@@ -13,7 +13,7 @@
 use fixed::types::{I1F31, U1F31};
 use rand::{rngs::SmallRng, RngCore, SeedableRng};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt,
     hash::{BuildHasherDefault, Hasher},
     num::NonZeroU8,
@@ -23,13 +23,20 @@ use std::{
 pub use array_iterator;
 
 pub use derive_more;
+pub mod block;
 pub mod components;
 pub mod context;
 mod display;
+pub mod files;
+mod history;
+pub mod midi_file;
 pub mod octahack_components;
 pub mod output;
 pub mod params;
+pub mod parse;
+pub mod quantise;
 pub mod rack;
+pub mod scene;
 
 pub use components::{
     AnyComponent, AnyInputSpec, AnyOutputSpec, AnyParamSpec, Component, GetOutput,
@@ -37,11 +44,12 @@ pub use components::{
 };
 pub use context::{Context, GetInput, GetParam};
 pub use params::DisplayParam;
-pub use rack::{Rack, Wire, WireDst, WireSrc};
+pub use parse::ParseError;
+pub use rack::{Rack, Wire, WireDst, WireError, WireSrc};
 
 pub use nom_midi::MidiEventType as MidiValue;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Uid(u32);
 
 impl Uid {
@@ -59,6 +67,7 @@ impl fmt::Display for Uid {
 #[derive(Debug)]
 pub struct UidGen {
     cur: SmallRng,
+    reserved: HashSet<Uid, BuildHasherDefault<XOrHasher>>,
 }
 
 impl UidGen {
@@ -67,12 +76,24 @@ impl UidGen {
             // Since seed doesn't matter (we only ever create one `UidGen` and use it for
             // all `Uid`s) we hard-code it.
             cur: SmallRng::from_seed([0; 16]),
+            reserved: Default::default(),
         }
     }
 
     pub fn next(&mut self) -> Uid {
-        let id = self.cur.next_u32();
-        Uid(id)
+        loop {
+            let id = Uid(self.cur.next_u32());
+
+            if !self.reserved.contains(&id) {
+                return id;
+            }
+        }
+    }
+
+    /// Make sure that `uid` is never returned by `next`, for when we create an element with an
+    /// ID that didn't come from this generator (for example, when loading a saved rack).
+    pub fn reserve(&mut self, uid: Uid) {
+        self.reserved.insert(uid);
     }
 }
 
@@ -160,6 +181,22 @@ impl<T> UidMap<T> {
     pub fn insert(&mut self, uid: Uid, val: T) -> Option<T> {
         self.storage.insert(uid, val)
     }
+
+    pub fn get(&self, uid: Uid) -> Option<&T> {
+        self.storage.get(&uid)
+    }
+
+    pub fn get_mut(&mut self, uid: Uid) -> Option<&mut T> {
+        self.storage.get_mut(&uid)
+    }
+
+    pub fn remove(&mut self, uid: Uid) -> Option<T> {
+        self.storage.remove(&uid)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Uid, &mut T)> + '_ {
+        self.storage.iter_mut().map(|(s, v)| (*s, v))
+    }
 }
 
 impl<T> Default for UidMap<T> {
@@ -222,6 +259,9 @@ pub enum ValueKind {
     Continuous,
     // The inner U8 is the maximum
     Discrete(u8),
+    Midi,
+    // A file selected by a param. These can't be wired to or from anything.
+    File,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -236,6 +276,8 @@ impl fmt::Display for ValueType {
             ValueKind::Binary => write!(f, "gate")?,
             ValueKind::Continuous => write!(f, "analogue")?,
             ValueKind::Discrete(max) => write!(f, "discrete(0..{})", max)?,
+            ValueKind::Midi => return write!(f, "midi"),
+            ValueKind::File => return write!(f, "file"),
         }
         match self.channels.map(NonZeroU8::get) {
             Some(1) => {}
@@ -270,6 +312,38 @@ impl ValueType {
             channels: Some(unsafe { NonZeroU8::new_unchecked(2) }),
         }
     }
+
+    pub const fn midi() -> Self {
+        ValueType {
+            kind: ValueKind::Midi,
+            channels: None,
+        }
+    }
+
+    pub const fn file() -> Self {
+        ValueType {
+            kind: ValueKind::File,
+            channels: None,
+        }
+    }
+
+    /// Whether an output of this type can be wired into an input of type `dst`. MIDI can only
+    /// be wired to MIDI, files can't be wired at all, and an unspecified channel count is
+    /// compatible with any other.
+    pub fn can_wire_to(self, dst: ValueType) -> bool {
+        let kind = match (self.kind, dst.kind) {
+            (ValueKind::File, _) | (_, ValueKind::File) => false,
+            (ValueKind::Midi, ValueKind::Midi) => true,
+            (ValueKind::Midi, _) | (_, ValueKind::Midi) => false,
+            _ => true,
+        };
+        let channels = match (self.channels, dst.channels) {
+            (Some(src), Some(dst)) => src == dst,
+            _ => true,
+        };
+
+        kind && channels
+    }
 }
 
 pub type Value = f64;
@@ -0,0 +1,464 @@
+//! Standard MIDI Files, for reading with `FileAccess<MidiValue>`. Unlike audio files, MIDI files
+//! are timed in beats rather than seconds so that they follow the tempo of the transport, so
+//! positions passed to `File::at` and `File::between` are in beats.
+//! MIDI files can be recorded into the same way as audio files, and written back out as a
+//! Standard MIDI File with `MidiFile::encode`.
+
+use crate::{
+    context::File,
+    files::{read_file, SharedFile},
+    MidiValue, Value,
+};
+use nom_midi::{Division, EventType};
+use std::{cmp::Ordering, iter, ops::Range};
+
+/// A MIDI file with every track merged into one, keeping only the channel events.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MidiFile {
+    /// Events along with the beat they happen at, sorted by beat.
+    events: Vec<(Value, MidiValue)>,
+    /// The beat that the file ends at, which is never before the last event.
+    end: Value,
+    /// The most events that can be recorded into this file, or `None` if it can grow until we
+    /// run out of memory.
+    max_events: Option<usize>,
+}
+
+impl MidiFile {
+    /// Parse a Standard MIDI File, returning `None` if it's malformed or if it's timed in SMPTE
+    /// frames instead of beats.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let (_, file) = nom_midi::parser::parse_smf(bytes).ok()?;
+        let ticks_per_beat = match file.header.division {
+            Division::Metrical(ticks) if ticks > 0 => Value::from(ticks),
+            _ => return None,
+        };
+
+        let mut events = vec![];
+        let mut end = 0u64;
+        for track in &file.tracks {
+            let mut ticks = 0u64;
+
+            for event in &track.events {
+                ticks += u64::from(event.delta_time);
+
+                if let EventType::Midi(midi) = &event.event {
+                    events.push((ticks as Value / ticks_per_beat, midi.event));
+                }
+            }
+
+            end = end.max(ticks);
+        }
+        // Stable, so that events at the same time stay in the order they were in the file
+        events.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+
+        Some(MidiFile {
+            events,
+            end: end as Value / ticks_per_beat,
+            max_events: None,
+        })
+    }
+
+    /// An empty file to record into, which can hold at most `max_events` events.
+    pub fn buffer(max_events: Option<usize>) -> Self {
+        MidiFile {
+            max_events,
+            ..Default::default()
+        }
+    }
+
+    /// Write this file as a format 0 Standard MIDI File with `ticks_per_beat` ticks per beat,
+    /// with every event on the first channel. Events are rounded to the nearest tick.
+    ///
+    /// # Panics
+    ///
+    /// If `ticks_per_beat` is 0 or doesn't fit in 15 bits.
+    pub fn encode(&self, ticks_per_beat: u16) -> Vec<u8> {
+        assert!(
+            ticks_per_beat > 0 && ticks_per_beat < 0x8000,
+            "Ticks per beat must be between 1 and 32767"
+        );
+
+        let ticks = |beat: Value| (beat.max(0.) * Value::from(ticks_per_beat)).round() as u64;
+        let mut track = vec![];
+        let mut last = 0;
+
+        for &(beat, event) in &self.events {
+            let now = ticks(beat);
+
+            write_var_len(&mut track, now - last);
+            write_event(&mut track, event);
+            last = now;
+        }
+
+        write_var_len(&mut track, ticks(self.end).max(last) - last);
+        track.extend_from_slice(&[0xFF, 0x2F, 0]);
+
+        let mut out = b"MThd".to_vec();
+        out.extend_from_slice(&6u32.to_be_bytes());
+        out.extend_from_slice(&[0, 0, 0, 1]);
+        out.extend_from_slice(&ticks_per_beat.to_be_bytes());
+        out.extend_from_slice(b"MTrk");
+        out.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        out.extend(track);
+
+        out
+    }
+
+    /// The beat that the file ends at, which for a recording is when recording stopped.
+    pub fn beats(&self) -> Value {
+        self.end
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    #[inline]
+    pub fn max_events(&self) -> Option<usize> {
+        self.max_events
+    }
+
+    /// Remove every event so that the file can be recorded over.
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.end = 0.;
+    }
+
+    /// Add an event at `beat`, after any events already at that beat, returning `false` if the
+    /// file already has as many events as it can hold. The end of the file is moved to `beat` if
+    /// it's before it.
+    pub fn insert(&mut self, beat: Value, event: MidiValue) -> bool {
+        if self
+            .max_events
+            .map_or(false, |max| self.events.len() >= max)
+        {
+            return false;
+        }
+
+        let beat = beat.max(0.);
+        let index = self
+            .events
+            .iter()
+            .rposition(|&(other, _)| other <= beat)
+            .map_or(0, |i| i + 1);
+
+        self.events.insert(index, (beat, event));
+        self.end = self.end.max(beat);
+
+        true
+    }
+
+    /// Move the end of the file, for example to the length of a loop when recording stops. It
+    /// can't be moved before the last event.
+    pub fn set_end(&mut self, beat: Value) {
+        self.end = self.events.last().map_or(0., |&(last, _)| last).max(beat);
+    }
+
+    /// The events at `beat`.
+    pub fn at(&self, beat: Value) -> impl ExactSizeIterator<Item = MidiValue> + '_ {
+        self.events[self.events_at(beat)]
+            .iter()
+            .map(|&(_, event)| event)
+    }
+
+    /// The events from `start` up to but not including `end`, grouped by the beat they happen
+    /// at.
+    pub fn between(
+        &self,
+        start: Value,
+        end: Value,
+    ) -> impl Iterator<Item = impl ExactSizeIterator<Item = MidiValue> + '_> + '_ {
+        let mut events = self.range(start, end);
+
+        iter::from_fn(move || {
+            let group = events.start..events.start + self.group_len(events.clone());
+
+            events.start = group.end;
+
+            if group.start == group.end {
+                None
+            } else {
+                Some(self.events[group].iter().map(|&(_, event)| event))
+            }
+        })
+    }
+
+    /// The indices of the events from `start` up to but not including `end`, in beats.
+    fn range(&self, start: Value, end: Value) -> Range<usize> {
+        let from = self.index(start);
+
+        from..self.index(end).max(from)
+    }
+
+    /// The index of the first event at or after `beat`.
+    fn index(&self, beat: Value) -> usize {
+        // This never finds an exact match, so the error is always where `beat` would go
+        self.events
+            .binary_search_by(|&(at, _)| {
+                if at < beat {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            })
+            .unwrap_or_else(|index| index)
+    }
+
+    /// The indices of the events at `beat`.
+    fn events_at(&self, beat: Value) -> Range<usize> {
+        let start = self.index(beat);
+        let len = match self.events.get(start) {
+            Some(&(at, _)) if at == beat => self.group_len(start..self.events.len()),
+            _ => 0,
+        };
+
+        start..start + len
+    }
+
+    /// The number of events at the start of `events` that happen on the same beat, which is 0 if
+    /// `events` is empty or no longer in the file.
+    fn group_len(&self, events: Range<usize>) -> usize {
+        let events = self
+            .events
+            .get(events.start..events.end.min(self.events.len()))
+            .unwrap_or(&[]);
+
+        match events.first() {
+            Some(&(beat, _)) => events.iter().take_while(|&&(at, _)| at == beat).count(),
+            None => 0,
+        }
+    }
+
+    /// The number of different beats that `events` happen at.
+    fn group_count(&self, events: Range<usize>) -> usize {
+        let events = &self.events[events];
+        let changes = events
+            .windows(2)
+            .filter(|pair| pair[0].0 != pair[1].0)
+            .count();
+
+        if events.is_empty() {
+            0
+        } else {
+            changes + 1
+        }
+    }
+}
+
+/// Write a variable-length quantity, as used for delta times.
+fn write_var_len(out: &mut Vec<u8>, val: u64) {
+    let mut bytes = vec![(val & 0x7F) as u8];
+    let mut val = val >> 7;
+
+    while val > 0 {
+        bytes.push((val & 0x7F) as u8 | 0x80);
+        val >>= 7;
+    }
+
+    out.extend(bytes.into_iter().rev());
+}
+
+fn write_event(out: &mut Vec<u8>, event: MidiValue) {
+    match event {
+        MidiValue::NoteOff(note, velocity) => out.extend_from_slice(&[0x80, note.into(), velocity]),
+        MidiValue::NoteOn(note, velocity) => out.extend_from_slice(&[0x90, note.into(), velocity]),
+        MidiValue::PolyphonicPressure(note, amount) => {
+            out.extend_from_slice(&[0xA0, note.into(), amount])
+        }
+        MidiValue::Controller(controller, val) => out.extend_from_slice(&[0xB0, controller, val]),
+        MidiValue::ProgramChange(program) => out.extend_from_slice(&[0xC0, program]),
+        MidiValue::ChannelPressure(amount) => out.extend_from_slice(&[0xD0, amount]),
+        MidiValue::PitchBend(lsb, msb) => out.extend_from_slice(&[0xE0, lsb, msb]),
+    }
+}
+
+/// The events of a shared MIDI file that happen on one beat, as read by `File::at`. As with
+/// `SharedFrames`, each event is read as it's reached rather than holding the lock on the file,
+/// so if the file is cleared in the meantime then the rest of the events are missing.
+#[derive(Debug, Clone)]
+pub struct SharedEvents {
+    file: SharedFile<MidiFile>,
+    events: Range<usize>,
+}
+
+impl Iterator for SharedEvents {
+    type Item = MidiValue;
+
+    fn next(&mut self) -> Option<MidiValue> {
+        let index = self.events.next()?;
+
+        read_file(&self.file)
+            .events
+            .get(index)
+            .map(|&(_, event)| event)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.events.size_hint()
+    }
+}
+
+impl ExactSizeIterator for SharedEvents {}
+
+/// The events of a shared MIDI file from `File::between`, grouped by the beat they happen at.
+#[derive(Debug, Clone)]
+pub struct SharedEventGroups {
+    file: SharedFile<MidiFile>,
+    events: Range<usize>,
+    groups: usize,
+}
+
+impl Iterator for SharedEventGroups {
+    type Item = SharedEvents;
+
+    fn next(&mut self) -> Option<SharedEvents> {
+        if self.groups == 0 {
+            return None;
+        }
+
+        let len = read_file(&self.file).group_len(self.events.clone());
+        let events = self.events.start..self.events.start + len;
+
+        self.groups -= 1;
+        self.events.start = events.end;
+
+        Some(SharedEvents {
+            file: self.file.clone(),
+            events,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.groups, Some(self.groups))
+    }
+}
+
+impl ExactSizeIterator for SharedEventGroups {}
+
+impl File<MidiValue> for SharedFile<MidiFile> {
+    type SamplesAt = SharedEvents;
+    type SamplesBetween = SharedEventGroups;
+
+    fn duration(&self) -> Value {
+        read_file(self).beats()
+    }
+
+    fn at(&self, beat: Value) -> Self::SamplesAt {
+        SharedEvents {
+            file: self.clone(),
+            events: read_file(self).events_at(beat),
+        }
+    }
+
+    fn between(&self, last: Value, beat: Value) -> Self::SamplesBetween {
+        let file = read_file(self);
+        let events = file.range(last, beat);
+
+        SharedEventGroups {
+            file: self.clone(),
+            groups: file.group_count(events.clone()),
+            events,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MidiFile;
+    use crate::{context::File, MidiValue};
+    use std::sync::{Arc, RwLock};
+
+    /// A format 1 file with two tracks and 4 ticks per beat.
+    const FILE: &[u8] = &[
+        b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 1, 0, 2, 0, 4, //
+        b'M', b'T', b'r', b'k', 0, 0, 0, 12, //
+        0, 0x90, 60, 100, // Note on at beat 0
+        4, 0x80, 60, 0, // Note off at beat 1
+        0, 0xFF, 0x2F, 0, // End of track
+        b'M', b'T', b'r', b'k', 0, 0, 0, 12, //
+        2, 0x91, 64, 90, // Note on at beat 0.5, on another channel
+        2, 0xB1, 7, 127, // Controller at beat 1
+        0, 0xFF, 0x2F, 0, // End of track
+    ];
+
+    #[test]
+    fn parse_and_read() {
+        let file = MidiFile::parse(FILE).unwrap();
+        let between = |start, end| {
+            file.between(start, end)
+                .map(|group| group.collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(file.beats(), 1.);
+        assert_eq!(
+            between(0., 1.),
+            [
+                vec![MidiValue::NoteOn(60.into(), 100)],
+                vec![MidiValue::NoteOn(64.into(), 90)],
+            ]
+        );
+        assert_eq!(
+            between(1., 2.),
+            [vec![
+                MidiValue::NoteOff(60.into(), 0),
+                MidiValue::Controller(7, 127),
+            ]]
+        );
+        assert_eq!(
+            file.at(0.5).collect::<Vec<_>>(),
+            [MidiValue::NoteOn(64.into(), 90)]
+        );
+        assert!(MidiFile::parse(&FILE[..20]).is_none());
+
+        // Reading a shared file gives the same events
+        let shared = Arc::new(RwLock::new(file.clone()));
+        let groups = shared.between(0., 2.);
+
+        assert_eq!(shared.duration(), 1.);
+        assert_eq!(groups.len(), 3);
+        assert_eq!(
+            groups
+                .map(|group| group.collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+            [between(0., 1.), between(1., 2.)].concat()
+        );
+        assert_eq!(shared.at(1.).len(), 2);
+        assert!(shared.at(0.25).is_empty());
+    }
+
+    #[test]
+    fn record_and_encode() {
+        let mut file = MidiFile::buffer(Some(3));
+
+        assert!(file.insert(1., MidiValue::NoteOff(60.into(), 0)));
+        assert!(file.insert(0.25, MidiValue::NoteOn(60.into(), 100)));
+        assert!(file.insert(1., MidiValue::PitchBend(0, 0x40)));
+        assert!(!file.insert(2., MidiValue::NoteOn(62.into(), 100)));
+        file.set_end(2.);
+        assert_eq!(file.beats(), 2.);
+
+        let encoded = file.encode(4);
+        assert_eq!(
+            &encoded[14..],
+            &[
+                b'M', b'T', b'r', b'k', 0, 0, 0, 16, //
+                1, 0x90, 60, 100, //
+                3, 0x80, 60, 0, //
+                0, 0xE0, 0, 0x40, //
+                4, 0xFF, 0x2F, 0, //
+            ][..]
+        );
+
+        let parsed = MidiFile::parse(&encoded).unwrap();
+        assert_eq!(parsed.events, file.events);
+        assert_eq!(parsed.beats(), 2.);
+    }
+}
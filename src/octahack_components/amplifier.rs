@@ -1,4 +1,7 @@
-use crate::{Component, Context, DisplayParam, GetOutput, UiElement, Value};
+use crate::{
+    block::BlockOutputs, context::BlockContext, Component, Context, DisplayParam, GetOutput,
+    UiElement, Value,
+};
 use az::Az;
 use std::fmt;
 
@@ -44,7 +47,7 @@ impl Default for params::Params {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default)]
 pub struct Amplifier;
 
 impl UiElement for Amplifier {
@@ -62,6 +65,26 @@ impl Component for Amplifier {
     {
         *self
     }
+
+    fn process_block<Ctx>(&self, ctx: &Ctx, outputs: &mut BlockOutputs) -> Option<Self>
+    where
+        Ctx: BlockContext<Self>,
+    {
+        for i in 0..ctx.frames() {
+            let frame = ctx.frame(i);
+
+            match frame.input::<input::Input>() {
+                Some(inputs) => {
+                    let amount = frame.param::<params::Amount>();
+
+                    outputs.push_values(output::Specifier::Output, inputs.map(|val| val * amount));
+                }
+                None => outputs.push_values(output::Specifier::Output, None),
+            }
+        }
+
+        Some(*self)
+    }
 }
 
 impl GetOutput<output::Output> for Amplifier {
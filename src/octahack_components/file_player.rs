@@ -1,15 +1,132 @@
-use crate::{context::ContextMetaExt, Component, Context, GetOutput, Value};
-use az::Az;
-use std::time::Duration;
+use crate::{
+    context::{ContextMetaExt, DisplayFile, File, FileId},
+    files::FrameSamples,
+    Component, Context, DisplayParam, GetOutput, UiElement, Value,
+};
+use std::{fmt, time::Duration};
 
 crate::specs! {
-    mod params {
+    pub mod params {
         File: Option<crate::context::FileId<crate::Value>>,
-        Speed: crate::Value
+        Speed: crate::Value,
+        Start: crate::Value,
+        End: crate::Value,
+        Loop: crate::Value
     }
 
-    mod output {
-        Output: crate::Value
+    pub mod input {
+        Gate: crate::Value
+    }
+
+    // One channel for each channel of the file
+    pub mod output {
+        Output: crate::Value = crate::ValueType::continuous()
+    }
+}
+
+impl DisplayParam for params::File {
+    type Display = impl fmt::Display;
+
+    fn display(val: Option<FileId<Value>>) -> Self::Display {
+        DisplayFile(val)
+    }
+}
+
+impl DisplayParam for params::Speed {
+    type Display = impl fmt::Display;
+
+    fn display(val: Value) -> Self::Display {
+        struct SpeedDisplay(Value);
+
+        impl fmt::Display for SpeedDisplay {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}x", (self.0 * 100.).round() / 100.)
+            }
+        }
+
+        SpeedDisplay(val)
+    }
+}
+
+/// The natural value of the `Start` and `End` params, as a fraction of the length of the file.
+fn fraction(value: Value) -> Value {
+    value.max(0.).min(1.)
+}
+
+struct FractionDisplay(Value);
+
+impl fmt::Display for FractionDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}%", (fraction(self.0) * 100.).round())
+    }
+}
+
+impl DisplayParam for params::Start {
+    type Display = impl fmt::Display;
+
+    fn display(val: Value) -> Self::Display {
+        FractionDisplay(val)
+    }
+}
+
+impl DisplayParam for params::End {
+    type Display = impl fmt::Display;
+
+    fn display(val: Value) -> Self::Display {
+        FractionDisplay(val)
+    }
+}
+
+/// What happens when playback reaches the end of the region between `Start` and `End`, or the
+/// start of it when playing in reverse.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LoopMode {
+    /// Stop until the next trigger.
+    Off,
+    /// Jump back to the other end of the region.
+    Loop,
+    /// Turn around and play back through the region in the other direction.
+    PingPong,
+}
+
+impl LoopMode {
+    /// The natural value of the `Loop` param that selects this mode.
+    pub fn value(self) -> Value {
+        match self {
+            LoopMode::Off => -1.,
+            LoopMode::Loop => 0.,
+            LoopMode::PingPong => 1.,
+        }
+    }
+
+    fn from_value(value: Value) -> Self {
+        if value < -0.5 {
+            LoopMode::Off
+        } else if value < 0.5 {
+            LoopMode::Loop
+        } else {
+            LoopMode::PingPong
+        }
+    }
+}
+
+impl DisplayParam for params::Loop {
+    type Display = impl fmt::Display;
+
+    fn display(val: Value) -> Self::Display {
+        struct LoopDisplay(LoopMode);
+
+        impl fmt::Display for LoopDisplay {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self.0 {
+                    LoopMode::Off => write!(f, "Off"),
+                    LoopMode::Loop => write!(f, "Loop"),
+                    LoopMode::PingPong => write!(f, "Ping-pong"),
+                }
+            }
+        }
+
+        LoopDisplay(LoopMode::from_value(val))
     }
 }
 
@@ -18,17 +135,78 @@ impl Default for params::Params {
         Self {
             File: None,
             Speed: 1.,
+            Start: 0.,
+            End: 1.,
+            Loop: LoopMode::Off.value(),
         }
     }
 }
 
-#[derive(Copy, Clone)]
+/// The part of the file that's played, in seconds.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Region {
+    start: Value,
+    end: Value,
+    mode: LoopMode,
+}
+
+/// Plays an audio file, starting from `Start` whenever the `Gate` input opens, or from `End`
+/// if `Speed` is negative. `Speed` is the number of seconds of the file played per second, and
+/// samples between the frames of the file are interpolated. If `Gate` isn't wired then the file
+/// plays as soon as it's chosen.
+#[derive(Debug, Copy, Clone, Default)]
 pub struct FilePlayer {
-    seek_pos: Duration,
+    /// How far through the file playback is, in seconds, or `None` if it's stopped.
+    position: Option<Value>,
+    /// Whether playback is going in the opposite direction to `Speed`, after ping-ponging.
+    reversed: bool,
+    /// Whether the gate was open last tick, so that holding it open only triggers playback once.
+    gate: bool,
+}
+
+impl UiElement for FilePlayer {
+    const NAME: &'static str = "FilePlayer";
+}
+
+impl FilePlayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move playback by `step` seconds, which is negative when playing in reverse, and handle
+    /// running off either end of the region.
+    fn advance(&mut self, region: Region, step: Value) {
+        let position = match self.position {
+            Some(position) => position + if self.reversed { -step } else { step },
+            None => return,
+        };
+        let length = region.end - region.start;
+
+        self.position = if position >= region.start && position <= region.end {
+            Some(position)
+        } else if length <= 0. {
+            None
+        } else {
+            match region.mode {
+                LoopMode::Off => None,
+                LoopMode::Loop => Some(region.start + (position - region.start).rem_euclid(length)),
+                LoopMode::PingPong => {
+                    let edge = if position > region.end {
+                        region.end
+                    } else {
+                        region.start
+                    };
+
+                    self.reversed = !self.reversed;
+                    Some((2. * edge - position).max(region.start).min(region.end))
+                }
+            }
+        };
+    }
 }
 
 impl Component for FilePlayer {
-    type InputSpecifier = !;
+    type InputSpecifier = input::Specifier;
     type OutputSpecifier = output::Specifier;
     type ParamSpecifier = params::Specifier;
 
@@ -36,9 +214,36 @@ impl Component for FilePlayer {
     where
         Ctx: Context<Self>,
     {
-        Self {
-            seek_pos: self.seek_pos + ctx.sample_duration(),
+        let file = match ctx.param::<params::File>() {
+            Some(file) => ctx.read(file),
+            None => return Self::default(),
+        };
+        let duration = file.duration().as_secs_f64();
+        let (start, end) = (
+            fraction(ctx.param::<params::Start>()) * duration,
+            fraction(ctx.param::<params::End>()) * duration,
+        );
+        let region = Region {
+            start: start.min(end),
+            end: start.max(end),
+            mode: LoopMode::from_value(ctx.param::<params::Loop>()),
+        };
+        let speed = ctx.param::<params::Speed>();
+        let gate = ctx
+            .input::<input::Gate>()
+            .map_or(true, |mut gate| gate.any(|val| val >= 0.5));
+        let mut out = *self;
+
+        out.gate = gate;
+
+        if gate && !self.gate {
+            out.position = Some(if speed < 0. { region.end } else { region.start });
+            out.reversed = false;
+        } else {
+            out.advance(region, speed * ctx.sample_duration().as_secs_f64());
         }
+
+        out
     }
 }
 
@@ -49,13 +254,52 @@ impl GetOutput<output::Output> for FilePlayer {
     where
         Ctx: Context<Self>,
     {
-        use crate::context::File;
-
-        if let Some(file) = ctx.param::<params::File>() {
-            ctx.read(file).at(self.seek_pos).collect::<Vec<_>>()
-        } else {
-            std::iter::empty().collect::<Vec<_>>()
+        match (ctx.param::<params::File>(), self.position) {
+            (Some(file), Some(position)) => ctx
+                .read(file)
+                .at(Duration::from_secs_f64(position))
+                .collect::<FrameSamples>(),
+            _ => FrameSamples::default(),
         }
-        .into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FilePlayer, LoopMode, Region};
+
+    #[test]
+    fn loop_modes() {
+        let region = |mode| Region {
+            start: 1.,
+            end: 2.,
+            mode,
+        };
+        let play = |mode, steps: &[f64]| {
+            let mut player = FilePlayer {
+                position: Some(1.5),
+                ..FilePlayer::new()
+            };
+
+            steps
+                .iter()
+                .map(|&step| {
+                    player.advance(region(mode), step);
+                    player.position
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(play(LoopMode::Off, &[0.25, 0.5]), [Some(1.75), None]);
+        assert_eq!(play(LoopMode::Off, &[-0.5, -0.25]), [Some(1.), None]);
+        assert_eq!(
+            play(LoopMode::Loop, &[0.75, -0.5, -0.5]),
+            [Some(1.25), Some(1.75), Some(1.25)]
+        );
+        // Ping-ponging turns around, so later steps go the other way
+        assert_eq!(
+            play(LoopMode::PingPong, &[0.75, 0.5, 0.5]),
+            [Some(1.75), Some(1.25), Some(1.25)]
+        );
     }
 }
@@ -0,0 +1,335 @@
+//! Golden-audio tests, which render small racks built around components and compare each output
+//! with a reference render checked in under `tests/golden`, so that changes to the sound of a
+//! component don't go unnoticed. If a change is intended, run the tests with `OCTAHACK_BLESS=1`
+//! to regenerate the references and check the new ones in.
+
+use super::{
+    amplifier::{self, Amplifier},
+    midi_expander::{self, MidiExpander},
+    midi_player::{self, MidiPlayer},
+    midi_splitter::{self, MidiSplitter},
+    recorder::{self, Recorder},
+    synth::{self, Synth},
+    OctahackComponent,
+};
+use crate::{
+    components::EnumerateValues, files::AudioFile, midi_file::MidiFile, output::render::Renderer,
+    params::HasStorage, rack::InternalWire, MidiValue, Rack, Value, WireDst, WireSrc,
+};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+crate::specs! {
+    mod mono {
+        Audio: crate::Value
+    }
+}
+
+crate::specs! {
+    mod waves {
+        Sine: crate::Value,
+        Saw: crate::Value,
+        Square: crate::Value
+    }
+}
+
+crate::specs! {
+    mod voices {
+        Sine: crate::Value = crate::ValueType::stereo(),
+        Gate: crate::Value = crate::ValueType::stereo()
+    }
+}
+
+crate::specs! {
+    mod split {
+        Gate: crate::Value,
+        Note: crate::Value,
+        Vel: crate::Value,
+        Bend: crate::Value,
+        Cc1: crate::Value
+    }
+}
+
+/// Low enough that the references are short, but high enough that a 440Hz oscillator isn't
+/// aliased.
+const SAMPLE_RATE: u32 = 4410;
+const SAMPLES: u64 = 64;
+/// The most that any sample can differ from the reference by, which allows for differences in
+/// floating-point maths between platforms.
+const TOLERANCE: Value = 1e-9;
+
+fn reference_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.txt", name))
+}
+
+/// Whether the references should be regenerated instead of checked.
+fn blessing() -> bool {
+    env::var_os("OCTAHACK_BLESS").map_or(false, |val| !val.is_empty() && val != "0")
+}
+
+/// Write `file` as the reference for `name`, with one frame per line.
+fn bless(name: &str, file: &AudioFile) {
+    let text = (0..file.frames())
+        .map(|i| {
+            let frame = file
+                .frame(i)
+                .iter()
+                .map(|sample| format!("{:?}", sample))
+                .collect::<Vec<_>>();
+
+            format!("{}\n", frame.join(" "))
+        })
+        .collect::<String>();
+    let path = reference_path(name);
+
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, text).unwrap_or_else(|e| panic!("Couldn't write {}: {}", path.display(), e));
+}
+
+/// Compare `file` with the reference for `name`, or regenerate the reference if
+/// `OCTAHACK_BLESS` is set.
+fn check(name: &str, file: &AudioFile) {
+    if blessing() {
+        bless(name, file);
+        return;
+    }
+
+    let path = reference_path(name);
+    let text = fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "Couldn't read {}, run with `OCTAHACK_BLESS=1` to create it: {}",
+            path.display(),
+            e
+        )
+    });
+    let expected = text
+        .lines()
+        .map(|line| {
+            line.split_whitespace()
+                .map(|sample| sample.parse::<Value>().unwrap())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        file.frames(),
+        expected.len(),
+        "`{}` rendered a different number of frames to the reference",
+        name
+    );
+
+    for (i, expected) in expected.iter().enumerate() {
+        let actual = file.frame(i);
+
+        assert!(
+            actual.len() == expected.len()
+                && actual
+                    .iter()
+                    .zip(expected)
+                    .all(|(actual, expected)| (actual - expected).abs() <= TOLERANCE),
+            "`{}` differs from the reference at frame {}: expected {:?}, got {:?}. If this is \
+             intended, run with `OCTAHACK_BLESS=1` to regenerate the reference.",
+            name,
+            i,
+            expected,
+            actual
+        );
+    }
+}
+
+/// Render `rack` and check each of its continuous outputs against the reference named after
+/// `name` and the output, returning the rack so that its files can be checked too.
+fn check_rack<InputSpec, OutputSpec>(
+    name: &str,
+    rack: Rack<OctahackComponent, InputSpec, OutputSpec>,
+) -> Rack<OctahackComponent, InputSpec, OutputSpec>
+where
+    InputSpec: EnumerateValues,
+    OutputSpec: EnumerateValues + HasStorage<InternalWire>,
+{
+    let mut renderer = Renderer::new(SAMPLE_RATE, rack);
+
+    for (spec, file) in renderer.render(SAMPLES) {
+        check(&format!("{}_{}", name, spec).to_lowercase(), &file);
+    }
+
+    renderer.into_rack()
+}
+
+/// The beat halfway through `sample` at the default tempo, so that rounding can't move an event
+/// at that beat to another sample.
+fn beat(sample: u32) -> Value {
+    (Value::from(sample) + 0.5) * 120. / 60. / Value::from(SAMPLE_RATE)
+}
+
+/// Two overlapping notes with a controller and a pitch bend while they're held, which
+/// `MidiPlayer` plays the sample after each event.
+fn midi_file() -> MidiFile {
+    let mut file = MidiFile::buffer(None);
+
+    for &(sample, event) in &[
+        (4, MidiValue::NoteOn(57.into(), 100)),
+        (10, MidiValue::Controller(1, 64)),
+        (20, MidiValue::NoteOn(64.into(), 127)),
+        (30, MidiValue::PitchBend(0, 0x60)),
+        (40, MidiValue::NoteOff(64.into(), 0)),
+        (50, MidiValue::NoteOff(57.into(), 0)),
+    ] {
+        file.insert(beat(sample), event);
+    }
+
+    file
+}
+
+#[test]
+fn synth() {
+    use synth::output::Specifier::*;
+
+    let mut rack = Rack::<OctahackComponent, mono::Specifier, waves::Specifier>::new();
+    let mut main = rack.main_mut();
+    let synth = main.push_component(Synth::new());
+
+    for &(output, wave) in &[
+        (Sine, waves::Specifier::Sine),
+        (Saw, waves::Specifier::Saw),
+        (Square, waves::Specifier::Square),
+    ] {
+        main.wire(
+            WireSrc::component_output(synth, output),
+            WireDst::func_output(wave),
+        )
+        .unwrap();
+    }
+
+    check_rack("synth", rack);
+}
+
+#[test]
+fn amplifier() {
+    let mut rack = Rack::<OctahackComponent, mono::Specifier, mono::Specifier>::new();
+    let mut main = rack.main_mut();
+    let synth = main.push_component(Synth::new());
+    let amp = main.push_component(Amplifier);
+
+    main.set_param(amp, amplifier::params::Specifier::Amount, 0.5);
+    main.wire(
+        WireSrc::component_output(synth, synth::output::Specifier::Sine),
+        WireDst::component_input(amp, amplifier::input::Specifier::Input),
+    )
+    .unwrap();
+    main.wire(
+        WireSrc::component_output(amp, amplifier::output::Specifier::Output),
+        WireDst::func_output(mono::Specifier::Audio),
+    )
+    .unwrap();
+
+    check_rack("amplifier", rack);
+}
+
+#[test]
+fn midi_expander() {
+    let mut rack = Rack::<OctahackComponent, mono::Specifier, voices::Specifier>::new();
+    let file = rack.files_mut().add_midi(midi_file());
+    let mut main = rack.main_mut();
+    let player = main.push_component(MidiPlayer::new());
+    let expander = main.push_component(MidiExpander::new());
+    let synth = main.push_component(Synth::new());
+
+    main.set_param(player, midi_player::params::Specifier::File, Some(file));
+    main.set_param(expander, midi_expander::params::Specifier::Voices, 2.);
+    main.wire(
+        WireSrc::component_output(player, midi_player::output::Specifier::Midi),
+        WireDst::component_input(expander, midi_expander::input::Specifier::Midi),
+    )
+    .unwrap();
+    main.wire(
+        WireSrc::component_output(expander, midi_expander::output::Specifier::Freq),
+        WireDst::component_input(synth, synth::input::Specifier::Pitch),
+    )
+    .unwrap();
+    main.wire(
+        WireSrc::component_output(synth, synth::output::Specifier::Sine),
+        WireDst::func_output(voices::Specifier::Sine),
+    )
+    .unwrap();
+    main.wire(
+        WireSrc::component_output(expander, midi_expander::output::Specifier::Gate),
+        WireDst::func_output(voices::Specifier::Gate),
+    )
+    .unwrap();
+
+    check_rack("midi_expander", rack);
+}
+
+#[test]
+fn midi_splitter() {
+    use midi_splitter::output::Specifier::*;
+
+    let mut rack = Rack::<OctahackComponent, mono::Specifier, split::Specifier>::new();
+    let file = rack.files_mut().add_midi(midi_file());
+    let mut main = rack.main_mut();
+    let player = main.push_component(MidiPlayer::new());
+    let splitter = main.push_component(MidiSplitter::new());
+
+    main.set_param(player, midi_player::params::Specifier::File, Some(file));
+    main.set_param(splitter, midi_splitter::params::Specifier::Cc1, 1.);
+    main.wire(
+        WireSrc::component_output(player, midi_player::output::Specifier::Midi),
+        WireDst::component_input(splitter, midi_splitter::input::Specifier::Midi),
+    )
+    .unwrap();
+
+    for &(output, dst) in &[
+        (Gate, split::Specifier::Gate),
+        (Note, split::Specifier::Note),
+        (Vel, split::Specifier::Vel),
+        (Bend, split::Specifier::Bend),
+        (Cc1, split::Specifier::Cc1),
+    ] {
+        main.wire(
+            WireSrc::component_output(splitter, output),
+            WireDst::func_output(dst),
+        )
+        .unwrap();
+    }
+
+    check_rack("midi_splitter", rack);
+}
+
+#[test]
+fn recorder() {
+    let mut rack = Rack::<OctahackComponent, mono::Specifier, mono::Specifier>::new();
+    // Shorter than the render, so that recording stops when the buffer is full
+    let buffer = rack.files_mut().add_buffer(Some(48));
+    let mut main = rack.main_mut();
+    let synth = main.push_component(Synth::new());
+    // Slow enough that its square wave keeps the `Record` gate open for the whole render
+    let lfo = main.push_component(Synth::new());
+    let recorder = main.push_component(Recorder::new());
+
+    main.set_param(lfo, synth::params::Specifier::Freq, synth::freq(1));
+    main.set_param(recorder, recorder::params::Specifier::Buffer, Some(buffer));
+    main.wire(
+        WireSrc::component_output(synth, synth::output::Specifier::Sine),
+        WireDst::component_input(recorder, recorder::input::Specifier::Input),
+    )
+    .unwrap();
+    main.wire(
+        WireSrc::component_output(lfo, synth::output::Specifier::Square),
+        WireDst::component_input(recorder, recorder::input::Specifier::Record),
+    )
+    .unwrap();
+    main.wire(
+        WireSrc::component_output(recorder, recorder::output::Specifier::Output),
+        WireDst::func_output(mono::Specifier::Audio),
+    )
+    .unwrap();
+
+    let rack = check_rack("recorder", rack);
+
+    check("recorder_buffer", &rack.files().audio(buffer).unwrap());
+}
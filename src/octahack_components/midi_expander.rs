@@ -1,61 +1,253 @@
-use crate::{params::Key, Component, Context, GetOutput, MidiValue, Value};
-use staticvec::StaticVec;
+use crate::{Component, Context, DisplayParam, GetOutput, MidiValue, UiElement, Value};
+use staticvec::{StaticVec, StaticVecIntoIter};
+use std::fmt;
 
 crate::specs! {
+    pub mod params {
+        Voices: crate::Value,
+        Steal: crate::Value
+    }
+
     pub mod input {
         Midi: crate::MidiValue
     }
 
-    // TODO: Have some system of having each output be "multiplexable", i.e. have a
-    //       single `Freq` output with some parameter representing the voice to
-    //       address at runtime, allowing us to change the number of voices at
-    //       runtime.
+    // Each output has one channel per voice
     pub mod output {
-        Freq0: crate::Value,
-        Vel0: crate::Value,
-        Freq1: crate::Value,
-        Vel1: crate::Value,
-        Freq2: crate::Value,
-        Vel2: crate::Value,
-        Freq3: crate::Value,
-        Vel3: crate::Value
+        Freq: crate::Value = crate::ValueType::continuous(),
+        Vel: crate::Value = crate::ValueType::continuous(),
+        Gate: crate::Value = crate::ValueType::continuous()
+    }
+}
+
+/// The most voices that a polyphonic component can play at once.
+pub(crate) const MAX_VOICES: usize = 16;
+const DEFAULT_VOICES: usize = 4;
+
+/// The number of voices selected by the natural value of the `Voices` param.
+fn voice_count(value: Value) -> usize {
+    (value.round().max(1.) as usize).min(MAX_VOICES)
+}
+
+impl DisplayParam for params::Voices {
+    type Display = impl fmt::Display;
+
+    fn display(val: Value) -> Self::Display {
+        voice_count(val)
+    }
+}
+
+/// Which voice to take over when a note is played while every voice is already playing a note.
+/// Voices that aren't playing a note are always used first.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Steal {
+    /// The voice whose note was played first.
+    Oldest,
+    /// The voice playing the lowest note.
+    Lowest,
+    /// Each voice in turn, starting after the voice that was used last. Voices that are playing
+    /// a note are skipped, unless every voice is.
+    RoundRobin,
+}
+
+impl Steal {
+    /// The natural value of the `Steal` param that selects this mode.
+    pub fn value(self) -> Value {
+        match self {
+            Steal::Oldest => -1.,
+            Steal::Lowest => 0.,
+            Steal::RoundRobin => 1.,
+        }
+    }
+
+    fn from_value(value: Value) -> Self {
+        if value < -0.5 {
+            Steal::Oldest
+        } else if value < 0.5 {
+            Steal::Lowest
+        } else {
+            Steal::RoundRobin
+        }
+    }
+}
+
+impl DisplayParam for params::Steal {
+    type Display = impl fmt::Display;
+
+    fn display(val: Value) -> Self::Display {
+        struct StealDisplay(Steal);
+
+        impl fmt::Display for StealDisplay {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self.0 {
+                    Steal::Oldest => write!(f, "Oldest"),
+                    Steal::Lowest => write!(f, "Lowest"),
+                    Steal::RoundRobin => write!(f, "Round-robin"),
+                }
+            }
+        }
+
+        StealDisplay(Steal::from_value(val))
+    }
+}
+
+impl Default for params::Params {
+    fn default() -> Self {
+        Self {
+            Voices: DEFAULT_VOICES as Value,
+            Steal: Steal::Oldest.value(),
+        }
     }
 }
 
-const VOICES: usize = 4;
+#[derive(Debug, Copy, Clone, Default)]
+struct Voice {
+    /// The note this voice is playing, or was playing last if the gate is closed, so that the
+    /// note keeps its pitch while it's released.
+    note: u8,
+    velocity: Value,
+    gate: bool,
+    /// When the note was played, counted in notes, for finding the oldest voice.
+    started: u64,
+}
 
-struct Note {
-    freq: f64,
-    vel: f64,
+impl Voice {
+    /// The frequency of the note in volts per octave, the same as `synth::freq`.
+    fn freq(&self) -> Value {
+        super::synth::note(self.note)
+    }
 }
 
-#[derive(Clone, Default)]
+/// Splits the notes coming in over MIDI between a number of voices, giving the frequency,
+/// velocity and gate of each voice as one channel of its outputs.
+#[derive(Debug, Clone)]
 pub struct MidiExpander {
-    notes: StaticVec<f64, VOICES>,
+    voices: [Voice; MAX_VOICES],
+    /// The number of voices in use, set by the `Voices` param. Voices after these are silent.
+    voice_count: usize,
+    /// The number of notes played so far.
+    notes_played: u64,
+    /// The voice after the one that was played last, where round-robin allocation starts from.
+    next_voice: usize,
+}
+
+impl Default for MidiExpander {
+    fn default() -> Self {
+        MidiExpander {
+            voices: Default::default(),
+            voice_count: DEFAULT_VOICES,
+            notes_played: 0,
+            next_voice: 0,
+        }
+    }
+}
+
+impl UiElement for MidiExpander {
+    const NAME: &'static str = "MidiExpander";
 }
 
 impl MidiExpander {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Choose a voice for a new note, preferring voices that aren't playing anything.
+    fn allocate(&self, note: u8, steal: Steal) -> usize {
+        let count = self.voice_count;
+        let voices = self.voices[..count].iter().enumerate();
+        let round_robin = (0..count).map(|i| (self.next_voice + i) % count);
+
+        // Playing a note that's already held retriggers it instead of playing it twice
+        if let Some((i, _)) = voices
+            .clone()
+            .find(|(_, voice)| voice.gate && voice.note == note)
+        {
+            return i;
+        }
+
+        let free = match steal {
+            Steal::RoundRobin => round_robin.find(|&i| !self.voices[i].gate),
+            // Use the voice that was released longest ago, so that recent notes can ring out
+            Steal::Oldest | Steal::Lowest => voices
+                .clone()
+                .filter(|(_, voice)| !voice.gate)
+                .min_by_key(|(_, voice)| voice.started)
+                .map(|(i, _)| i),
+        };
+
+        free.unwrap_or_else(|| match steal {
+            Steal::Oldest => voices.min_by_key(|(_, voice)| voice.started).unwrap().0,
+            Steal::Lowest => voices.min_by_key(|(_, voice)| voice.note).unwrap().0,
+            Steal::RoundRobin => self.next_voice,
+        })
+    }
+
+    fn note_on(&mut self, note: u8, velocity: u8, steal: Steal) {
+        let i = self.allocate(note, steal);
+
+        self.notes_played += 1;
+        self.voices[i] = Voice {
+            note,
+            velocity: Value::from(velocity) / 127.,
+            gate: true,
+            started: self.notes_played,
+        };
+        self.next_voice = (i + 1) % self.voice_count;
+    }
+
+    /// Change the number of voices, silencing any voices that are no longer in use.
+    fn set_voice_count(&mut self, count: usize) {
+        for voice in &mut self.voices[count..] {
+            voice.gate = false;
+        }
+
+        self.voice_count = count;
+        self.next_voice %= count;
+    }
+
+    /// One output channel per voice.
+    fn channels(
+        &self,
+        value: impl Fn(&Voice) -> Value,
+    ) -> StaticVecIntoIter<Value, { MAX_VOICES }> {
+        self.voices[..self.voice_count]
+            .iter()
+            .map(value)
+            .collect::<StaticVec<_, { MAX_VOICES }>>()
+            .into_iter()
+    }
+
+    fn note_off(&mut self, note: u8) {
+        for voice in &mut self.voices {
+            if voice.gate && voice.note == note {
+                voice.gate = false;
+            }
+        }
+    }
 }
 
 impl Component for MidiExpander {
     type InputSpecifier = input::Specifier;
     type OutputSpecifier = output::Specifier;
-    type ParamSpecifier = !;
+    type ParamSpecifier = params::Specifier;
 
     fn update<Ctx>(&self, ctx: &Ctx) -> Self
     where
         Ctx: Context<Self>,
     {
-        let out = self.clone();
+        let mut out = self.clone();
+        let steal = Steal::from_value(ctx.param::<params::Steal>());
+
+        out.set_voice_count(voice_count(ctx.param::<params::Voices>()));
 
         if let Some(midi) = ctx.input::<input::Midi>() {
             for msg in midi {
                 match msg {
-                    MidiValue::NoteOn(_note, _) => unimplemented!(),
-                    MidiValue::NoteOff(_note, _) => unimplemented!(),
+                    // A note on with no velocity is a note off, by MIDI convention
+                    MidiValue::NoteOn(note, 0) | MidiValue::NoteOff(note, _) => {
+                        out.note_off(note.into())
+                    }
+                    MidiValue::NoteOn(note, velocity) => out.note_on(note.into(), velocity, steal),
                     _ => {}
                 }
             }
@@ -64,3 +256,90 @@ impl Component for MidiExpander {
         out
     }
 }
+
+impl GetOutput<output::Freq> for MidiExpander {
+    type Iter = impl ExactSizeIterator<Item = Value> + Send;
+
+    fn output<Ctx>(&self, _: &Ctx) -> Self::Iter
+    where
+        Ctx: Context<Self>,
+    {
+        self.channels(Voice::freq)
+    }
+}
+
+impl GetOutput<output::Vel> for MidiExpander {
+    type Iter = impl ExactSizeIterator<Item = Value> + Send;
+
+    fn output<Ctx>(&self, _: &Ctx) -> Self::Iter
+    where
+        Ctx: Context<Self>,
+    {
+        self.channels(|voice| voice.velocity)
+    }
+}
+
+impl GetOutput<output::Gate> for MidiExpander {
+    type Iter = impl ExactSizeIterator<Item = Value> + Send;
+
+    fn output<Ctx>(&self, _: &Ctx) -> Self::Iter
+    where
+        Ctx: Context<Self>,
+    {
+        self.channels(|voice| if voice.gate { 1. } else { 0. })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MidiExpander, Steal};
+
+    fn notes(expander: &MidiExpander) -> Vec<Option<u8>> {
+        expander.voices[..expander.voice_count]
+            .iter()
+            .map(|voice| if voice.gate { Some(voice.note) } else { None })
+            .collect()
+    }
+
+    #[test]
+    fn voice_stealing() {
+        let play = |steal, notes: &[u8]| {
+            let mut expander = MidiExpander::new();
+            for &note in notes {
+                expander.note_on(note, 100, steal);
+            }
+            expander
+        };
+
+        let mut expander = play(Steal::Oldest, &[64, 60, 67, 62, 72]);
+        assert_eq!(notes(&expander), [Some(72), Some(60), Some(67), Some(62)]);
+        // Released voices are reused before any held voice is stolen
+        expander.note_off(67);
+        expander.note_on(65, 100, Steal::Oldest);
+        assert_eq!(notes(&expander), [Some(72), Some(60), Some(65), Some(62)]);
+        // Playing a held note again doesn't take another voice
+        expander.note_on(60, 50, Steal::Oldest);
+        assert_eq!(notes(&expander), [Some(72), Some(60), Some(65), Some(62)]);
+
+        let expander = play(Steal::Lowest, &[64, 60, 67, 62, 72]);
+        assert_eq!(notes(&expander), [Some(64), Some(72), Some(67), Some(62)]);
+
+        let mut expander = play(Steal::RoundRobin, &[64, 60, 67, 62]);
+        expander.note_off(60);
+        expander.note_on(72, 100, Steal::RoundRobin);
+        assert_eq!(notes(&expander), [Some(64), Some(72), Some(67), Some(62)]);
+        expander.note_on(74, 100, Steal::RoundRobin);
+        assert_eq!(notes(&expander), [Some(64), Some(72), Some(74), Some(62)]);
+
+        assert_eq!(expander.voices[2].freq(), 5. / 12.);
+        assert_eq!(expander.voices[2].velocity, 100. / 127.);
+
+        // Voices that are no longer in use are silenced
+        expander.set_voice_count(2);
+        assert_eq!(notes(&expander), [Some(64), Some(72)]);
+        expander.note_on(76, 100, Steal::RoundRobin);
+        assert_eq!(notes(&expander), [Some(64), Some(76)]);
+        expander.set_voice_count(3);
+        assert_eq!(notes(&expander), [Some(64), Some(76), None]);
+    }
+}
@@ -0,0 +1,349 @@
+use crate::{
+    block::BlockOutputs,
+    context::{BlockContext, ContextMetaExt, DisplayFile, File, FileAccess, FileId},
+    Component, Context, DisplayParam, GetOutput, MidiValue, UiElement, Value,
+};
+use staticvec::StaticVec;
+use std::fmt;
+
+crate::specs! {
+    pub mod params {
+        File: Option<crate::context::FileId<crate::MidiValue>>,
+        Start: crate::Value,
+        Length: crate::Value,
+        Loop: crate::Value,
+        Speed: crate::Value
+    }
+
+    pub mod output {
+        Midi: crate::MidiValue
+    }
+}
+
+impl DisplayParam for params::File {
+    type Display = impl fmt::Display;
+
+    fn display(val: Option<FileId<MidiValue>>) -> Self::Display {
+        DisplayFile(val)
+    }
+}
+
+struct BeatsDisplay(Value);
+
+impl fmt::Display for BeatsDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} beats", (self.0.max(0.) * 100.).round() / 100.)
+    }
+}
+
+impl DisplayParam for params::Start {
+    type Display = impl fmt::Display;
+
+    fn display(val: Value) -> Self::Display {
+        BeatsDisplay(val)
+    }
+}
+
+impl DisplayParam for params::Length {
+    type Display = impl fmt::Display;
+
+    fn display(val: Value) -> Self::Display {
+        BeatsDisplay(val)
+    }
+}
+
+impl DisplayParam for params::Loop {
+    type Display = &'static str;
+
+    fn display(val: Value) -> Self::Display {
+        if is_on(val) {
+            "On"
+        } else {
+            "Off"
+        }
+    }
+}
+
+impl DisplayParam for params::Speed {
+    type Display = impl fmt::Display;
+
+    fn display(val: Value) -> Self::Display {
+        struct SpeedDisplay(Value);
+
+        impl fmt::Display for SpeedDisplay {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}x", (self.0.max(0.) * 100.).round() / 100.)
+            }
+        }
+
+        SpeedDisplay(val)
+    }
+}
+
+impl Default for params::Params {
+    fn default() -> Self {
+        Self {
+            File: None,
+            Start: 0.,
+            Length: 16.,
+            Loop: 1.,
+            Speed: 1.,
+        }
+    }
+}
+
+/// The most MIDI events that a component sends in one tick, which is enough for a player to stop
+/// every note and still play the events of the file.
+pub(crate) const MAX_EVENTS: usize = 256;
+
+fn is_on(val: Value) -> bool {
+    val >= 0.5
+}
+
+/// The part of a MIDI file that's played, in beats of the file.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Region {
+    start: Value,
+    length: Value,
+    looping: bool,
+}
+
+/// Plays a MIDI file in time with the transport, where `Speed` is the number of beats of the
+/// file played per beat of the transport. Only the `Length` beats from `Start` are played, over
+/// and over if `Loop` is on.
+#[derive(Debug, Clone, Default)]
+pub struct MidiPlayer {
+    /// How far through the region playback is, in beats of the file.
+    position: Value,
+    /// The beat position of the transport at the last tick, or `None` if this hasn't been
+    /// updated yet.
+    last_beat: Option<Value>,
+    /// The notes that have been started but not stopped, as a bitset, so that they can be stopped
+    /// when playback jumps instead of being left hanging.
+    held: u128,
+    /// The events sent this tick. Any events past the first `MAX_EVENTS` are dropped.
+    events: StaticVec<MidiValue, { MAX_EVENTS }>,
+}
+
+impl UiElement for MidiPlayer {
+    const NAME: &'static str = "MidiPlayer";
+}
+
+impl MidiPlayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn send(&mut self, events: impl IntoIterator<Item = MidiValue>) {
+        for event in events {
+            if self.events.try_push(event).is_err() {
+                break;
+            }
+
+            match event {
+                MidiValue::NoteOn(note, velocity) if velocity > 0 => {
+                    self.held |= 1 << u8::from(note)
+                }
+                MidiValue::NoteOn(note, _) | MidiValue::NoteOff(note, _) => {
+                    self.held &= !(1 << u8::from(note))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Stop every note that's playing.
+    fn release(&mut self) {
+        let held = self.held;
+
+        self.send(
+            (0..128)
+                .filter(|note| held & (1 << note) != 0)
+                .map(|note| MidiValue::NoteOff(note.into(), 0)),
+        );
+    }
+
+    /// Play the events of the region between `from` and `to`.
+    fn play_between<F>(&mut self, file: &F, region: Region, from: Value, to: Value)
+    where
+        F: File<MidiValue>,
+    {
+        self.send(
+            file.between(region.start + from, region.start + to)
+                .flatten(),
+        );
+    }
+
+    /// Play the events of one tick.
+    fn tick<Ctx>(&mut self, ctx: &Ctx)
+    where
+        Ctx: Context<Self>,
+    {
+        self.events.clear();
+
+        let region = Region {
+            start: ctx.param::<params::Start>().max(0.),
+            length: ctx.param::<params::Length>().max(0.),
+            looping: is_on(ctx.param::<params::Loop>()),
+        };
+        let speed = ctx.param::<params::Speed>().max(0.);
+        let beat = ctx.beat_position();
+        let step = beat - self.last_beat.unwrap_or(beat);
+
+        self.last_beat = Some(beat);
+
+        let file = match ctx.param::<params::File>() {
+            Some(file) => FileAccess::<MidiValue>::read(ctx, file),
+            None => {
+                self.release();
+                return;
+            }
+        };
+
+        if step < 0. || step > 1. {
+            // The transport jumped, so jump to the same place in the file instead of playing
+            // everything in between
+            let position = beat * speed;
+
+            self.release();
+            self.position = if region.looping && region.length > 0. {
+                position % region.length
+            } else {
+                position
+            };
+        } else {
+            self.advance(&file, region, step * speed);
+        }
+    }
+
+    /// Move playback forward by `step` beats of the file.
+    fn advance<F>(&mut self, file: &F, region: Region, step: Value)
+    where
+        F: File<MidiValue>,
+    {
+        let (from, to) = (self.position, self.position + step);
+
+        if region.looping && region.length > 0. && to >= region.length {
+            let to = (to - region.length) % region.length;
+
+            self.play_between(file, region, from, region.length);
+            self.release();
+            self.play_between(file, region, 0., to);
+            self.position = to;
+        } else {
+            if from < region.length {
+                self.play_between(file, region, from, to.min(region.length));
+                if to >= region.length {
+                    self.release();
+                }
+            }
+            self.position = to;
+        }
+    }
+}
+
+impl Component for MidiPlayer {
+    type InputSpecifier = !;
+    type OutputSpecifier = output::Specifier;
+    type ParamSpecifier = params::Specifier;
+
+    fn update<Ctx>(&self, ctx: &Ctx) -> Self
+    where
+        Ctx: Context<Self>,
+    {
+        let mut out = self.clone();
+        out.tick(ctx);
+        out
+    }
+
+    fn process_block<Ctx>(&self, ctx: &Ctx, outputs: &mut BlockOutputs) -> Option<Self>
+    where
+        Ctx: BlockContext<Self>,
+    {
+        let mut player = self.clone();
+
+        for i in 0..ctx.frames() {
+            player.tick(&ctx.frame(i));
+            outputs.push_midi(output::Specifier::Midi, player.events.iter().copied());
+        }
+
+        Some(player)
+    }
+}
+
+impl GetOutput<output::Midi> for MidiPlayer {
+    type Iter = impl ExactSizeIterator<Item = MidiValue> + Send;
+
+    fn output<Ctx>(&self, _: &Ctx) -> Self::Iter
+    where
+        Ctx: Context<Self>,
+    {
+        self.events.clone().into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MidiPlayer, Region};
+    use crate::{midi_file::MidiFile, MidiValue};
+    use std::sync::{Arc, RwLock};
+
+    /// One track with 1 tick per beat, playing note 60 for two beats and then note 62 for two
+    /// beats.
+    const FILE: &[u8] = &[
+        b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 1, //
+        b'M', b'T', b'r', b'k', 0, 0, 0, 20, //
+        0, 0x90, 60, 100, //
+        2, 0x80, 60, 0, //
+        0, 0x90, 62, 100, //
+        2, 0x80, 62, 0, //
+        0, 0xFF, 0x2F, 0, //
+    ];
+
+    #[test]
+    fn loop_region() {
+        let file = Arc::new(RwLock::new(MidiFile::parse(FILE).unwrap()));
+        let region = Region {
+            start: 1.,
+            length: 2.,
+            looping: true,
+        };
+        let mut player = MidiPlayer::new();
+        let step = |player: &mut MidiPlayer, region, beats| {
+            player.events.clear();
+            player.advance(&file, region, beats);
+            player.events.clone()
+        };
+
+        assert!(step(&mut player, region, 0.5).is_empty());
+        // Note 62 starts at the second beat of the region
+        assert_eq!(
+            step(&mut player, region, 1.),
+            [
+                MidiValue::NoteOff(60.into(), 0),
+                MidiValue::NoteOn(62.into(), 100),
+            ]
+        );
+        // Looping back stops note 62, which would otherwise be left playing
+        assert_eq!(
+            step(&mut player, region, 1.),
+            [MidiValue::NoteOff(62.into(), 0)]
+        );
+        assert_eq!(player.position, 0.5);
+
+        let region = Region {
+            looping: false,
+            ..region
+        };
+        player.position = 0.;
+        assert_eq!(
+            step(&mut player, region, 5.),
+            [
+                MidiValue::NoteOff(60.into(), 0),
+                MidiValue::NoteOn(62.into(), 100),
+                MidiValue::NoteOff(62.into(), 0),
+            ]
+        );
+        assert!(step(&mut player, region, 1.).is_empty());
+    }
+}
@@ -0,0 +1,320 @@
+use super::midi_player::MAX_EVENTS;
+use crate::{
+    context::{ContextMetaExt, DisplayFile, FileId},
+    midi_file::MidiFile,
+    Component, Context, DisplayParam, GetOutput, MidiValue, UiElement, Value,
+};
+use staticvec::StaticVec;
+use std::fmt;
+
+crate::specs! {
+    pub mod params {
+        Buffer: Option<crate::context::FileId<crate::MidiValue>>,
+        Quantise: crate::Value
+    }
+
+    pub mod input {
+        Midi: crate::MidiValue,
+        Record: crate::Value,
+        Overdub: crate::Value,
+        Stop: crate::Value
+    }
+
+    pub mod output {
+        Midi: crate::MidiValue
+    }
+}
+
+impl DisplayParam for params::Buffer {
+    type Display = impl fmt::Display;
+
+    fn display(val: Option<FileId<MidiValue>>) -> Self::Display {
+        DisplayFile(val)
+    }
+}
+
+impl DisplayParam for params::Quantise {
+    type Display = impl fmt::Display;
+
+    fn display(val: Value) -> Self::Display {
+        struct QuantiseDisplay(Value);
+
+        impl fmt::Display for QuantiseDisplay {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                if self.0 > 0. {
+                    write!(f, "{} beats", (self.0 * 100.).round() / 100.)
+                } else {
+                    write!(f, "Off")
+                }
+            }
+        }
+
+        QuantiseDisplay(val)
+    }
+}
+
+impl Default for params::Params {
+    fn default() -> Self {
+        Self {
+            Buffer: None,
+            Quantise: 0.,
+        }
+    }
+}
+
+/// The number of held notes whose quantising is remembered.
+const HELD_NOTES: usize = 16;
+
+/// Round `beat` to the nearest multiple of `grid`, or leave it alone if `grid` isn't positive.
+fn quantise(beat: Value, grid: Value) -> Value {
+    if grid > 0. {
+        (beat / grid).round() * grid
+    } else {
+        beat
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Stopped,
+    /// Replacing the buffer with the input.
+    Recording,
+    /// Adding the input to the buffer, going back to the start of the buffer when we reach the
+    /// end of it.
+    Overdubbing,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::Stopped
+    }
+}
+
+/// Records its MIDI input into the file chosen by `Buffer`, which should be made with
+/// `Files::add_midi_buffer`, so that it can be played by a `MidiPlayer` or written out with
+/// `MidiFile::encode`. The gates work the same as `Recorder`'s. Events are recorded at the beat
+/// of the sample they arrive at, or at the nearest multiple of `Quantise` beats if it's set, in
+/// which case note offs are moved by the same amount as their note on so that notes keep their
+/// length. Stopping a recording rounds the length of the file to `Quantise` too, so that it
+/// loops in time. The input is passed through to the output unchanged, except that any events
+/// past the first `MAX_EVENTS` in one tick are dropped.
+#[derive(Debug, Clone, Default)]
+pub struct MidiRecorder {
+    state: State,
+    /// The beat of the transport that the buffer starts at.
+    start: Value,
+    /// The notes that have been recorded but not stopped, along with how far their note on was
+    /// moved by quantising. If more notes than this are held then the oldest is forgotten, and
+    /// its note off is quantised by itself.
+    held: StaticVec<(u8, Value), { HELD_NOTES }>,
+    /// Whether the `Record`, `Overdub` and `Stop` gates were open last tick, so that holding a
+    /// gate open only triggers it once.
+    gates: [bool; 3],
+}
+
+impl UiElement for MidiRecorder {
+    const NAME: &'static str = "MidiRecorder";
+}
+
+impl MidiRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Switch to `trigger` if a gate was opened, then record the events that arrived at `beat`.
+    fn record(
+        &mut self,
+        buffer: &mut MidiFile,
+        trigger: Option<State>,
+        events: impl IntoIterator<Item = MidiValue>,
+        beat: Value,
+        grid: Value,
+    ) {
+        match trigger {
+            Some(State::Recording) => {
+                buffer.clear();
+                self.start = beat;
+                self.held.clear();
+            }
+            Some(State::Overdubbing) => {
+                self.start = beat;
+                self.held.clear();
+            }
+            Some(State::Stopped) if self.state == State::Recording => {
+                buffer.set_end(quantise(beat - self.start, grid));
+            }
+            _ => {}
+        }
+
+        if let Some(state) = trigger {
+            self.state = state;
+        }
+
+        // Overdubbing onto nothing is the same as recording
+        if self.state == State::Overdubbing && buffer.beats() <= 0. {
+            buffer.clear();
+            self.state = State::Recording;
+        }
+
+        if self.state == State::Stopped {
+            return;
+        }
+
+        let position = beat - self.start;
+
+        for event in events {
+            let shift = match event {
+                MidiValue::NoteOn(note, velocity) if velocity > 0 => {
+                    let note = u8::from(note);
+                    let shift = quantise(position, grid) - position;
+
+                    self.held.retain(|&(other, _)| other != note);
+                    if self.held.is_full() {
+                        self.held.remove(0);
+                    }
+                    self.held.push((note, shift));
+
+                    shift
+                }
+                MidiValue::NoteOn(note, _) | MidiValue::NoteOff(note, _) => {
+                    let note = u8::from(note);
+
+                    match self.held.iter().position(|&(other, _)| other == note) {
+                        Some(i) => self.held.remove(i).1,
+                        None => quantise(position, grid) - position,
+                    }
+                }
+                _ => quantise(position, grid) - position,
+            };
+            let at = match self.state {
+                State::Overdubbing => (position + shift).rem_euclid(buffer.beats()),
+                _ => position + shift,
+            };
+
+            if !buffer.insert(at, event) {
+                if self.state == State::Recording {
+                    buffer.set_end(position);
+                }
+                self.state = State::Stopped;
+                return;
+            }
+        }
+    }
+}
+
+impl Component for MidiRecorder {
+    type InputSpecifier = input::Specifier;
+    type OutputSpecifier = output::Specifier;
+    type ParamSpecifier = params::Specifier;
+
+    fn update<Ctx>(&self, ctx: &Ctx) -> Self
+    where
+        Ctx: Context<Self>,
+    {
+        let mut out = self.clone();
+        let gates = [
+            ctx.input::<input::Record>()
+                .map_or(false, |mut gate| gate.any(|val| val >= 0.5)),
+            ctx.input::<input::Overdub>()
+                .map_or(false, |mut gate| gate.any(|val| val >= 0.5)),
+            ctx.input::<input::Stop>()
+                .map_or(false, |mut gate| gate.any(|val| val >= 0.5)),
+        ];
+        let opened = |i: usize| gates[i] && !self.gates[i];
+        // As with `Recorder`, stopping wins if more than one gate opens at once
+        let trigger = if opened(2) {
+            Some(State::Stopped)
+        } else if opened(0) {
+            Some(State::Recording)
+        } else if opened(1) {
+            Some(State::Overdubbing)
+        } else {
+            None
+        };
+
+        out.gates = gates;
+
+        let id = match ctx.param::<params::Buffer>() {
+            Some(id) => id,
+            None => {
+                out.state = State::Stopped;
+                return out;
+            }
+        };
+
+        // Only lock the buffer when we're going to write to it, which includes setting the end
+        // of a recording when it's stopped
+        if self.state == State::Stopped && trigger.map_or(true, |state| state == State::Stopped) {
+            return out;
+        }
+
+        if let Some(mut buffer) = ctx.files().midi_mut(id) {
+            out.record(
+                &mut buffer,
+                trigger,
+                ctx.input::<input::Midi>().into_iter().flatten(),
+                ctx.beat_position(),
+                ctx.param::<params::Quantise>(),
+            );
+        } else {
+            out.state = State::Stopped;
+        }
+
+        out
+    }
+}
+
+impl GetOutput<output::Midi> for MidiRecorder {
+    type Iter = impl ExactSizeIterator<Item = MidiValue> + Send;
+
+    fn output<Ctx>(&self, ctx: &Ctx) -> Self::Iter
+    where
+        Ctx: Context<Self>,
+    {
+        ctx.input::<input::Midi>()
+            .into_iter()
+            .flatten()
+            .collect::<StaticVec<_, { MAX_EVENTS }>>()
+            .into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MidiRecorder, State};
+    use crate::{midi_file::MidiFile, MidiValue};
+
+    #[test]
+    fn record_quantised_and_overdub() {
+        let mut recorder = MidiRecorder::new();
+        let mut buffer = MidiFile::buffer(None);
+        let on = |note: u8| MidiValue::NoteOn(note.into(), 100);
+        let off = |note: u8| MidiValue::NoteOff(note.into(), 0);
+        let events = |buffer: &MidiFile| {
+            buffer
+                .between(0., 16.)
+                .map(|group| group.collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+
+        // Recording starts at the transport's beat 4 and is quantised to half beats
+        recorder.record(&mut buffer, Some(State::Recording), vec![], 4., 0.5);
+        recorder.record(&mut buffer, None, vec![on(60)], 4.625, 0.5);
+        recorder.record(&mut buffer, None, vec![off(60)], 5.125, 0.5);
+        recorder.record(&mut buffer, Some(State::Stopped), vec![on(62)], 5.875, 0.5);
+        assert_eq!(buffer.beats(), 2.);
+        assert_eq!(events(&buffer), [vec![on(60)], vec![off(60)]]);
+        // The note on is moved back to the grid and the note off is moved with it
+        assert_eq!(buffer.at(1.).collect::<Vec<_>>(), [off(60)]);
+
+        // Overdubbing goes round the buffer
+        recorder.record(&mut buffer, Some(State::Overdubbing), vec![], 8., 0.);
+        recorder.record(&mut buffer, None, vec![on(64)], 9.75, 0.);
+        recorder.record(&mut buffer, None, vec![off(64)], 10.25, 0.);
+        assert_eq!(
+            events(&buffer),
+            [vec![off(64)], vec![on(60)], vec![off(60)], vec![on(64)]]
+        );
+        assert_eq!(buffer.beats(), 2.);
+    }
+}
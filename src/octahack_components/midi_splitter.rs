@@ -0,0 +1,294 @@
+use super::synth;
+use crate::{Component, Context, DisplayParam, GetOutput, MidiValue, UiElement, Value};
+use staticvec::StaticVec;
+use std::{fmt, iter};
+
+crate::specs! {
+    pub mod params {
+        Cc1: crate::Value,
+        Cc2: crate::Value,
+        Cc3: crate::Value,
+        Cc4: crate::Value
+    }
+
+    pub mod input {
+        Midi: crate::MidiValue
+    }
+
+    pub mod output {
+        Gate: crate::Value,
+        Note: crate::Value,
+        Vel: crate::Value,
+        Bend: crate::Value,
+        Aftertouch: crate::Value,
+        Cc1: crate::Value,
+        Cc2: crate::Value,
+        Cc3: crate::Value,
+        Cc4: crate::Value
+    }
+}
+
+const CC_SLOTS: usize = 4;
+/// The number of held notes that are remembered in the order they were played.
+const RECENT_NOTES: usize = 16;
+
+/// The controller selected by the natural value of a `Cc` param, where negative values select
+/// nothing.
+fn controller(value: Value) -> Option<u8> {
+    if value < 0. {
+        None
+    } else {
+        Some(value.round().min(127.) as u8)
+    }
+}
+
+struct ControllerDisplay(Option<u8>);
+
+impl fmt::Display for ControllerDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(cc) => write!(f, "CC {}", cc),
+            None => write!(f, "Off"),
+        }
+    }
+}
+
+macro_rules! display_controller {
+    ($($param:ident),*) => {
+        $(
+            impl DisplayParam for params::$param {
+                type Display = impl fmt::Display;
+
+                fn display(val: Value) -> Self::Display {
+                    ControllerDisplay(controller(val))
+                }
+            }
+        )*
+    };
+}
+
+display_controller!(Cc1, Cc2, Cc3, Cc4);
+
+impl Default for params::Params {
+    fn default() -> Self {
+        Self {
+            Cc1: -1.,
+            Cc2: -1.,
+            Cc3: -1.,
+            Cc4: -1.,
+        }
+    }
+}
+
+/// Splits a monophonic MIDI stream into separate outputs for the gate, pitch, velocity, pitch
+/// bend and aftertouch of the note being played, along with the values of up to four
+/// controllers chosen by the `Cc` params. When several notes are held, the one played last is
+/// the one that sounds.
+#[derive(Debug, Clone, Default)]
+pub struct MidiSplitter {
+    /// The notes being held, as a bitset.
+    held: u128,
+    /// The held notes that were played last, oldest first, so that releasing the sounding note
+    /// goes back to the one played before it. If more notes than this are held then the oldest
+    /// are forgotten, and releasing every remembered note goes back to the highest held note.
+    recent: StaticVec<u8, { RECENT_NOTES }>,
+    /// The note sounding, or the one that was sounding last if none are held, so that the note
+    /// keeps its pitch while it's released.
+    note: u8,
+    velocity: Value,
+    /// Between -1 and 1, where 0 is no bend.
+    bend: Value,
+    aftertouch: Value,
+    /// The controller selected by each `Cc` param along with its last value. Controllers that
+    /// aren't selected are ignored.
+    ccs: [Option<(u8, Value)>; CC_SLOTS],
+}
+
+impl UiElement for MidiSplitter {
+    const NAME: &'static str = "MidiSplitter";
+}
+
+impl MidiSplitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Change which controllers are followed. A slot whose controller changes starts at 0 until
+    /// the new controller is sent.
+    fn select(&mut self, controllers: [Option<u8>; CC_SLOTS]) {
+        for (slot, &controller) in self.ccs.iter_mut().zip(&controllers) {
+            if slot.map(|(cc, _)| cc) != controller {
+                *slot = controller.map(|cc| (cc, 0.));
+            }
+        }
+    }
+
+    fn receive(&mut self, msg: MidiValue) {
+        match msg {
+            // A note on with no velocity is a note off, by MIDI convention
+            MidiValue::NoteOn(note, 0) | MidiValue::NoteOff(note, _) => {
+                let note = u8::from(note);
+
+                self.held &= !(1 << note);
+                self.recent.retain(|&other| other != note);
+                if let Some(&last) = self.recent.last() {
+                    self.note = last;
+                } else if self.held != 0 {
+                    self.note = 127 - self.held.leading_zeros() as u8;
+                }
+            }
+            MidiValue::NoteOn(note, velocity) => {
+                let note = u8::from(note);
+
+                self.held |= 1 << note;
+                self.recent.retain(|&other| other != note);
+                if self.recent.is_full() {
+                    self.recent.remove(0);
+                }
+                self.recent.push(note);
+                self.note = note;
+                self.velocity = Value::from(velocity) / 127.;
+                self.aftertouch = 0.;
+            }
+            MidiValue::PolyphonicPressure(note, pressure) if u8::from(note) == self.note => {
+                self.aftertouch = Value::from(pressure) / 127.
+            }
+            MidiValue::ChannelPressure(pressure) => self.aftertouch = Value::from(pressure) / 127.,
+            MidiValue::PitchBend(lsb, msb) => {
+                let bend = (u16::from(msb) << 7) | u16::from(lsb);
+
+                self.bend = (Value::from(bend) - 8192.) / 8192.
+            }
+            MidiValue::Controller(controller, value) => {
+                for (cc, cc_value) in self.ccs.iter_mut().flatten() {
+                    if *cc == controller {
+                        *cc_value = Value::from(value) / 127.
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn cc(&self, slot: usize) -> iter::Once<Value> {
+        iter::once(self.ccs[slot].map_or(0., |(_, value)| value))
+    }
+}
+
+impl Component for MidiSplitter {
+    type InputSpecifier = input::Specifier;
+    type OutputSpecifier = output::Specifier;
+    type ParamSpecifier = params::Specifier;
+
+    fn update<Ctx>(&self, ctx: &Ctx) -> Self
+    where
+        Ctx: Context<Self>,
+    {
+        let mut out = self.clone();
+
+        out.select([
+            controller(ctx.param::<params::Cc1>()),
+            controller(ctx.param::<params::Cc2>()),
+            controller(ctx.param::<params::Cc3>()),
+            controller(ctx.param::<params::Cc4>()),
+        ]);
+
+        if let Some(midi) = ctx.input::<input::Midi>() {
+            for msg in midi {
+                out.receive(msg);
+            }
+        }
+
+        out
+    }
+}
+
+macro_rules! outputs {
+    ($($output:ident => |$this:ident| $value:expr),* $(,)?) => {
+        $(
+            impl GetOutput<output::$output> for MidiSplitter {
+                type Iter = impl ExactSizeIterator<Item = Value> + Send;
+
+                fn output<Ctx>(&self, _: &Ctx) -> Self::Iter
+                where
+                    Ctx: Context<Self>,
+                {
+                    let $this = self;
+
+                    $value
+                }
+            }
+        )*
+    };
+}
+
+outputs! {
+    Gate => |this| iter::once(if this.held == 0 { 0. } else { 1. }),
+    Note => |this| iter::once(synth::note(this.note)),
+    Vel => |this| iter::once(this.velocity),
+    Bend => |this| iter::once(this.bend),
+    Aftertouch => |this| iter::once(this.aftertouch),
+    Cc1 => |this| this.cc(0),
+    Cc2 => |this| this.cc(1),
+    Cc3 => |this| this.cc(2),
+    Cc4 => |this| this.cc(3),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MidiSplitter;
+    use crate::MidiValue;
+
+    #[test]
+    fn split() {
+        let mut splitter = MidiSplitter::new();
+        splitter.select([Some(1), None, Some(74), Some(1)]);
+
+        for &msg in &[
+            MidiValue::NoteOn(60.into(), 127),
+            MidiValue::NoteOn(64.into(), 0),
+            MidiValue::NoteOn(67.into(), 100),
+            MidiValue::Controller(1, 127),
+            MidiValue::Controller(7, 127),
+            MidiValue::PitchBend(0, 0),
+            MidiValue::ChannelPressure(127),
+        ] {
+            splitter.receive(msg);
+        }
+
+        assert_eq!(splitter.held, 1 << 60 | 1 << 67);
+        assert_eq!(splitter.recent, [60, 67]);
+        assert_eq!(splitter.note, 67);
+        assert_eq!(splitter.velocity, 100. / 127.);
+        assert_eq!(splitter.bend, -1.);
+        assert_eq!(splitter.aftertouch, 1.);
+        assert_eq!(
+            splitter.ccs,
+            [Some((1, 1.)), None, Some((74, 0.)), Some((1, 1.))]
+        );
+
+        // Releasing the sounding note goes back to the one held before it
+        splitter.receive(MidiValue::NoteOff(67.into(), 0));
+        assert_eq!(splitter.note, 60);
+        splitter.receive(MidiValue::NoteOff(60.into(), 0));
+        assert_eq!(splitter.held, 0);
+        assert_eq!(splitter.note, 60);
+
+        // Once the notes played last are released, the highest note still held sounds
+        for note in 0..20u8 {
+            splitter.receive(MidiValue::NoteOn(note.into(), 100));
+        }
+        for note in 4..20u8 {
+            splitter.receive(MidiValue::NoteOff(note.into(), 0));
+        }
+        assert!(splitter.recent.is_empty());
+        assert_eq!(splitter.note, 3);
+
+        // Only slots whose controller changed are reset
+        splitter.select([Some(1), Some(7), None, Some(2)]);
+        assert_eq!(
+            splitter.ccs,
+            [Some((1, 1.)), Some((7, 0.)), None, Some((2, 0.))]
+        );
+    }
+}
@@ -3,14 +3,33 @@ use crate::Value;
 pub mod amplifier;
 pub mod file_player;
 pub mod midi_expander;
+pub mod midi_player;
+pub mod midi_recorder;
+pub mod midi_splitter;
+pub mod recorder;
 pub mod synth;
 
+#[cfg(test)]
+mod golden;
+
 use amplifier::Amplifier;
+use file_player::FilePlayer;
+use midi_expander::MidiExpander;
+use midi_player::MidiPlayer;
+use midi_recorder::MidiRecorder;
+use midi_splitter::MidiSplitter;
+use recorder::Recorder;
 use synth::Synth;
 
 crate::component_set! {
     pub mod octahack_component {
         Amplifier,
+        FilePlayer,
+        MidiExpander,
+        MidiPlayer,
+        MidiRecorder,
+        MidiSplitter,
+        Recorder,
         Synth
     }
 }
@@ -33,6 +52,26 @@ mod test {
         }
     }
 
+    crate::specs! {
+        mod midi {
+            Midi: crate::MidiValue
+        }
+    }
+
+    crate::specs! {
+        mod midi_and_audio {
+            Midi: crate::MidiValue,
+            Audio: crate::Value
+        }
+    }
+
+    crate::specs! {
+        mod two_mono {
+            Left: crate::Value,
+            Right: crate::Value
+        }
+    }
+
     use self::any::Specifier;
 
     #[test]
@@ -47,16 +86,18 @@ mod test {
         func.wire(
             WireSrc::func_input(Specifier::OneChannel),
             WireDst::component_input(amp, super::amplifier::input::Specifier::Input),
-        );
+        )
+        .unwrap();
         func.wire(
             WireSrc::component_output(amp, super::amplifier::output::Specifier::Output),
             WireDst::component_input(amp, super::amplifier::input::Specifier::Input),
-        );
+        )
+        .unwrap();
         func.set_param(amp, super::amplifier::params::Specifier::Amount, 1.);
 
         println!("{}", rack);
 
-        let mut streamer = crate::output::AudioStreamer::new_convert(
+        let mut streamer = crate::output::AudioStreamer::<_, _, _, _, i16>::new_convert(
             None,
             rack,
             rodio::source::SineWave::new(440),
@@ -69,4 +110,799 @@ mod test {
             Iterator::take(streamer, 100).collect::<Vec<_>>()
         );
     }
+
+    #[test]
+    fn midi_streaming() {
+        use super::midi_expander::{
+            input::Specifier::Midi, output::Specifier::Gate, params::Specifier::Voices,
+        };
+        use crate::{
+            output::{AudioStreamer, TimedMidi},
+            MidiValue,
+        };
+
+        let mut rack =
+            Rack::<super::OctahackComponent, midi::Specifier, midi_and_audio::Specifier>::new();
+        let mut main = rack.main_mut();
+        let expander = main.push_component(super::MidiExpander::new());
+        main.set_param(expander, Voices, 1.);
+        main.wire(
+            WireSrc::func_input(midi::Specifier::Midi),
+            WireDst::component_input(expander, Midi),
+        )
+        .unwrap();
+        main.wire(
+            WireSrc::component_output(expander, Gate),
+            WireDst::func_output(midi_and_audio::Specifier::Audio),
+        )
+        .unwrap();
+        main.wire(
+            WireSrc::func_input(midi::Specifier::Midi),
+            WireDst::func_output(midi_and_audio::Specifier::Midi),
+        )
+        .unwrap();
+
+        let mut streamer = AudioStreamer::<_, _, _, _, i16>::new_unchecked(
+            None,
+            rack,
+            rodio::source::Zero::<i16>::new(0, 44100),
+        );
+        let note_on = TimedMidi {
+            time: 2,
+            event: MidiValue::NoteOn(60.into(), 100),
+        };
+        streamer.send_midi(midi::Specifier::Midi, note_on);
+
+        assert_eq!(
+            Iterator::take(&mut streamer, 3).collect::<Vec<_>>(),
+            [0, 0, i16::max_value()]
+        );
+
+        let sent = streamer.take_midi();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].0 == midi_and_audio::Specifier::Midi);
+        assert_eq!(sent[0].1, note_on);
+        assert!(streamer.take_midi().is_empty());
+    }
+
+    #[test]
+    fn streaming_inputs() {
+        use crate::output::{AudioStreamer, TimedMidi};
+
+        // Every output reads the inputs of the same frame
+        let mut rack =
+            Rack::<super::OctahackComponent, two_mono::Specifier, two_mono::Specifier>::new();
+        let mut main = rack.main_mut();
+        main.wire(
+            WireSrc::func_input(two_mono::Specifier::Left),
+            WireDst::func_output(two_mono::Specifier::Right),
+        )
+        .unwrap();
+        main.wire(
+            WireSrc::func_input(two_mono::Specifier::Right),
+            WireDst::func_output(two_mono::Specifier::Left),
+        )
+        .unwrap();
+
+        let source = rodio::buffer::SamplesBuffer::new(2, 44100, vec![0.25f32, 0.5, -0.25, -0.5]);
+        let streamer = AudioStreamer::<_, _, _, _, f32>::new_unchecked(None, rack, source);
+        assert_eq!(streamer.collect::<Vec<_>>(), [0.5, 0.25, -0.5, -0.25]);
+
+        // The source has to have a channel for each input, whatever the rack outputs
+        let mono = || rodio::buffer::SamplesBuffer::new(1, 44100, vec![0.25f32]);
+        let stereo = || rodio::buffer::SamplesBuffer::new(2, 44100, vec![0.25f32, 0.5]);
+        let rack = || Rack::<super::OctahackComponent, any::Specifier, two_mono::Specifier>::new();
+        assert!(AudioStreamer::<_, _, _, _, f32>::new(None, rack(), mono()).is_some());
+        assert!(AudioStreamer::<_, _, _, _, f32>::new(None, rack(), stereo()).is_none());
+
+        // A rack with only MIDI outputs still runs, giving a silent sample per frame
+        let mut rack = Rack::<super::OctahackComponent, midi::Specifier, midi::Specifier>::new();
+        rack.main_mut()
+            .wire(
+                WireSrc::func_input(midi::Specifier::Midi),
+                WireDst::func_output(midi::Specifier::Midi),
+            )
+            .unwrap();
+
+        let mut streamer = AudioStreamer::<_, _, _, _, i16>::new_unchecked(
+            None,
+            rack,
+            rodio::source::Zero::<i16>::new(0, 44100),
+        );
+        let event = |time| TimedMidi {
+            time,
+            event: crate::MidiValue::NoteOn((60 + time as u8).into(), 100),
+        };
+        // Events that are sent out of order are still sent when they're due
+        streamer.send_midi(midi::Specifier::Midi, event(2));
+        streamer.send_midi(midi::Specifier::Midi, event(1));
+
+        assert_eq!(
+            Iterator::take(&mut streamer, 3).collect::<Vec<_>>(),
+            [0, 0, 0]
+        );
+        assert_eq!(
+            streamer
+                .take_midi()
+                .into_iter()
+                .map(|(_, event)| event)
+                .collect::<Vec<_>>(),
+            [event(1), event(2)]
+        );
+    }
+
+    #[test]
+    fn polyphonic_into_mono() {
+        use super::{midi_expander, synth};
+        use crate::{
+            output::{AudioStreamer, TimedMidi},
+            MidiValue,
+        };
+
+        let mut rack =
+            Rack::<super::OctahackComponent, midi::Specifier, two_mono::Specifier>::new();
+        let mut main = rack.main_mut();
+        let expander = main.push_component(super::MidiExpander::new());
+        let synth = main.push_component(super::Synth::new());
+        main.wire(
+            WireSrc::func_input(midi::Specifier::Midi),
+            WireDst::component_input(expander, midi_expander::input::Specifier::Midi),
+        )
+        .unwrap();
+        main.wire(
+            WireSrc::component_output(expander, midi_expander::output::Specifier::Freq),
+            WireDst::component_input(synth, synth::input::Specifier::Pitch),
+        )
+        .unwrap();
+        // Every voice of the synth is wired into a mono output, so only the first is heard
+        main.wire(
+            WireSrc::component_output(synth, synth::output::Specifier::Square),
+            WireDst::func_output(two_mono::Specifier::Left),
+        )
+        .unwrap();
+        main.wire(
+            WireSrc::component_output(synth, synth::output::Specifier::Saw),
+            WireDst::func_output(two_mono::Specifier::Right),
+        )
+        .unwrap();
+
+        let mut streamer = AudioStreamer::<_, _, _, _, f64>::new_unchecked(
+            None,
+            rack,
+            rodio::source::Zero::<i16>::new(0, 44100),
+        );
+        streamer.send_midi(
+            midi::Specifier::Midi,
+            TimedMidi {
+                time: 0,
+                event: MidiValue::NoteOn(69.into(), 100),
+            },
+        );
+
+        let samples = Iterator::take(&mut streamer, 16).collect::<Vec<_>>();
+        assert_eq!(samples.len(), 16);
+
+        // Each frame is one sample of each output, however many voices the synth has
+        for (i, frame) in samples.chunks(2).enumerate() {
+            assert_eq!(frame[0], 1.);
+            assert!(frame[1] > 0.8 && frame[1] < 1.);
+            if i > 0 {
+                assert!(frame[1] < samples[i * 2 - 1]);
+            }
+        }
+    }
+
+    #[test]
+    fn float_streaming() {
+        use super::amplifier::{
+            input::Specifier::Input, output::Specifier::Output, params::Specifier::Amount,
+        };
+        use crate::output::{AudioStreamer, ToI16};
+
+        let mut rack = Rack::<super::OctahackComponent, Specifier, Specifier>::new();
+        let mut main = rack.main_mut();
+        let amp = main.push_component(super::Amplifier);
+        main.set_param(amp, Amount, 0.5);
+        main.wire(
+            WireSrc::func_input(Specifier::OneChannel),
+            WireDst::component_input(amp, Input),
+        )
+        .unwrap();
+        main.wire(
+            WireSrc::component_output(amp, Output),
+            WireDst::func_output(Specifier::OneChannel),
+        )
+        .unwrap();
+
+        let source = || rodio::buffer::SamplesBuffer::new(1, 44100, vec![0.1f32, 3., -0.25]);
+        let mut streamer = AudioStreamer::<_, _, _, _, f64>::new_unchecked(None, rack, source());
+
+        // Neither the inputs nor the outputs are quantised or clipped
+        assert_eq!(
+            Iterator::take(&mut streamer, 3).collect::<Vec<_>>(),
+            [f64::from(0.1f32) * 0.5, 1.5, -0.125]
+        );
+
+        let mut rack = Rack::<super::OctahackComponent, Specifier, Specifier>::new();
+        let mut main = rack.main_mut();
+        main.wire(
+            WireSrc::func_input(Specifier::OneChannel),
+            WireDst::func_output(Specifier::OneChannel),
+        )
+        .unwrap();
+
+        let streamer = AudioStreamer::<_, _, _, _, f32>::new_unchecked(None, rack, source());
+        assert_eq!(
+            ToI16::new(streamer).collect::<Vec<_>>(),
+            [3277, i16::max_value(), -8192]
+        );
+
+        // Dithering moves each sample by less than two steps
+        let dithered = ToI16::dithered(vec![0.25f32; 100].into_iter()).collect::<Vec<_>>();
+        assert!(dithered.iter().all(|&val| (val - 8192).abs() < 2));
+        assert!(dithered.iter().any(|&val| val != 8192));
+    }
+
+    #[test]
+    fn wire_errors() {
+        use super::{
+            amplifier::{input::Specifier::Input, output::Specifier::Output},
+            file_player::params::Specifier::File,
+            synth::{output::Specifier as SynthOut, params::Specifier::Freq},
+        };
+        use crate::{ValueType, WireError};
+
+        let mut rack = Rack::<super::OctahackComponent, midi::Specifier, Specifier>::new();
+
+        let func_id = rack.new_func();
+        let other = rack.func_mut(func_id).push_component(super::Amplifier);
+
+        let mut func = rack.main_mut();
+        let fcall = func.push_function_call(func_id);
+        let amp = func.push_component(super::Amplifier);
+        let synth = func.push_component(super::Synth::new());
+        let player = func.push_component(super::FilePlayer::new());
+
+        assert_eq!(
+            func.wire(
+                WireSrc::func_input(midi::Specifier::Midi),
+                WireDst::component_input(amp, Input),
+            ),
+            Err(WireError::TypeMismatch {
+                src: ValueType::midi(),
+                dst: ValueType::mono(),
+            })
+        );
+        assert_eq!(
+            func.wire(
+                WireSrc::component_output(other, Output),
+                WireDst::component_input(amp, Input),
+            ),
+            Err(WireError::UnknownComponent(other))
+        );
+        assert_eq!(
+            func.wire(
+                WireSrc::component_output(synth, SynthOut::Sine),
+                WireDst::component_param(fcall, Freq, 1.),
+            ),
+            Err(WireError::UnknownSpecifier(0))
+        );
+        assert_eq!(
+            func.wire(
+                WireSrc::component_output(synth, SynthOut::Sine),
+                WireDst::component_param(player, File, 1.),
+            ),
+            Err(WireError::UnwirableParam(0))
+        );
+        assert_eq!(
+            func.wire(
+                WireSrc::component_output(synth, SynthOut::Sine),
+                WireDst::component_input(amp, Input),
+            ),
+            Ok(None)
+        );
+        assert_eq!(
+            func.wire(
+                WireSrc::component_output(synth, SynthOut::Saw),
+                WireDst::component_input(amp, Input),
+            ),
+            Ok(Some(WireSrc::component_output(synth, SynthOut::Sine)))
+        );
+    }
+
+    #[test]
+    fn remove() {
+        use super::{
+            amplifier::{input::Specifier::Input, output::Specifier::Output},
+            synth::output::Specifier as SynthOut,
+        };
+
+        let mut rack = Rack::<super::OctahackComponent, Specifier, Specifier>::new();
+
+        let func_id = rack.new_func();
+        let mut func = rack.func_mut(func_id);
+        let synth = func.push_component(super::Synth::new());
+        func.wire(
+            WireSrc::component_output(synth, SynthOut::Sine),
+            WireDst::func_output(Specifier::OneChannel),
+        )
+        .unwrap();
+
+        let mut main = rack.main_mut();
+        let fcall = main.push_function_call(func_id);
+        let amp = main.push_component(super::Amplifier);
+        main.wire(
+            WireSrc::component_output(fcall, Specifier::OneChannel),
+            WireDst::component_input(amp, Input),
+        )
+        .unwrap();
+        main.wire(
+            WireSrc::component_output(amp, Output),
+            WireDst::func_output(Specifier::OneChannel),
+        )
+        .unwrap();
+
+        // The synth in the definition, the synth in the call and the amplifier
+        assert_eq!((&rack.state_storage).into_iter().len(), 3);
+
+        assert!(rack.func_mut(func_id).remove_component(synth).is_some());
+        assert_eq!((&rack.state_storage).into_iter().len(), 1);
+
+        let mut main = rack.main_mut();
+        assert!(main.remove_component(fcall).is_none());
+        assert_eq!(main.remove_function_call(fcall), Some(func_id));
+        assert_eq!(main.unwire(WireDst::component_input(amp, Input)), Ok(None));
+        assert!(main.remove_component(amp).is_some());
+        assert_eq!(
+            main.unwire(WireDst::func_output(Specifier::OneChannel)),
+            Ok(None)
+        );
+
+        assert_eq!((&rack.state_storage).into_iter().len(), 0);
+        assert_eq!((&rack.meta_storage).into_iter().len(), 0);
+    }
+
+    #[test]
+    fn insert_and_replace() {
+        use super::{
+            amplifier::{
+                input::Specifier::Input, output::Specifier::Output, params::Specifier::Amount,
+            },
+            synth::{output::Specifier::Sine, params::Specifier::Freq},
+        };
+
+        let mut rack = Rack::<super::OctahackComponent, Specifier, Specifier>::new();
+        let mut main = rack.main_mut();
+
+        let synth = main.push_component(super::Synth::new());
+        let amp = main
+            .insert_component_before(synth, super::Amplifier)
+            .unwrap();
+        let other = main
+            .insert_component_after(amp, super::Synth::new())
+            .unwrap();
+        assert_eq!(main.def().statements, [amp, other, synth]);
+
+        main.wire(
+            WireSrc::component_output(synth, Sine),
+            WireDst::component_input(amp, Input),
+        )
+        .unwrap();
+        main.wire(
+            WireSrc::component_output(amp, Output),
+            WireDst::func_output(Specifier::OneChannel),
+        )
+        .unwrap();
+        main.wire(
+            WireSrc::component_output(other, Sine),
+            WireDst::component_param(synth, Freq, 1.),
+        )
+        .unwrap();
+
+        // `MidiExpander` only has a MIDI input, but its first output has the same type as the
+        // amplifier's output
+        assert_eq!(
+            main.replace_component(amp, super::MidiExpander::new()),
+            Some(vec![WireDst::component_input(amp, Input)])
+        );
+        assert_eq!(
+            main.unwire(WireDst::func_output(Specifier::OneChannel)),
+            Ok(Some(WireSrc::component_output(amp, Output)))
+        );
+
+        // `Amount` has the same type as `Freq`, so the wire and the natural value are kept
+        main.set_param(synth, Freq, 0.5);
+        assert_eq!(
+            main.replace_component(synth, super::Amplifier),
+            Some(vec![])
+        );
+        assert_eq!(
+            main.unwire(WireDst::component_param(synth, Amount, 1.)),
+            Ok(Some(WireSrc::component_output(other, Sine)))
+        );
+        assert_eq!(*main.param::<_, Value>(synth, Amount).as_mut(), 0.5);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        use super::{
+            amplifier::{input::Specifier::Input, output::Specifier::Output},
+            synth::{output::Specifier::Sine, params::Specifier::Freq},
+        };
+        use crate::{
+            parse::ParseErrorKind,
+            rack::{AsParam, Param},
+        };
+
+        let mut rack = Rack::<super::OctahackComponent, Specifier, Specifier>::new();
+
+        let func_id = rack.new_func();
+        let mut func = rack.func_mut(func_id);
+        let amp = func.push_component(super::Amplifier);
+        func.wire(
+            WireSrc::func_input(crate::AnyInputSpec(0)),
+            WireDst::component_input(amp, Input),
+        )
+        .unwrap();
+        func.wire(
+            WireSrc::component_output(amp, Output),
+            WireDst::func_output(crate::AnyOutputSpec(0)),
+        )
+        .unwrap();
+        func.set_param(amp, super::amplifier::params::Specifier::Amount, -0.25);
+
+        let mut main = rack.main_mut();
+        let cv_modulator = main.push_component(super::Synth::new());
+        let modulator = main.push_component(super::Synth::new());
+        let carrier = main.push_component(super::Synth::new());
+        let fcall = main.push_function_call(func_id);
+        main.wire(
+            WireSrc::component_output(carrier, Sine),
+            WireDst::component_input(fcall, crate::AnyInputSpec(0)),
+        )
+        .unwrap();
+        main.wire(
+            WireSrc::component_output(fcall, crate::AnyOutputSpec(0)),
+            WireDst::func_output(Specifier::OneChannel),
+        )
+        .unwrap();
+        main.set_param(modulator, Freq, 0.1);
+        {
+            let mut carrier_freq = main.param::<_, Value>(carrier, Freq).as_param().unwrap();
+            carrier_freq.wire(WireSrc::component_output(modulator, Sine), 1.);
+            carrier_freq
+                .cv()
+                .unwrap()
+                .wire(WireSrc::component_output(cv_modulator, Sine), 4.);
+        }
+
+        let (a, b) = (rack.new_scene(), rack.new_scene());
+        assert!(rack.set_scene_param(a, carrier, Freq, 220.));
+        assert!(rack.set_scene_param(a, modulator, Freq, 0.2));
+        assert!(rack.set_scene_param(b, carrier, Freq, 880.));
+        rack.new_scene();
+        assert!(rack.set_scene_a(Some(a)));
+        assert!(rack.set_scene_b(Some(b)));
+        rack.set_crossfader(0.25);
+        rack.wire_crossfader(Some(Specifier::OneChannel)).unwrap();
+
+        let text = rack.to_string();
+        let parsed = text
+            .parse::<Rack<super::OctahackComponent, Specifier, Specifier>>()
+            .unwrap();
+
+        assert_eq!(parsed.to_string(), text);
+        assert_eq!((parsed.scene_a(), parsed.scene_b()), (Some(a), Some(b)));
+        assert_eq!(parsed.scene_param(a, modulator, Freq), Some(0.2));
+        assert_eq!(parsed.crossfader(), 0.25);
+        assert!(parsed.crossfader_input() == Some(Specifier::OneChannel));
+
+        // Racks written before scenes were saved have no crossfader
+        let without_scenes = &text[..text.find("\nscene ").unwrap() + 1];
+        let parsed = without_scenes
+            .parse::<Rack<super::OctahackComponent, Specifier, Specifier>>()
+            .unwrap();
+        assert_eq!(parsed.scene_a(), None);
+        assert!(parsed.crossfader_input().is_none());
+
+        let err = text
+            .replace("Amplifier", "Amplifire")
+            .parse::<Rack<super::OctahackComponent, Specifier, Specifier>>()
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::UnknownComponent("Amplifire".to_string())
+        );
+    }
+
+    #[test]
+    fn export_and_import_func() {
+        use super::{
+            amplifier::{input::Specifier::Input, output::Specifier::Output},
+            synth::output::Specifier::Sine,
+        };
+        use crate::AnyOutputSpec;
+
+        let mut rack = Rack::<super::OctahackComponent, Specifier, Specifier>::new();
+
+        let inner = rack.new_func();
+        let mut func = rack.func_mut(inner);
+        let synth = func.push_component(super::Synth::new());
+        func.wire(
+            WireSrc::component_output(synth, Sine),
+            WireDst::func_output(AnyOutputSpec(0)),
+        )
+        .unwrap();
+
+        let outer = rack.new_func();
+        let mut func = rack.func_mut(outer);
+        let fcall = func.push_function_call(inner);
+        let amp = func.push_component(super::Amplifier);
+        func.wire(
+            WireSrc::component_output(fcall, AnyOutputSpec(0)),
+            WireDst::component_input(amp, Input),
+        )
+        .unwrap();
+        func.wire(
+            WireSrc::component_output(amp, Output),
+            WireDst::func_output(AnyOutputSpec(0)),
+        )
+        .unwrap();
+        func.set_param(amp, super::amplifier::params::Specifier::Amount, 0.5);
+        let file = rack.files_mut().add_buffer(None);
+        let player = rack
+            .func_mut(outer)
+            .push_component(super::FilePlayer::new());
+        rack.func_mut(outer).set_param(
+            player,
+            super::file_player::params::Specifier::File,
+            Some(file),
+        );
+
+        let text = rack.export_func(outer).to_string();
+
+        let mut other = Rack::<super::OctahackComponent, Specifier, Specifier>::new();
+        other.new_func();
+        let imported = other.import_func(&text).unwrap();
+        let imported_again = other.import_func(&text).unwrap();
+        assert_ne!(imported, imported_again);
+
+        // Both imports and their callees get new IDs, so nothing is shared between them
+        assert_eq!((&other.funcs).into_iter().len(), 5);
+        let reexported = other.export_func(imported).to_string();
+        assert_eq!(reexported.lines().count(), text.lines().count());
+        // File params refer to the files of the rack they were exported from, so they're dropped
+        assert!(text.contains("File(0)"));
+        assert!(!reexported.contains("File(0)"));
+        assert_ne!(reexported, other.export_func(imported_again).to_string());
+
+        // A failed import leaves the rack unchanged
+        assert!(other
+            .import_func(&text.replace("Amplifier", "Amplifire"))
+            .is_err());
+        assert_eq!((&other.funcs).into_iter().len(), 5);
+    }
+
+    #[test]
+    fn funcs_written_callees_first() {
+        let mut rack = Rack::<super::OctahackComponent, Specifier, Specifier>::new();
+
+        let uncalled = [rack.new_func(), rack.new_func()];
+        let inner = rack.new_func();
+        let outer = rack.new_func();
+        rack.func_mut(outer).push_function_call(inner);
+        rack.main_mut().push_function_call(outer);
+
+        let text = rack.to_string();
+        let position = |id| text.find(&format!("def {} ", id)).unwrap();
+
+        // Functions that main calls come first, and any others are written in order of their IDs
+        let mut expected = vec![inner, outer];
+        let mut rest = uncalled.to_vec();
+        rest.sort_by_key(|id| id.0);
+        expected.extend(rest);
+
+        let mut written = expected.clone();
+        written.sort_by_key(|&id| position(id));
+        assert_eq!(written, expected);
+        assert_eq!(rack.to_string(), text);
+    }
+
+    #[test]
+    fn func_params() {
+        use super::{
+            amplifier::params::Specifier::Amount,
+            synth::{output::Specifier::Sine, params::Specifier::Freq},
+        };
+        use crate::{AnyOutputSpec, AnyParamSpec, RefRuntimeSpecifier};
+
+        let mut rack = Rack::<super::OctahackComponent, Specifier, Specifier>::new();
+
+        let func_id = rack.new_func();
+        let mut func = rack.func_mut(func_id);
+        let synth = func.push_component(super::Synth::new());
+        func.wire(
+            WireSrc::component_output(synth, Sine),
+            WireDst::func_output(AnyOutputSpec(0)),
+        )
+        .unwrap();
+
+        let mut main = rack.main_mut();
+        let lfo = main.push_component(super::Synth::new());
+        let early_call = main.push_function_call(func_id);
+        assert!(main.add_param("Pitch", vec![]).is_none());
+
+        let mut func = rack.func_mut(func_id);
+        assert!(func
+            .add_param("Not a name", vec![(synth, AnyParamSpec(Freq.id()))])
+            .is_none());
+        assert!(func
+            .add_param("Pitch", vec![(lfo, AnyParamSpec(Freq.id()))])
+            .is_none());
+        func.set_param(synth, Freq, 0.5);
+        assert_eq!(
+            func.add_param("Pitch", vec![(synth, AnyParamSpec(Freq.id()))])
+                .map(|spec| spec.0),
+            Some(0)
+        );
+        assert!(func.add_param("Pitch", vec![]).is_none());
+        // The call that already existed keeps playing at the synth's frequency
+        assert_eq!(rack.meta_storage[early_call.0].param_value(0), Some(0.5));
+
+        let mut main = rack.main_mut();
+        let late_call = main.push_function_call(func_id);
+        main.set_param(early_call, AnyParamSpec(0), 0.25);
+        assert_eq!(
+            main.wire(
+                WireSrc::component_output(lfo, Sine),
+                WireDst::component_param(late_call, AnyParamSpec(0), 0.5),
+            ),
+            Ok(None)
+        );
+        main.wire(
+            WireSrc::component_output(early_call, AnyOutputSpec(0)),
+            WireDst::func_output(Specifier::OneChannel),
+        )
+        .unwrap();
+
+        let text = rack.to_string();
+        let parsed = text
+            .parse::<Rack<super::OctahackComponent, Specifier, Specifier>>()
+            .unwrap();
+        assert_eq!(parsed.to_string(), text);
+
+        // Removing the LFO disconnects the wire into the call's param
+        assert!(rack.main_mut().remove_component(lfo).is_some());
+        assert_eq!(
+            rack.main_mut()
+                .unwire(WireDst::component_param(late_call, AnyParamSpec(0), 0.5)),
+            Ok(None)
+        );
+
+        // `Amount` has the same type as `Freq`, so the function param still forwards to it
+        let mut func = rack.func_mut(func_id);
+        func.replace_component(synth, super::Amplifier).unwrap();
+        assert_eq!(func.def().params[0].targets().len(), 1);
+        func.remove_component(synth).unwrap();
+        assert_eq!(func.def().params[0].targets().len(), 0);
+        assert!(func
+            .add_param("Volume", vec![(synth, AnyParamSpec(Amount.id()))])
+            .is_none());
+    }
+
+    #[test]
+    fn linked_params() {
+        use super::{
+            amplifier::{output::Specifier::Output, params::Specifier::Amount},
+            synth::params::Specifier::Freq,
+        };
+
+        let mut rack = Rack::<super::OctahackComponent, Specifier, Specifier>::new();
+
+        let func_id = rack.new_func();
+        let other = rack.func_mut(func_id).push_component(super::Synth::new());
+
+        let mut main = rack.main_mut();
+        let first = main.push_component(super::Synth::new());
+        let second = main.push_component(super::Synth::new());
+        let amp = main.push_component(super::Amplifier);
+        main.set_param(first, Freq, 0.1);
+
+        assert!(!main.link_params::<Value, _, _>((first, Freq), (other, Freq)));
+        assert!(!main.link_params::<crate::MidiValue, _, _>((first, Freq), (second, Freq)));
+        assert!(main.link_params::<Value, _, _>((first, Freq), (second, Freq)));
+        assert!(main.link_params::<Value, _, _>((amp, Amount), (second, Freq)));
+        assert_eq!(main.def().links.len(), 1);
+        assert_eq!(*main.param::<_, Value>(first, Freq).as_mut(), 0.);
+
+        main.set_param(second, Freq, 0.5);
+        assert_eq!(*main.param::<_, Value>(first, Freq).as_mut(), 0.5);
+        assert_eq!(*main.param::<_, Value>(amp, Amount).as_mut(), 0.5);
+
+        main.wire(
+            WireSrc::component_output(amp, Output),
+            WireDst::component_param(first, Freq, 1.),
+        )
+        .unwrap();
+
+        let text = rack.to_string();
+        let parsed = text
+            .parse::<Rack<super::OctahackComponent, Specifier, Specifier>>()
+            .unwrap();
+        assert_eq!(parsed.to_string(), text);
+
+        let mut main = rack.main_mut();
+        assert_eq!(
+            main.unwire(WireDst::component_param(second, Freq, 1.)),
+            Ok(Some(WireSrc::component_output(amp, Output)))
+        );
+        // Unwiring one param unwires everything linked to it
+        assert_eq!(
+            main.unwire(WireDst::component_param(first, Freq, 1.)),
+            Ok(None)
+        );
+
+        assert!(main.unlink_param(amp, Amount));
+        assert!(main.remove_component(second).is_some());
+        assert!(main.def().links.is_empty());
+    }
+
+    #[test]
+    fn undo_redo() {
+        use super::synth::{output::Specifier::Sine, params::Specifier::Freq};
+
+        let mut rack = Rack::<super::OctahackComponent, Specifier, Specifier>::new();
+        assert!(!rack.undo());
+
+        let func_id = rack.new_func();
+        rack.func_mut(func_id).push_component(super::Synth::new());
+        rack.clear_history();
+
+        let mut texts = vec![rack.to_string()];
+        let carrier = rack.main_mut().push_component(super::Synth::new());
+        texts.push(rack.to_string());
+        let modulator = rack.main_mut().push_component(super::Synth::new());
+        texts.push(rack.to_string());
+        rack.main_mut().push_function_call(func_id);
+        texts.push(rack.to_string());
+        rack.main_mut().set_param(carrier, Freq, 0.25);
+        texts.push(rack.to_string());
+        {
+            let mut main = rack.main_mut();
+            let mut freq = main.param::<_, Value>(carrier, Freq).as_param().unwrap();
+            freq.wire(WireSrc::component_output(modulator, Sine), 1.);
+            freq.cv()
+                .unwrap()
+                .wire(WireSrc::component_output(modulator, Sine), 4.);
+        }
+        texts.push(rack.to_string());
+        rack.main_mut().remove_component(modulator).unwrap();
+        let last = rack.to_string();
+
+        // Reading a param isn't an edit
+        assert_eq!(
+            *rack.main_mut().param::<_, Value>(carrier, Freq).as_mut(),
+            0.25
+        );
+
+        for text in texts.iter().rev() {
+            assert!(rack.undo());
+            assert_eq!(&rack.to_string(), text);
+        }
+        assert!(!rack.undo());
+
+        while rack.redo() {}
+        assert_eq!(rack.to_string(), last);
+
+        rack.undo();
+        rack.undo();
+        rack.main_mut().set_param(carrier, Freq, 0.5);
+        assert!(!rack.can_redo());
+
+        rack.set_history_limit(1);
+        assert!(rack.undo());
+        assert!(!rack.undo());
+        assert_eq!(&rack.to_string(), &texts[4]);
+    }
 }
@@ -0,0 +1,228 @@
+use crate::{
+    context::{DisplayFile, FileId},
+    files::{AudioFile, FrameSamples},
+    Component, Context, DisplayParam, GetOutput, UiElement, Value,
+};
+use std::fmt;
+
+crate::specs! {
+    pub mod params {
+        Buffer: Option<crate::context::FileId<crate::Value>>
+    }
+
+    pub mod input {
+        Input: crate::Value = crate::ValueType::continuous(),
+        Record: crate::Value,
+        Overdub: crate::Value,
+        Stop: crate::Value
+    }
+
+    pub mod output {
+        Output: crate::Value = crate::ValueType::continuous()
+    }
+}
+
+impl DisplayParam for params::Buffer {
+    type Display = impl fmt::Display;
+
+    fn display(val: Option<FileId<Value>>) -> Self::Display {
+        DisplayFile(val)
+    }
+}
+
+impl Default for params::Params {
+    fn default() -> Self {
+        Self { Buffer: None }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Stopped,
+    /// Replacing the buffer with the input.
+    Recording,
+    /// Mixing the input into the buffer, going back to the start of the buffer when we reach the
+    /// end of it.
+    Overdubbing,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::Stopped
+    }
+}
+
+/// Records its input into the file chosen by `Buffer`, which should be made with
+/// `Files::add_buffer`. Opening the `Record` gate starts a new recording, opening `Overdub`
+/// plays the input over what's already there and opening `Stop` stops either. Recording stops
+/// by itself when the buffer is full. The input is passed through to the output unchanged so
+/// that it can still be heard while it's recorded.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Recorder {
+    state: State,
+    /// The frame being overdubbed.
+    position: usize,
+    /// Whether the `Record`, `Overdub` and `Stop` gates were open last tick, so that holding a
+    /// gate open only triggers it once.
+    gates: [bool; 3],
+}
+
+impl UiElement for Recorder {
+    const NAME: &'static str = "Recorder";
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Switch to `trigger` if a gate was opened, then record one frame of input.
+    fn record(
+        &mut self,
+        buffer: &mut AudioFile,
+        trigger: Option<State>,
+        frame: &[Value],
+        sample_rate: u32,
+    ) {
+        match trigger {
+            Some(State::Recording) => buffer.clear(sample_rate, frame.len().max(1)),
+            Some(State::Overdubbing) => self.position = 0,
+            _ => {}
+        }
+
+        if let Some(state) = trigger {
+            self.state = state;
+        }
+
+        // Overdubbing onto nothing is the same as recording
+        if self.state == State::Overdubbing && buffer.frames() == 0 {
+            buffer.clear(sample_rate, frame.len().max(1));
+            self.state = State::Recording;
+        }
+
+        match self.state {
+            State::Stopped => {}
+            State::Recording => {
+                if !buffer.push_frame(frame) {
+                    self.state = State::Stopped;
+                }
+            }
+            State::Overdubbing => {
+                buffer.overdub_frame(self.position, frame);
+                self.position = (self.position + 1) % buffer.frames();
+            }
+        }
+    }
+}
+
+impl Component for Recorder {
+    type InputSpecifier = input::Specifier;
+    type OutputSpecifier = output::Specifier;
+    type ParamSpecifier = params::Specifier;
+
+    fn update<Ctx>(&self, ctx: &Ctx) -> Self
+    where
+        Ctx: Context<Self>,
+    {
+        let mut out = *self;
+        let gates = [
+            ctx.input::<input::Record>()
+                .map_or(false, |mut gate| gate.any(|val| val >= 0.5)),
+            ctx.input::<input::Overdub>()
+                .map_or(false, |mut gate| gate.any(|val| val >= 0.5)),
+            ctx.input::<input::Stop>()
+                .map_or(false, |mut gate| gate.any(|val| val >= 0.5)),
+        ];
+        let opened = |i: usize| gates[i] && !self.gates[i];
+        // If more than one gate opens at once then stopping wins, so that a stray trigger can't
+        // wipe out a recording
+        let trigger = if opened(2) {
+            Some(State::Stopped)
+        } else if opened(0) {
+            Some(State::Recording)
+        } else if opened(1) {
+            Some(State::Overdubbing)
+        } else {
+            None
+        };
+
+        out.gates = gates;
+
+        let id = match ctx.param::<params::Buffer>() {
+            Some(id) => id,
+            None => {
+                out.state = State::Stopped;
+                return out;
+            }
+        };
+
+        // Only lock the buffer when we're going to write to it, so that a stopped recorder never
+        // makes anything reading the buffer wait
+        if trigger.unwrap_or(self.state) == State::Stopped {
+            out.state = State::Stopped;
+            return out;
+        }
+
+        if let Some(mut buffer) = ctx.files().audio_mut(id) {
+            let frame = ctx
+                .input::<input::Input>()
+                .map(|input| input.collect::<FrameSamples>())
+                .unwrap_or_default();
+
+            out.record(&mut buffer, trigger, frame.as_slice(), ctx.sample_rate());
+        } else {
+            out.state = State::Stopped;
+        }
+
+        out
+    }
+}
+
+impl GetOutput<output::Output> for Recorder {
+    type Iter = impl ExactSizeIterator<Item = Value> + Send;
+
+    fn output<Ctx>(&self, ctx: &Ctx) -> Self::Iter
+    where
+        Ctx: Context<Self>,
+    {
+        ctx.input::<input::Input>()
+            .map(|input| input.collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Recorder, State};
+    use crate::files::AudioFile;
+
+    #[test]
+    fn record_and_overdub() {
+        let mut recorder = Recorder::new();
+        let mut buffer = AudioFile::buffer(Some(2));
+        let frames = |buffer: &AudioFile| {
+            (0..buffer.frames())
+                .map(|i| buffer.frame(i).to_vec())
+                .collect::<Vec<_>>()
+        };
+
+        // Overdubbing an empty buffer records into it, and recording stops when it's full
+        recorder.record(&mut buffer, Some(State::Overdubbing), &[0.5, 0.25], 4);
+        recorder.record(&mut buffer, None, &[0.25, 0.5], 4);
+        recorder.record(&mut buffer, None, &[1., 1.], 4);
+        assert_eq!(recorder.state, State::Stopped);
+        assert_eq!(frames(&buffer), [vec![0.5, 0.25], vec![0.25, 0.5]]);
+
+        // Overdubbing goes round the buffer
+        recorder.record(&mut buffer, Some(State::Overdubbing), &[0.25, 0.25], 4);
+        recorder.record(&mut buffer, None, &[0.25], 4);
+        recorder.record(&mut buffer, None, &[0.25, 0.25], 4);
+        assert_eq!(frames(&buffer), [vec![1., 0.75], vec![0.5, 0.5]]);
+
+        recorder.record(&mut buffer, Some(State::Recording), &[0.5], 4);
+        recorder.record(&mut buffer, Some(State::Stopped), &[0.5], 4);
+        assert_eq!(frames(&buffer), [vec![0.5]]);
+        assert_eq!(buffer.channels(), 1);
+    }
+}
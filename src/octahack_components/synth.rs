@@ -1,15 +1,25 @@
-use crate::{Component, Context, DisplayParam, GetOutput, UiElement, Value};
-use std::fmt;
+use super::midi_expander::MAX_VOICES;
+use crate::{
+    block::BlockOutputs, context::BlockContext, Component, Context, DisplayParam, GetOutput,
+    UiElement, Value,
+};
+use staticvec::StaticVec;
+use std::{fmt, iter};
 
 crate::specs! {
     pub mod params {
         Freq: crate::Value
     }
 
+    // Each of these has one channel per voice
+    pub mod input {
+        Pitch: crate::Value = crate::ValueType::continuous()
+    }
+
     pub mod output {
-        Sine: crate::Value,
-        Saw: crate::Value,
-        Square: crate::Value
+        Sine: crate::Value = crate::ValueType::continuous(),
+        Saw: crate::Value = crate::ValueType::continuous(),
+        Square: crate::Value = crate::ValueType::continuous()
     }
 }
 
@@ -39,9 +49,22 @@ impl Default for params::Params {
     }
 }
 
-#[derive(Clone, Debug, Default)]
+/// An oscillator. Each channel of the `Pitch` input, in volts per octave, is added to the
+/// frequency to play a separate voice, so wiring a polyphonic source such as `MidiExpander` into
+/// it gives one output channel per voice. If `Pitch` isn't wired then a single voice plays at
+/// the frequency. Channels of `Pitch` past the first `MAX_VOICES` are ignored.
+#[derive(Clone, Debug)]
 pub struct Synth {
-    tick: f64,
+    /// How far through its cycle each voice is, from 0 to 1
+    ticks: StaticVec<f64, { MAX_VOICES }>,
+}
+
+impl Default for Synth {
+    fn default() -> Self {
+        Synth {
+            ticks: iter::once(0.).collect(),
+        }
+    }
 }
 
 impl UiElement for Synth {
@@ -52,6 +75,61 @@ impl Synth {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Change the number of voices. A voice that was just added starts at the beginning of its
+    /// cycle.
+    fn set_voice_count(&mut self, count: usize) {
+        self.ticks.truncate(count);
+
+        let added = count.saturating_sub(self.ticks.len());
+        self.ticks.extend(iter::repeat(0.).take(added));
+    }
+
+    /// Every voice of one waveform, as one channel each.
+    fn voices(&self, wave: fn(f64) -> Value) -> impl ExactSizeIterator<Item = Value> + Send {
+        self.ticks.clone().into_iter().map(wave)
+    }
+
+    /// Move each voice on by one sample.
+    fn advance<Ctx>(&mut self, ctx: &Ctx)
+    where
+        Ctx: Context<Self>,
+    {
+        let freq = ctx.param::<params::Freq>();
+        let step = |tick: &mut f64, pitch: Value| {
+            *tick = (*tick + volt_to_octave(freq + pitch) / ctx.sample_rate() as f64) % 1.;
+        };
+
+        match ctx.input::<input::Pitch>() {
+            Some(pitches) => {
+                self.set_voice_count(pitches.len());
+
+                for (tick, pitch) in self.ticks.iter_mut().zip(pitches) {
+                    step(tick, pitch);
+                }
+            }
+            None => {
+                self.set_voice_count(1);
+                step(&mut self.ticks[0], 0.);
+            }
+        }
+    }
+}
+
+fn sine(tick: f64) -> Value {
+    (2. * std::f64::consts::PI * tick).sin()
+}
+
+fn saw(tick: f64) -> Value {
+    1. - 2. * tick
+}
+
+fn square(tick: f64) -> Value {
+    if tick < 0.5 {
+        1.
+    } else {
+        -1.
+    }
 }
 
 fn volt_to_octave(volts: impl Into<Value>) -> f64 {
@@ -66,8 +144,13 @@ pub fn freq(freq: impl Into<Value>) -> Value {
     (freq.into() / 440.).log2()
 }
 
+// This converts a MIDI note number to the same virtual "volts", where note 69 is A440
+pub fn note(note: u8) -> Value {
+    (Value::from(note) - 69.) / 12.
+}
+
 impl Component for Synth {
-    type InputSpecifier = !;
+    type InputSpecifier = input::Specifier;
     type OutputSpecifier = output::Specifier;
     type ParamSpecifier = params::Specifier;
 
@@ -75,11 +158,32 @@ impl Component for Synth {
     where
         Ctx: Context<Self>,
     {
-        let freq = volt_to_octave(ctx.param::<params::Freq>());
+        let mut new = self.clone();
+        new.advance(ctx);
+        new
+    }
 
-        Synth {
-            tick: ((self.tick + freq / ctx.sample_rate() as f64) % 1.),
+    fn process_block<Ctx>(&self, ctx: &Ctx, outputs: &mut BlockOutputs) -> Option<Self>
+    where
+        Ctx: BlockContext<Self>,
+    {
+        let mut synth = self.clone();
+
+        for i in 0..ctx.frames() {
+            synth.advance(&ctx.frame(i));
+
+            outputs.push_values(
+                output::Specifier::Sine,
+                synth.ticks.iter().map(|&t| sine(t)),
+            );
+            outputs.push_values(output::Specifier::Saw, synth.ticks.iter().map(|&t| saw(t)));
+            outputs.push_values(
+                output::Specifier::Square,
+                synth.ticks.iter().map(|&t| square(t)),
+            );
         }
+
+        Some(synth)
     }
 }
 
@@ -90,9 +194,7 @@ impl GetOutput<output::Sine> for Synth {
     where
         Ctx: Context<Self>,
     {
-        use std::{f64, iter};
-
-        iter::once((2. * f64::consts::PI * self.tick).sin())
+        self.voices(sine)
     }
 }
 
@@ -103,9 +205,7 @@ impl GetOutput<output::Saw> for Synth {
     where
         Ctx: Context<Self>,
     {
-        use std::iter;
-
-        iter::once(1. - 2. * self.tick)
+        self.voices(saw)
     }
 }
 
@@ -116,9 +216,7 @@ impl GetOutput<output::Square> for Synth {
     where
         Ctx: Context<Self>,
     {
-        use std::iter;
-
-        iter::once(if self.tick < 0.5 { 1. } else { -1. })
+        self.voices(square)
     }
 }
 
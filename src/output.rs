@@ -1,13 +1,17 @@
 use crate::{
-    components::{EnumerateValues, PossiblyIter, ValueIterImplHelper},
-    context::{ContextMeta, GetFunctionParam},
+    components::{AnyIter, EnumerateValues, PossiblyIter},
+    context::{ContextMeta, GetFunctionParam, TimeSignature, Transport},
     params::HasStorage,
     rack::InternalWire,
-    AnyComponent, Rack, RuntimeSpecifier, SpecId, Value, ValueKind,
+    AnyComponent, MidiValue, Rack, RuntimeSpecifier, SpecId, Value, ValueKind,
 };
 use fixed::types::I1F15;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
 use rodio::Source;
-use std::{borrow::Cow, marker::PhantomData};
+use std::{iter, marker::PhantomData, slice};
+
+pub mod device;
+pub mod render;
 
 trait Sources<'a> {
     type Iter;
@@ -36,67 +40,211 @@ where
     out
 }
 
+/// A type of sample that `AudioStreamer` can read its inputs as and write its outputs as.
+pub trait StreamSample: Copy + Send + 'static {
+    /// Convert from a value of the rack, where -1 to 1 is full scale.
+    fn from_value(val: Value) -> Self;
+    fn to_value(self) -> Value;
+}
+
+impl StreamSample for f32 {
+    #[inline]
+    fn from_value(val: Value) -> Self {
+        val as f32
+    }
+
+    #[inline]
+    fn to_value(self) -> Value {
+        self.into()
+    }
+}
+
+impl StreamSample for f64 {
+    #[inline]
+    fn from_value(val: Value) -> Self {
+        val
+    }
+
+    #[inline]
+    fn to_value(self) -> Value {
+        self
+    }
+}
+
+/// Values outside of -1 to 1 are clipped. To dither the conversion, stream floats and convert
+/// them with `ToI16::dithered` instead.
+impl StreamSample for i16 {
+    #[inline]
+    fn from_value(val: Value) -> Self {
+        I1F15::saturating_from_num(val).to_bits()
+    }
+
+    #[inline]
+    fn to_value(self) -> Value {
+        I1F15::from_bits(self).into()
+    }
+}
+
+/// Converts samples to 16-bit integers, as the last stage before a device or file that needs
+/// them so that everything before it keeps its full resolution. Samples outside of -1 to 1 are
+/// clipped.
+pub struct ToI16<I> {
+    samples: I,
+    /// The source of noise for dithering, or `None` to round each sample to the nearest step.
+    dither: Option<SmallRng>,
+}
+
+impl<I> ToI16<I> {
+    pub fn new(samples: I) -> Self {
+        ToI16 {
+            samples,
+            dither: None,
+        }
+    }
+
+    /// Add triangular noise of up to one step to each sample before it's rounded, which turns
+    /// the distortion that rounding adds to quiet sounds into a constant, very quiet hiss.
+    pub fn dithered(samples: I) -> Self {
+        ToI16 {
+            samples,
+            dither: Some(SmallRng::from_seed([0; 16])),
+        }
+    }
+}
+
+impl<I> Iterator for ToI16<I>
+where
+    I: Iterator,
+    I::Item: StreamSample,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let val = self.samples.next()?.to_value() * 32768.;
+        let noise = match &mut self.dither {
+            Some(rng) => rng.gen::<Value>() - rng.gen::<Value>(),
+            None => 0.,
+        };
+
+        Some((val + noise).round().max(-32768.).min(32767.) as i16)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.samples.size_hint()
+    }
+}
+
+impl<I> Source for ToI16<I>
+where
+    I: Source,
+    I::Item: StreamSample + rodio::Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.samples.current_frame_len()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.samples.sample_rate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.samples.channels()
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        self.samples.total_duration()
+    }
+}
+
+/// One frame of an output with exactly `len` channels, for when the output has to have the
+/// width that it was declared with. Missing channels are silent and any channels past `len` are
+/// dropped, since outputs such as a polyphonic `Synth` can have any number of channels.
 struct OrZero<I> {
     iter: Option<I>,
-    min_len: usize,
+    len: usize,
 }
 
 impl<I> Iterator for OrZero<I>
 where
-    I: std::iter::ExactSizeIterator<Item = i16>,
+    I: std::iter::ExactSizeIterator,
+    I::Item: StreamSample,
 {
-    type Item = i16;
+    type Item = I::Item;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(val) = self.iter.as_mut().and_then(|i| i.next()) {
-            self.min_len -= 1;
-            Some(val)
-        } else if self.min_len > 0 {
-            self.min_len -= 1;
-            Some(0)
-        } else {
-            None
+        if self.len == 0 {
+            return None;
         }
+
+        self.len -= 1;
+
+        Some(
+            self.iter
+                .as_mut()
+                .and_then(|i| i.next())
+                .unwrap_or_else(|| StreamSample::from_value(0.)),
+        )
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.len(), Some(self.len()))
+        (self.len, Some(self.len))
     }
 }
 
 impl<I> std::iter::ExactSizeIterator for OrZero<I>
 where
-    I: std::iter::ExactSizeIterator<Item = i16>,
+    I: std::iter::ExactSizeIterator,
+    I::Item: StreamSample,
 {
-    fn len(&self) -> usize {
-        self.iter
-            .as_ref()
-            .map(|i| i.len())
-            .unwrap_or(0)
-            .max(self.min_len)
-    }
 }
 
-pub struct AudioStreamer<S, C, InputSpec, OutputSpec>
+/// A MIDI event along with the sample it happens at, counted from when the streamer started.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TimedMidi {
+    pub time: u64,
+    pub event: MidiValue,
+}
+
+/// Plays a rack, reading its continuous inputs from `S` and producing samples of type `T` with
+/// the channels of every continuous output one after another. Rack values are converted to `T`
+/// as they're produced, so streaming `f32` or `f64` keeps their full resolution. A rack with no
+/// continuous outputs produces one silent sample per frame, so that it still runs and sends MIDI
+/// as it's streamed.
+pub struct AudioStreamer<S, C, InputSpec, OutputSpec, T = i16>
 where
     C: AnyComponent,
     OutputSpec: HasStorage<InternalWire>,
 {
-    output_id: SpecId,
-    out_iter: Option<OutputIter<S, C, InputSpec, OutputSpec>>,
+    /// The samples of the current frame, one output after another.
+    frame: Vec<T>,
+    /// The index in `frame` of the next sample to produce.
+    frame_pos: usize,
     sample_rate: u32,
+    transport: Transport,
+    /// The number of samples played, which unlike the transport keeps counting while stopped.
+    elapsed: u64,
     audio_inputs: S,
+    /// The samples of the continuous inputs and the MIDI events for the current frame, kept so
+    /// that their memory is reused from one frame to the next.
+    sources: Vec<Value>,
+    midi: Vec<(SpecId, MidiValue)>,
+    /// Events waiting to be sent to the rack's MIDI inputs, along with the input for each, in
+    /// the order they're due.
+    midi_inputs: Vec<(SpecId, TimedMidi)>,
+    /// Events sent by the rack's MIDI outputs that haven't been taken yet.
+    midi_outputs: Vec<(SpecId, TimedMidi)>,
     rack: Rack<C, InputSpec, OutputSpec>,
 }
 
-impl<S, C, InputSpec, OutputSpec>
-    AudioStreamer<rodio::source::UniformSourceIterator<S, i16>, C, InputSpec, OutputSpec>
+impl<S, C, InputSpec, OutputSpec, T>
+    AudioStreamer<rodio::source::UniformSourceIterator<S, f32>, C, InputSpec, OutputSpec, T>
 where
     C: AnyComponent,
     InputSpec: EnumerateValues,
     OutputSpec: EnumerateValues + HasStorage<InternalWire>,
     S: Source + Iterator + 'static,
     S::Item: rodio::Sample,
+    T: StreamSample,
 {
     pub fn new_convert(
         sample_rate: impl Into<Option<u32>>,
@@ -118,12 +266,14 @@ where
 
 const DEFAULT_SAMPLE_RATE: u32 = 44100;
 
-impl<S, C, InputSpec, OutputSpec> AudioStreamer<S, C, InputSpec, OutputSpec>
+impl<S, C, InputSpec, OutputSpec, T> AudioStreamer<S, C, InputSpec, OutputSpec, T>
 where
     C: AnyComponent,
-    S: Source + Iterator<Item = i16> + 'static,
+    S: Source + Iterator + 'static,
+    S::Item: StreamSample + rodio::Sample,
     InputSpec: RuntimeSpecifier,
     OutputSpec: EnumerateValues + HasStorage<InternalWire>,
+    T: StreamSample,
 {
     pub fn new_unchecked(
         sample_rate: impl Into<Option<u32>>,
@@ -131,11 +281,17 @@ where
         source: S,
     ) -> Self {
         AudioStreamer {
-            output_id: 0,
+            frame: vec![],
+            frame_pos: 0,
             rack,
             sample_rate: sample_rate.into().unwrap_or(DEFAULT_SAMPLE_RATE),
-            out_iter: None,
+            transport: Transport::default(),
+            elapsed: 0,
             audio_inputs: source,
+            sources: vec![],
+            midi: vec![],
+            midi_inputs: vec![],
+            midi_outputs: vec![],
         }
     }
 
@@ -146,18 +302,99 @@ where
     ) -> Option<Self> {
         let sample_rate = sample_rate.into().unwrap_or(DEFAULT_SAMPLE_RATE);
         if source.sample_rate() == sample_rate
-            && source.channels() == num_audio_channels::<OutputSpec>() as u16
+            && source.channels() == num_audio_channels::<InputSpec>() as u16
         {
             Some(Self::new_unchecked(sample_rate, rack, source))
         } else {
             None
         }
     }
+
+    /// Beats per minute
+    #[inline]
+    pub fn set_tempo(&mut self, tempo: Value) {
+        self.transport.tempo = tempo;
+    }
+
+    #[inline]
+    pub fn set_time_signature(&mut self, time_signature: TimeSignature) {
+        self.transport.time_signature = time_signature;
+    }
+
+    /// Start moving the transport from where it was stopped.
+    #[inline]
+    pub fn play(&mut self) {
+        self.transport.playing = true;
+    }
+
+    /// Stop moving the transport. The rack keeps being updated, so audio coming from the inputs
+    /// still plays, but anything synced to the transport will hold its position.
+    #[inline]
+    pub fn stop(&mut self) {
+        self.transport.playing = false;
+    }
+
+    /// Move the transport to a number of samples since the start, as with `Transport::seek`.
+    #[inline]
+    pub fn seek(&mut self, position: u64) {
+        self.transport.seek(position, self.sample_rate);
+    }
+
+    #[inline]
+    pub fn transport(&self) -> Transport {
+        self.transport
+    }
+
+    /// The number of samples played so far, which is the time that `TimedMidi` is measured in.
+    #[inline]
+    pub fn elapsed(&self) -> u64 {
+        self.elapsed
+    }
+
+    /// Send an event to a MIDI input of the rack at `event.time`, or at the next sample if that
+    /// time has already passed. Events for the same sample arrive in the order they were sent.
+    pub fn send_midi(&mut self, input: InputSpec, event: TimedMidi) {
+        debug_assert_eq!(input.value_type().kind, ValueKind::Midi);
+
+        // Events are usually sent in order, so this is almost always the end
+        let index = self
+            .midi_inputs
+            .iter()
+            .rposition(|(_, waiting)| waiting.time <= event.time)
+            .map_or(0, |i| i + 1);
+
+        self.midi_inputs.insert(index, (input.id(), event));
+    }
+
+    /// Take every event sent by the rack's MIDI outputs since this was last called, in the order
+    /// they were sent.
+    pub fn take_midi(&mut self) -> Vec<(OutputSpec, TimedMidi)> {
+        self.midi_outputs
+            .drain(..)
+            .map(|(id, event)| (OutputSpec::from_id(id), event))
+            .collect()
+    }
+
+    /// The rack being played, for editing it while it plays.
+    #[inline]
+    pub fn rack(&self) -> &Rack<C, InputSpec, OutputSpec> {
+        &self.rack
+    }
+
+    #[inline]
+    pub fn rack_mut(&mut self) -> &mut Rack<C, InputSpec, OutputSpec> {
+        &mut self.rack
+    }
 }
 
 pub struct Context<'a, ISpec> {
-    sources: Cow<'a, [i16]>,
+    /// The samples of every continuous input, one input after another.
+    sources: &'a [Value],
+    /// The MIDI events for this sample, along with the input each is for. The events for each
+    /// input are next to one another, in the order that they arrived.
+    midi: &'a [(SpecId, MidiValue)],
     sample_rate: u32,
+    transport: Transport,
     _marker: PhantomData<ISpec>,
 }
 
@@ -165,6 +402,18 @@ impl<'a, I> ContextMeta for Context<'a, I> {
     fn sample_rate(&self) -> u32 {
         self.sample_rate
     }
+
+    fn transport(&self) -> Transport {
+        self.transport
+    }
+}
+
+/// The events of one MIDI input of a `Context`.
+type MidiEvents<'a> =
+    iter::Map<slice::Iter<'a, (SpecId, MidiValue)>, fn(&(SpecId, MidiValue)) -> MidiValue>;
+
+fn midi_event(&(_, event): &(SpecId, MidiValue)) -> MidiValue {
+    event
 }
 
 impl<'a, InputSpec> GetFunctionParam for Context<'a, InputSpec>
@@ -172,147 +421,182 @@ where
     InputSpec: RuntimeSpecifier,
 {
     type InputSpec = InputSpec;
-    type Iter = <Value as ValueIterImplHelper<std::vec::IntoIter<Value>>>::AnyIter;
+    type Iter = AnyIter<MidiEvents<'a>, OrZero<iter::Copied<slice::Iter<'a, Value>>>>;
 
     // `None` means that this input is not wired
     fn input(&self, spec: InputSpec) -> Option<Self::Iter> {
-        let mut id = 0;
+        if spec.value_type().kind == ValueKind::Midi {
+            let midi = self.midi;
+            let start = midi
+                .iter()
+                .position(|(input, _)| *input == spec.id())
+                .unwrap_or(midi.len());
+            let len = midi[start..]
+                .iter()
+                .take_while(|(input, _)| *input == spec.id())
+                .count();
+
+            return Some(AnyIter::midi(
+                midi[start..start + len]
+                    .iter()
+                    .map(midi_event as fn(&_) -> _),
+            ));
+        }
+
+        let mut start = 0;
 
         for i in 0..spec.id() {
             let ty = InputSpec::from_id(i).value_type();
             if ty.kind == ValueKind::Continuous {
-                id += ty.channels.unwrap().get();
+                start += ty.channels.unwrap().get() as usize;
             }
         }
 
-        Some(
-            // TODO
-            self.sources[id as usize
-                ..(id
-                    + InputSpec::from_id(id as _)
-                        .value_type()
-                        .channels
-                        .unwrap()
-                        .get()) as usize]
-                .iter()
-                .map(|&val| I1F15::from_bits(val).into())
-                .collect::<Vec<Value>>()
-                .into_iter()
-                .into(),
-        )
+        let len = spec.value_type().channels.unwrap().get() as usize;
+        let sources = self.sources.get(start..).unwrap_or_default();
+
+        // Any channels missing from `sources` are silent
+        Some(AnyIter::analog(OrZero {
+            iter: Some(sources[..len.min(sources.len())].iter().copied()),
+            len,
+        }))
     }
 }
 
-type OutputIter<S, C, InputSpec, OutputSpec> = impl ExactSizeIterator<Item = i16>;
-
-impl<S, C, InputSpec, OutputSpec> AudioStreamer<S, C, InputSpec, OutputSpec>
+impl<S, C, InputSpec, OutputSpec, T> AudioStreamer<S, C, InputSpec, OutputSpec, T>
 where
-    S: Source + Iterator<Item = i16> + 'static,
+    S: Source + Iterator + 'static,
+    S::Item: StreamSample + rodio::Sample,
     C: AnyComponent + 'static,
     InputSpec: EnumerateValues,
     OutputSpec: EnumerateValues + HasStorage<InternalWire>,
+    T: StreamSample,
 {
-    fn update(&mut self) -> Option<OutputIter<S, C, InputSpec, OutputSpec>> {
-        loop {
-            let mut sources = vec![];
-            if self.output_id == 0 {
-                // `debug` because we should assert this in `fn new`
-                debug_assert_eq!(self.audio_inputs.sample_rate(), self.sample_rate());
-                debug_assert_eq!(
-                    self.audio_inputs.channels(),
-                    num_audio_channels::<InputSpec>() as u16
+    /// Run the rack for one frame, filling `frame` with its continuous outputs. Returns `None`
+    /// once the inputs run out.
+    fn update(&mut self) -> Option<()> {
+        // `debug` because we should assert this in `fn new`
+        debug_assert_eq!(self.audio_inputs.sample_rate(), self.sample_rate);
+        debug_assert_eq!(
+            self.audio_inputs.channels(),
+            num_audio_channels::<InputSpec>() as u16
+        );
+
+        self.sources.clear();
+        for _ in 0..self.audio_inputs.channels() {
+            self.sources.push(self.audio_inputs.next()?.to_value());
+        }
+
+        let elapsed = self.elapsed;
+        let due = self
+            .midi_inputs
+            .iter()
+            .take_while(|(_, event)| event.time <= elapsed)
+            .count();
+
+        self.midi.clear();
+
+        // `Context` needs the events for each input to be next to one another
+        for spec in InputSpec::values() {
+            if spec.value_type().kind == ValueKind::Midi {
+                self.midi.extend(
+                    self.midi_inputs[..due]
+                        .iter()
+                        .filter(|(input, _)| *input == spec.id())
+                        .map(|&(input, event)| (input, event.event)),
                 );
-                for _ in 0..self.audio_inputs.channels() {
-                    sources.push(self.audio_inputs.next()?);
-                }
+            }
+        }
 
-                let sources = Cow::Borrowed(&sources[..]);
-                let ctx = Context {
-                    sample_rate: self.sample_rate(),
-                    sources,
-                    _marker: PhantomData,
-                };
+        self.midi_inputs.drain(..due);
 
-                self.rack.update::<Context<InputSpec>>(&ctx);
-            }
+        let ctx = Context {
+            sample_rate: self.sample_rate,
+            transport: self.transport,
+            sources: &self.sources,
+            midi: &self.midi,
+            _marker: PhantomData,
+        };
+
+        self.rack.update(&ctx);
 
-            let new_id = {
-                let mut id = self.output_id;
-                loop {
-                    if let Some(v) = OutputSpec::values().nth(id) {
-                        if v.value_type().kind == ValueKind::Continuous {
-                            break Some(id);
-                        } else {
-                            id += 1;
-                            continue;
-                        }
-                    } else {
-                        break None;
-                    }
+        self.frame.clear();
+        self.frame_pos = 0;
+
+        for spec in OutputSpec::values() {
+            let ty = spec.value_type();
+            let output = self.rack.output(OutputSpec::from_id(spec.id()), &ctx);
+
+            if ty.kind == ValueKind::Midi {
+                let events = output.and_then(|iter| PossiblyIter::<MidiValue>::try_iter(iter).ok());
+
+                if let Some(events) = events {
+                    let id = spec.id();
+
+                    self.midi_outputs.extend(events.map(|event| {
+                        (
+                            id,
+                            TimedMidi {
+                                time: elapsed,
+                                event,
+                            },
+                        )
+                    }));
                 }
-            };
-
-            if let Some(new_id) = new_id {
-                let sources = Cow::Owned(sources);
-                let ctx: Context<'static, InputSpec> = Context {
-                    sample_rate: self.sample_rate(),
-                    sources,
-                    _marker: PhantomData,
-                };
-
-                self.output_id = new_id + 1;
-
-                return Some(OrZero {
-                    iter: self
-                        .rack
-                        .output(OutputSpec::from_id(new_id), &ctx)
-                        .map(|iter| {
-                            PossiblyIter::<Value>::try_iter(iter)
-                                .unwrap_or_else(|_| unimplemented!())
-                                .map(|val| I1F15::saturating_from_num(val).to_bits())
-                        }),
-                    min_len: OutputSpec::from_id(new_id)
-                        .value_type()
-                        .channels
-                        .unwrap()
-                        .get() as usize,
-                });
             } else {
-                self.output_id = 0;
+                self.frame.extend(OrZero {
+                    iter: output.map(|iter| {
+                        PossiblyIter::<Value>::try_iter(iter)
+                            .unwrap_or_else(|_| unimplemented!())
+                            .map(T::from_value)
+                    }),
+                    len: ty.channels.unwrap().get() as usize,
+                });
             }
         }
+
+        if self.frame.is_empty() {
+            self.frame.push(T::from_value(0.));
+        }
+
+        self.elapsed += 1;
+        self.transport.advance(self.sample_rate);
+
+        Some(())
     }
 }
 
-impl<S, C, InputSpec, OutputSpec> Iterator for AudioStreamer<S, C, InputSpec, OutputSpec>
+impl<S, C, InputSpec, OutputSpec, T> Iterator for AudioStreamer<S, C, InputSpec, OutputSpec, T>
 where
-    S: Source + Iterator<Item = i16> + 'static,
+    S: Source + Iterator + 'static,
+    S::Item: StreamSample + rodio::Sample,
     C: AnyComponent + 'static,
     InputSpec: EnumerateValues,
     OutputSpec: EnumerateValues + HasStorage<InternalWire>,
+    T: StreamSample,
 {
-    type Item = i16;
+    type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(a) = self.out_iter.as_mut().and_then(|i| i.next()) {
-            return Some(a);
+        if self.frame_pos == self.frame.len() {
+            self.update()?;
         }
 
-        let mut new_iter = self.update()?;
-        let out = new_iter.next();
-        if new_iter.len() > 0 {
-            self.out_iter = Some(new_iter);
-        }
-        out
+        let out = self.frame[self.frame_pos];
+        self.frame_pos += 1;
+
+        Some(out)
     }
 }
 
-impl<S, C, InputSpec, OutputSpec> rodio::Source for AudioStreamer<S, C, InputSpec, OutputSpec>
+impl<S, C, InputSpec, OutputSpec, T> rodio::Source for AudioStreamer<S, C, InputSpec, OutputSpec, T>
 where
-    S: Source + Iterator<Item = i16> + 'static,
+    S: Source + Iterator + 'static,
+    S::Item: StreamSample + rodio::Sample,
     C: AnyComponent + 'static,
     InputSpec: EnumerateValues,
     OutputSpec: EnumerateValues + HasStorage<InternalWire>,
+    T: StreamSample + rodio::Sample,
 {
     fn current_frame_len(&self) -> Option<usize> {
         None
@@ -323,7 +607,7 @@ where
     }
 
     fn channels(&self) -> u16 {
-        num_audio_channels::<OutputSpec>() as u16
+        (num_audio_channels::<OutputSpec>() as u16).max(1)
     }
 
     fn total_duration(&self) -> Option<std::time::Duration> {
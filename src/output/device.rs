@@ -0,0 +1,478 @@
+//! Playing a rack through an audio device with `cpal` directly, rather than through `rodio`. The
+//! rack is run in the device's callback and its outputs are sent to the device as floats, only
+//! being converted to integers if the device can't take floats.
+
+use super::{num_audio_channels, Context, DEFAULT_SAMPLE_RATE};
+use crate::{
+    block::{Block, BlockOutputs},
+    components::EnumerateValues,
+    context::{TimeSignature, Transport},
+    params::HasStorage,
+    rack::InternalWire,
+    AnyComponent, Rack, Value, ValueKind,
+};
+use cpal::{
+    CreationError, DefaultFormatError, Device, EventLoop, Format, SampleFormat, SampleRate,
+    StreamData, StreamId, UnknownTypeOutputBuffer,
+};
+use std::{
+    fmt,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How a `Driver` should open its stream. Anything that's `None` is left as the device's default.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DriverConfig {
+    pub sample_rate: Option<u32>,
+    pub sample_format: Option<SampleFormat>,
+    /// The number of frames that the rack is run for at a time. `cpal` doesn't let us choose the
+    /// size of the device's own buffer, so this is independent of it, but the output is delayed
+    /// by up to this many frames.
+    pub buffer_size: usize,
+}
+
+impl Default for DriverConfig {
+    fn default() -> Self {
+        DriverConfig {
+            sample_rate: None,
+            sample_format: None,
+            buffer_size: 256,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DriverError {
+    /// There's no audio output device.
+    NoDevice,
+    /// The device's default format couldn't be found.
+    DefaultFormat(DefaultFormatError),
+    /// The device doesn't support the stream that was asked for.
+    Creation(CreationError),
+}
+
+impl fmt::Display for DriverError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoDevice => write!(f, "no audio output device"),
+            Self::DefaultFormat(e) => write!(f, "couldn't get the default format: {}", e),
+            Self::Creation(e) => write!(f, "couldn't open the output stream: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DriverError {}
+
+impl From<DefaultFormatError> for DriverError {
+    fn from(e: DefaultFormatError) -> Self {
+        DriverError::DefaultFormat(e)
+    }
+}
+
+impl From<CreationError> for DriverError {
+    fn from(e: CreationError) -> Self {
+        DriverError::Creation(e)
+    }
+}
+
+/// Runs a rack in blocks of frames and hands them out as interleaved floats. This is the part of
+/// a `Driver` that runs in the audio callback, and it doesn't need a device so that it can be
+/// tested offline. The rack's inputs are silent.
+pub struct Engine<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent,
+    OutputSpec: HasStorage<InternalWire>,
+{
+    sample_rate: u32,
+    transport: Transport,
+    buffer_size: usize,
+    /// The last block of frames rendered, interleaved.
+    block: Vec<f32>,
+    /// The number of samples of `block` that have been sent.
+    sent: usize,
+    /// The outputs of the rack over the last block, kept so that their memory can be reused.
+    outputs: BlockOutputs,
+    /// The contexts of the last block, kept so that their memory can be reused. The rack's inputs
+    /// are always silent, so the contexts don't need any sources.
+    frames: Vec<Context<'static, InputSpec>>,
+    rack: Rack<C, InputSpec, OutputSpec>,
+}
+
+impl<C, InputSpec, OutputSpec> Engine<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent + 'static,
+    InputSpec: EnumerateValues,
+    OutputSpec: EnumerateValues + HasStorage<InternalWire>,
+{
+    /// # Panics
+    ///
+    /// If `buffer_size` is 0.
+    pub fn new(
+        sample_rate: impl Into<Option<u32>>,
+        buffer_size: usize,
+        rack: Rack<C, InputSpec, OutputSpec>,
+    ) -> Self {
+        assert!(buffer_size > 0, "The buffer must hold at least one frame");
+
+        Engine {
+            sample_rate: sample_rate.into().unwrap_or(DEFAULT_SAMPLE_RATE),
+            transport: Transport::default(),
+            buffer_size,
+            block: vec![],
+            sent: 0,
+            outputs: BlockOutputs::default(),
+            frames: Vec::with_capacity(buffer_size),
+            rack,
+        }
+    }
+
+    #[inline]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// The number of channels in each frame that the rack outputs.
+    #[inline]
+    pub fn channels(&self) -> usize {
+        num_audio_channels::<OutputSpec>() as usize
+    }
+
+    /// Beats per minute
+    #[inline]
+    pub fn set_tempo(&mut self, tempo: Value) {
+        self.transport.tempo = tempo;
+    }
+
+    #[inline]
+    pub fn set_time_signature(&mut self, time_signature: TimeSignature) {
+        self.transport.time_signature = time_signature;
+    }
+
+    /// Start moving the transport from where it was stopped.
+    #[inline]
+    pub fn play(&mut self) {
+        self.transport.playing = true;
+    }
+
+    /// Stop moving the transport, as with `AudioStreamer::stop`.
+    #[inline]
+    pub fn stop(&mut self) {
+        self.transport.playing = false;
+    }
+
+    #[inline]
+    pub fn transport(&self) -> Transport {
+        self.transport
+    }
+
+    /// The rack being played, for editing it while it plays.
+    #[inline]
+    pub fn rack(&self) -> &Rack<C, InputSpec, OutputSpec> {
+        &self.rack
+    }
+
+    #[inline]
+    pub fn rack_mut(&mut self) -> &mut Rack<C, InputSpec, OutputSpec> {
+        &mut self.rack
+    }
+
+    /// Run the rack for `buffer_size` frames.
+    fn render_block(&mut self) {
+        self.frames.clear();
+
+        for _ in 0..self.buffer_size {
+            self.frames.push(Context {
+                sample_rate: self.sample_rate,
+                transport: self.transport,
+                sources: &[],
+                midi: &[],
+                _marker: PhantomData,
+            });
+
+            self.transport.advance(self.sample_rate);
+        }
+
+        self.rack.process_block(&self.frames, &mut self.outputs);
+        self.block.clear();
+        self.sent = 0;
+
+        for i in 0..self.buffer_size {
+            for spec in OutputSpec::values() {
+                let ty = spec.value_type();
+
+                if ty.kind != ValueKind::Continuous {
+                    continue;
+                }
+
+                let channels = ty.channels.unwrap().get() as usize;
+                let len = self.block.len();
+
+                if let Some(Block::Continuous(frames)) = self.outputs.get(spec.id()) {
+                    self.block
+                        .extend(frames.frame(i).iter().take(channels).map(|&val| val as f32));
+                }
+
+                self.block.resize(len + channels, 0.);
+            }
+        }
+    }
+
+    /// Fill `out` with interleaved frames of `channels` channels, running the rack whenever the
+    /// last block runs out. Channels that the rack doesn't output are silent, except that a
+    /// mono rack is played on every channel.
+    pub fn process(&mut self, out: &mut [f32], channels: usize) {
+        let rack_channels = self.channels();
+
+        if rack_channels == 0 || channels == 0 {
+            for sample in out {
+                *sample = 0.;
+            }
+            return;
+        }
+
+        for frame in out.chunks_mut(channels) {
+            if self.sent >= self.block.len() {
+                self.render_block();
+            }
+
+            let rendered = &self.block[self.sent..self.sent + rack_channels];
+
+            for (i, sample) in frame.iter_mut().enumerate() {
+                *sample = match rendered {
+                    [mono] => *mono,
+                    _ => rendered.get(i).copied().unwrap_or(0.),
+                };
+            }
+
+            self.sent += rack_channels;
+        }
+    }
+}
+
+/// Plays a rack through an audio device. The stream is run on a thread of its own, and the
+/// rack can be edited while it plays with `engine`.
+///
+/// `cpal`'s event loop never returns once it's started, so the thread that runs the stream lives
+/// for as long as the program does, even after the `Driver` is dropped. Dropping the `Driver`
+/// destroys the stream and frees the rack, but the thread stays blocked waiting for streams that
+/// never come, so a program should make one `Driver` and keep it for as long as it plays audio.
+pub struct Driver<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent,
+    OutputSpec: HasStorage<InternalWire>,
+{
+    event_loop: Arc<EventLoop>,
+    stream: StreamId,
+    format: Format,
+    engine: Arc<Mutex<Engine<C, InputSpec, OutputSpec>>>,
+    late_callbacks: Arc<AtomicUsize>,
+}
+
+impl<C, InputSpec, OutputSpec> Driver<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent + 'static,
+    InputSpec: EnumerateValues,
+    OutputSpec: EnumerateValues + HasStorage<InternalWire>,
+    Engine<C, InputSpec, OutputSpec>: Send,
+{
+    /// Open a stream on the default output device. The stream is paused until `start` is called.
+    pub fn new(
+        rack: Rack<C, InputSpec, OutputSpec>,
+        config: DriverConfig,
+    ) -> Result<Self, DriverError> {
+        let device = cpal::default_output_device().ok_or(DriverError::NoDevice)?;
+
+        Self::with_device(&device, rack, config)
+    }
+
+    /// Open a stream on `device`, as with `new`.
+    pub fn with_device(
+        device: &Device,
+        rack: Rack<C, InputSpec, OutputSpec>,
+        config: DriverConfig,
+    ) -> Result<Self, DriverError> {
+        let mut format = device.default_output_format()?;
+
+        if let Some(sample_rate) = config.sample_rate {
+            format.sample_rate = SampleRate(sample_rate);
+        }
+        if let Some(sample_format) = config.sample_format {
+            format.data_type = sample_format;
+        }
+
+        let event_loop = Arc::new(EventLoop::new());
+        let stream = event_loop.build_output_stream(device, &format)?;
+        let engine = Arc::new(Mutex::new(Engine::new(
+            format.sample_rate.0,
+            config.buffer_size,
+            rack,
+        )));
+        let late_callbacks = Arc::new(AtomicUsize::new(0));
+
+        {
+            // The thread only holds a weak reference to the engine so that the rack is freed
+            // when the `Driver` is dropped, even though the thread never ends
+            let (event_loop, stream, engine, late_callbacks) = (
+                event_loop.clone(),
+                stream.clone(),
+                Arc::downgrade(&engine),
+                late_callbacks.clone(),
+            );
+            let (channels, sample_rate) = (format.channels as usize, format.sample_rate.0);
+            let mut scratch = vec![];
+
+            thread::Builder::new()
+                .name("octahack audio".into())
+                .spawn(move || {
+                    event_loop.run(move |id, data| {
+                        let buffer = match data {
+                            StreamData::Output { buffer } if id == stream => buffer,
+                            _ => return,
+                        };
+                        let started = Instant::now();
+                        let engine = match engine.upgrade() {
+                            Some(engine) => engine,
+                            None => return,
+                        };
+                        let mut engine = engine.try_lock().ok();
+                        let mut process = |out: &mut [f32]| match engine.as_mut() {
+                            Some(engine) => engine.process(out, channels),
+                            // The rack is being edited, so play silence rather than waiting
+                            None => {
+                                for sample in out {
+                                    *sample = 0.;
+                                }
+                            }
+                        };
+                        let len = match buffer {
+                            UnknownTypeOutputBuffer::F32(mut buffer) => {
+                                process(&mut buffer[..]);
+                                buffer.len()
+                            }
+                            UnknownTypeOutputBuffer::I16(mut buffer) => {
+                                scratch.resize(buffer.len(), 0.);
+                                process(&mut scratch);
+                                for (out, &val) in buffer.iter_mut().zip(&scratch) {
+                                    *out = (val.max(-1.).min(1.) * 32767.) as i16;
+                                }
+                                buffer.len()
+                            }
+                            UnknownTypeOutputBuffer::U16(mut buffer) => {
+                                scratch.resize(buffer.len(), 0.);
+                                process(&mut scratch);
+                                for (out, &val) in buffer.iter_mut().zip(&scratch) {
+                                    *out = ((val.max(-1.).min(1.) + 1.) * 32767.5) as u16;
+                                }
+                                buffer.len()
+                            }
+                        };
+                        let deadline = Duration::from_secs_f64(
+                            (len / channels.max(1)) as f64 / f64::from(sample_rate),
+                        );
+
+                        if engine.is_none() || started.elapsed() > deadline {
+                            late_callbacks.fetch_add(1, Ordering::Relaxed);
+                        }
+                    })
+                })
+                .expect("Couldn't start the audio thread");
+        }
+
+        Ok(Driver {
+            event_loop,
+            stream,
+            format,
+            engine,
+            late_callbacks,
+        })
+    }
+
+    /// Start or resume playing.
+    pub fn start(&self) {
+        self.event_loop.play_stream(self.stream.clone());
+    }
+
+    /// Pause playing. The rack isn't run while it's paused.
+    pub fn stop(&self) {
+        self.event_loop.pause_stream(self.stream.clone());
+    }
+
+    /// The number of callbacks that played silence because the rack was locked by `engine`, or
+    /// that took longer to fill than the audio they filled lasts. `cpal` doesn't tell us about
+    /// underruns, so this is only an estimate of them made by timing the callbacks: a slow
+    /// callback doesn't cause an underrun if the device's buffer has audio to spare, and an
+    /// underrun can happen without a slow callback if the thread isn't woken in time.
+    pub fn late_callbacks(&self) -> usize {
+        self.late_callbacks.load(Ordering::Relaxed)
+    }
+
+    /// The format of the stream, which may have a different number of channels to the rack.
+    #[inline]
+    pub fn format(&self) -> &Format {
+        &self.format
+    }
+
+    /// Lock the engine to change the rack or the transport. The device gets silence while this is
+    /// held, so it shouldn't be held for long.
+    pub fn engine(&self) -> MutexGuard<'_, Engine<C, InputSpec, OutputSpec>> {
+        self.engine.lock().unwrap()
+    }
+}
+
+impl<C, InputSpec, OutputSpec> Drop for Driver<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent,
+    OutputSpec: HasStorage<InternalWire>,
+{
+    /// Stop the stream for good. See the docs of `Driver` for why this doesn't end its thread.
+    fn drop(&mut self) {
+        self.event_loop.destroy_stream(self.stream.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Engine;
+    use crate::{
+        octahack_components::{
+            synth::{output::Specifier::Square, Synth},
+            OctahackComponent,
+        },
+        Rack, WireDst, WireSrc,
+    };
+
+    crate::specs! {
+        mod mono {
+            Audio: crate::Value
+        }
+    }
+
+    #[test]
+    fn process_in_blocks() {
+        let mut rack = Rack::<OctahackComponent, mono::Specifier, mono::Specifier>::new();
+        let mut main = rack.main_mut();
+        let synth = main.push_component(Synth::new());
+
+        main.wire(
+            WireSrc::component_output(synth, Square),
+            WireDst::func_output(mono::Specifier::Audio),
+        )
+        .unwrap();
+
+        // A 440Hz square wave at 1760Hz is two frames up and then two frames down
+        let mut engine = Engine::new(1760, 3, rack);
+        let mut out = [0.; 10];
+
+        // The mono output is played on both channels, and blocks carry on between calls
+        engine.process(&mut out[..4], 2);
+        engine.process(&mut out[4..], 2);
+        assert_eq!(out, [1., 1., -1., -1., -1., -1., 1., 1., 1., 1.]);
+        assert_eq!(engine.transport().position, 6);
+    }
+}
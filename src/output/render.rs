@@ -0,0 +1,394 @@
+//! Rendering a rack offline, as fast as it can be rendered instead of in real time and without an
+//! audio device, so that patches can be tested reproducibly. Inputs are read from files and each
+//! continuous output is rendered into a file of its own.
+
+use super::{num_audio_channels, Context, DEFAULT_SAMPLE_RATE};
+use crate::{
+    block::{Block, BlockOutputs},
+    components::EnumerateValues,
+    context::{File, TimeSignature, Transport},
+    files::{AudioFile, WavFormat},
+    params::HasStorage,
+    rack::InternalWire,
+    AnyComponent, Rack, SpecId, Value, ValueKind,
+};
+use rodio::decoder::DecoderError;
+use std::{
+    fs,
+    io::{self, BufWriter, Read, Seek},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The number of frames that the rack is run for at a time.
+const BLOCK_SIZE: u64 = 256;
+
+/// Runs a rack offline, feeding its continuous inputs from audio files.
+pub struct Renderer<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent,
+    OutputSpec: HasStorage<InternalWire>,
+{
+    sample_rate: u32,
+    transport: Transport,
+    /// The file for each input that's fed from one.
+    inputs: Vec<(SpecId, AudioFile)>,
+    /// The samples of the continuous inputs for each frame of the current block, one frame after
+    /// another, kept so that their memory is reused from one block to the next.
+    sources: Vec<Value>,
+    rack: Rack<C, InputSpec, OutputSpec>,
+}
+
+impl<C, InputSpec, OutputSpec> Renderer<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent + 'static,
+    InputSpec: EnumerateValues,
+    OutputSpec: EnumerateValues + HasStorage<InternalWire>,
+{
+    pub fn new(sample_rate: impl Into<Option<u32>>, rack: Rack<C, InputSpec, OutputSpec>) -> Self {
+        Renderer {
+            sample_rate: sample_rate.into().unwrap_or(DEFAULT_SAMPLE_RATE),
+            transport: Transport::default(),
+            inputs: vec![],
+            sources: vec![],
+            rack,
+        }
+    }
+
+    /// Beats per minute
+    #[inline]
+    pub fn set_tempo(&mut self, tempo: Value) {
+        self.transport.tempo = tempo;
+    }
+
+    #[inline]
+    pub fn set_time_signature(&mut self, time_signature: TimeSignature) {
+        self.transport.time_signature = time_signature;
+    }
+
+    #[inline]
+    pub fn transport(&self) -> Transport {
+        self.transport
+    }
+
+    /// Feed a continuous input of the rack from `file`, which is played from the start of the
+    /// render and resampled if its sample rate is different. The input is silent once the file
+    /// ends, and any channels that the file doesn't have are silent too.
+    pub fn set_input(&mut self, input: InputSpec, file: AudioFile) {
+        debug_assert_eq!(input.value_type().kind, ValueKind::Continuous);
+
+        let id = input.id();
+
+        self.inputs.retain(|(other, _)| *other != id);
+        self.inputs.push((id, file));
+    }
+
+    /// Decode a WAV file and feed an input from it, as with `set_input`.
+    pub fn load_input<R>(&mut self, input: InputSpec, reader: R) -> Result<(), DecoderError>
+    where
+        R: Read + Seek + Send + 'static,
+    {
+        AudioFile::decode(reader).map(|file| self.set_input(input, file))
+    }
+
+    /// Run the rack for `samples` samples, continuing from where the last render stopped, and
+    /// return what each continuous output produced.
+    pub fn render(&mut self, samples: u64) -> Vec<(OutputSpec, AudioFile)> {
+        let mut outputs = OutputSpec::values()
+            .filter(|spec| spec.value_type().kind == ValueKind::Continuous)
+            .map(|spec| {
+                let mut file = AudioFile::buffer(None);
+
+                file.clear(
+                    self.sample_rate,
+                    spec.value_type().channels.unwrap().get() as usize,
+                );
+
+                (spec.id(), file)
+            })
+            .collect::<Vec<_>>();
+
+        let mut block = BlockOutputs::default();
+        let mut rendered = 0;
+        let channels = num_audio_channels::<InputSpec>() as usize;
+
+        while rendered < samples {
+            let len = (samples - rendered).min(BLOCK_SIZE);
+
+            self.sources.clear();
+            for i in 0..len {
+                self.push_sources(self.transport.position + i);
+            }
+
+            let mut frames = Vec::<Context<InputSpec>>::with_capacity(len as usize);
+
+            for i in 0..len as usize {
+                frames.push(Context {
+                    sample_rate: self.sample_rate,
+                    transport: self.transport,
+                    sources: &self.sources[i * channels..(i + 1) * channels],
+                    midi: &[],
+                    _marker: PhantomData,
+                });
+
+                self.transport.advance(self.sample_rate);
+            }
+
+            self.rack.process_block(&frames, &mut block);
+
+            for (id, file) in &mut outputs {
+                if let Some(Block::Continuous(frames)) = block.get(*id) {
+                    for i in 0..frames.len() {
+                        file.push_frame(frames.frame(i));
+                    }
+                }
+            }
+
+            rendered += len;
+        }
+
+        outputs
+            .into_iter()
+            .map(|(id, file)| (OutputSpec::from_id(id), file))
+            .collect()
+    }
+
+    /// Add the value of each channel of each continuous input at `position` to `sources`.
+    fn push_sources(&mut self, position: u64) {
+        let time = Duration::from_secs_f64(position as f64 / f64::from(self.sample_rate));
+
+        for spec in InputSpec::values() {
+            let ty = spec.value_type();
+
+            if ty.kind != ValueKind::Continuous {
+                continue;
+            }
+
+            let frame = self
+                .inputs
+                .iter()
+                .find(|(id, _)| *id == spec.id())
+                .map(|(_, file)| file.at(time))
+                .unwrap_or_default();
+
+            self.sources.extend(
+                (0..ty.channels.unwrap().get() as usize)
+                    .map(|i| frame.as_slice().get(i).copied().unwrap_or(0.)),
+            );
+        }
+    }
+
+    /// Run the rack for `duration`, rounded to the nearest sample, as with `render`.
+    pub fn render_for(&mut self, duration: Duration) -> Vec<(OutputSpec, AudioFile)> {
+        self.render((duration.as_secs_f64() * f64::from(self.sample_rate)).round() as u64)
+    }
+
+    /// Run the rack for `samples` samples and write each continuous output to a WAV file in
+    /// `dir` named after the output, returning the paths of the files written.
+    pub fn render_to_dir<P>(
+        &mut self,
+        samples: u64,
+        format: WavFormat,
+        dir: P,
+    ) -> io::Result<Vec<PathBuf>>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+
+        fs::create_dir_all(dir)?;
+
+        self.render(samples)
+            .into_iter()
+            .map(|(spec, file)| {
+                let path = dir.join(format!("{}.wav", spec));
+
+                file.write_wav(BufWriter::new(fs::File::create(&path)?), format)?;
+
+                Ok(path)
+            })
+            .collect()
+    }
+
+    #[inline]
+    pub fn rack(&self) -> &Rack<C, InputSpec, OutputSpec> {
+        &self.rack
+    }
+
+    #[inline]
+    pub fn rack_mut(&mut self) -> &mut Rack<C, InputSpec, OutputSpec> {
+        &mut self.rack
+    }
+
+    #[inline]
+    pub fn into_rack(self) -> Rack<C, InputSpec, OutputSpec> {
+        self.rack
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Context, Renderer};
+    use crate::{
+        components::PossiblyIter,
+        context::Transport,
+        files::AudioFile,
+        octahack_components::{
+            amplifier::{input, output, params, Amplifier},
+            midi_expander::MidiExpander,
+            synth::{self, Synth},
+            OctahackComponent,
+        },
+        Rack, Value, WireDst, WireSrc,
+    };
+    use std::marker::PhantomData;
+
+    crate::specs! {
+        mod mono {
+            Audio: crate::Value
+        }
+    }
+
+    #[test]
+    fn render_amplified_input() {
+        let mut rack = Rack::<OctahackComponent, mono::Specifier, mono::Specifier>::new();
+        let mut main = rack.main_mut();
+        let amp = main.push_component(Amplifier);
+
+        main.set_param(amp, params::Specifier::Amount, 0.5);
+        main.wire(
+            WireSrc::func_input(mono::Specifier::Audio),
+            WireDst::component_input(amp, input::Specifier::Input),
+        )
+        .unwrap();
+        main.wire(
+            WireSrc::component_output(amp, output::Specifier::Output),
+            WireDst::func_output(mono::Specifier::Audio),
+        )
+        .unwrap();
+
+        let mut renderer = Renderer::new(4, rack);
+        renderer.set_input(
+            mono::Specifier::Audio,
+            AudioFile::new(4, 1, vec![0.5, -1., 0.75]),
+        );
+
+        let outputs = renderer.render(4);
+        assert_eq!(outputs.len(), 1);
+
+        let (_, file) = &outputs[0];
+        assert_eq!(file.channels(), 1);
+        // The input is silent once the file ends
+        assert_eq!(
+            (0..file.frames())
+                .flat_map(|i| file.frame(i).to_vec())
+                .collect::<Vec<_>>(),
+            [0.25, -0.5, 0.375, 0.]
+        );
+    }
+
+    #[test]
+    fn tempo_change_keeps_beat() {
+        let rack = Rack::<OctahackComponent, mono::Specifier, mono::Specifier>::new();
+        let mut renderer = Renderer::new(4, rack);
+
+        // Two samples per beat at 120 BPM
+        renderer.render(4);
+        assert_eq!(renderer.transport().beat, 2.);
+
+        // Four samples per beat from here on, without moving the beats already played
+        renderer.set_tempo(60.);
+        assert_eq!(renderer.transport().beat, 2.);
+        renderer.render(4);
+        assert_eq!(renderer.transport().position, 8);
+        assert_eq!(renderer.transport().beat, 3.);
+    }
+
+    fn lfo_rack(feedback: bool) -> Rack<OctahackComponent, mono::Specifier, mono::Specifier> {
+        let mut rack = Rack::<OctahackComponent, mono::Specifier, mono::Specifier>::new();
+        let mut main = rack.main_mut();
+        let lfo = main.push_component(Synth::new());
+        let osc = main.push_component(Synth::new());
+        let amp = main.push_component(Amplifier);
+        // This doesn't process blocks itself, so it's run a frame at a time within each block
+        main.push_component(MidiExpander::new());
+
+        main.set_param(lfo, synth::params::Specifier::Freq, synth::freq(50));
+        main.wire(
+            WireSrc::func_input(mono::Specifier::Audio),
+            WireDst::component_input(osc, synth::input::Specifier::Pitch),
+        )
+        .unwrap();
+        main.wire(
+            WireSrc::component_output(osc, synth::output::Specifier::Saw),
+            WireDst::component_input(amp, input::Specifier::Input),
+        )
+        .unwrap();
+        main.wire(
+            WireSrc::component_output(lfo, synth::output::Specifier::Sine),
+            WireDst::component_param(amp, params::Specifier::Amount, 0.5),
+        )
+        .unwrap();
+        main.wire(
+            WireSrc::component_output(amp, output::Specifier::Output),
+            WireDst::func_output(mono::Specifier::Audio),
+        )
+        .unwrap();
+
+        if feedback {
+            // A wire into an earlier component means that the whole rack is run a frame at a time
+            main.wire(
+                WireSrc::component_output(amp, output::Specifier::Output),
+                WireDst::component_input(lfo, synth::input::Specifier::Pitch),
+            )
+            .unwrap();
+        }
+
+        rack
+    }
+
+    #[test]
+    fn blocks_match_frames() {
+        for &feedback in &[false, true] {
+            let mut rack = lfo_rack(feedback);
+            let sources = [0.];
+            let mut transport = Transport::default();
+            let expected = (0..105)
+                .flat_map(|_| {
+                    let ctx = Context::<mono::Specifier> {
+                        sources: &sources,
+                        midi: &[],
+                        sample_rate: 4410,
+                        transport,
+                        _marker: PhantomData,
+                    };
+
+                    transport.advance(4410);
+
+                    rack.update(&ctx);
+                    rack.output(mono::Specifier::Audio, &ctx)
+                        .and_then(|iter| PossiblyIter::<Value>::try_iter(iter).ok())
+                        .map(|iter| iter.collect::<Vec<_>>())
+                        .unwrap_or_default()
+                })
+                .collect::<Vec<_>>();
+
+            // Render in blocks that are smaller than the renderer's own, so that components carry
+            // on from one block to the next
+            let mut renderer = Renderer::new(4410, lfo_rack(feedback));
+            let actual = (0..15)
+                .flat_map(|_| {
+                    let (_, file) = renderer.render(7).remove(0);
+
+                    (0..file.frames())
+                        .flat_map(|i| file.frame(i).to_vec())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(actual.len(), 105);
+            assert_eq!(actual, expected);
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{
     components::PossiblyIter,
     context::Context,
-    rack::{marker, InternalWire, ParamValue, ParamWire, Wire},
+    rack::{marker, InternalWire, ParamValue, ParamWire, Wire, WireSrc},
     AnyInputSpec, AnyOutputSpec, Component, RefRuntimeSpecifier, RuntimeSpecifier, Value,
 };
 use std::{
@@ -28,6 +28,12 @@ pub trait ParamStorage {
 
     fn get(&self, spec: &Self::Specifier) -> (&dyn Any, &dyn Any);
     fn get_mut(&mut self, spec: &Self::Specifier) -> (&mut dyn Any, &mut dyn Any);
+
+    /// Write the natural value of a param in a form that can be read back by `parse`.
+    fn serialize(&self, spec: &Self::Specifier, f: &mut fmt::Formatter) -> fmt::Result;
+    /// Set the natural value of a param from the output of `serialize`, returning `false` and
+    /// leaving the param unchanged if `text` isn't a valid value for this param.
+    fn parse(&mut self, spec: &Self::Specifier, text: &str) -> bool;
 }
 
 pub trait Storage {
@@ -105,6 +111,20 @@ where
             Self::Right(val) => val.get_mut(spec),
         }
     }
+
+    fn serialize(&self, spec: &Self::Specifier, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Left(val) => val.serialize(spec, f),
+            Self::Right(val) => val.serialize(spec, f),
+        }
+    }
+
+    fn parse(&mut self, spec: &Self::Specifier, text: &str) -> bool {
+        match self {
+            Self::Left(val) => val.parse(spec, text),
+            Self::Right(val) => val.parse(spec, text),
+        }
+    }
 }
 
 impl<A, B> StorageMut for EitherStorage<A, B>
@@ -206,12 +226,46 @@ pub trait HasParamStorage: Sized {
     type Storage: ParamStorage<Specifier = Self>;
 }
 
-pub trait Param {
+pub trait Param: Sized {
     type Extra: Default;
 
     fn access<Ctx>(&self, storage: &Self::Extra, ctx: &Ctx) -> Self
     where
         Ctx: crate::components::anycomponent::AnyContext;
+
+    /// Like `access`, but with the natural value replaced, either by a function param that
+    /// forwards to this param or by the active scenes. Only `Value` params can be replaced, so by
+    /// default the new natural value is ignored.
+    fn access_with_natural<Ctx>(&self, _natural: Value, storage: &Self::Extra, ctx: &Ctx) -> Self
+    where
+        Ctx: crate::components::anycomponent::AnyContext,
+    {
+        self.access(storage, ctx)
+    }
+
+    /// Write this value so that it can be read back exactly with `parse`. Unlike the `Display`
+    /// used for the UI this must be lossless.
+    fn serialize(&self, f: &mut fmt::Formatter) -> fmt::Result;
+    fn parse(text: &str) -> Option<Self>;
+}
+
+/// Split `Name(a, b, ..)` into `Name` and its arguments.
+fn parse_call(text: &str) -> Option<(&str, Vec<&str>)> {
+    let text = text.trim();
+    let open = text.find('(')?;
+
+    if !text.ends_with(')') {
+        return None;
+    }
+
+    let args = &text[open + 1..text.len() - 1];
+    let args = if args.trim().is_empty() {
+        vec![]
+    } else {
+        args.split(',').map(str::trim).collect()
+    };
+
+    Some((text[..open].trim(), args))
 }
 
 impl Param for crate::MidiValue {
@@ -223,6 +277,43 @@ impl Param for crate::MidiValue {
     {
         *self
     }
+
+    fn serialize(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use crate::MidiValue::*;
+
+        match *self {
+            NoteOff(note, vel) => write!(f, "NoteOff({}, {})", u8::from(note), vel),
+            NoteOn(note, vel) => write!(f, "NoteOn({}, {})", u8::from(note), vel),
+            PolyphonicPressure(note, pressure) => {
+                write!(f, "PolyphonicPressure({}, {})", u8::from(note), pressure)
+            }
+            Controller(controller, value) => write!(f, "Controller({}, {})", controller, value),
+            ProgramChange(program) => write!(f, "ProgramChange({})", program),
+            ChannelPressure(pressure) => write!(f, "ChannelPressure({})", pressure),
+            PitchBend(lsb, msb) => write!(f, "PitchBend({}, {})", lsb, msb),
+        }
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        use crate::MidiValue::*;
+
+        let (name, args) = parse_call(text)?;
+        let args = args
+            .into_iter()
+            .map(|arg| arg.parse::<u8>().ok())
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(match (name, &args[..]) {
+            ("NoteOff", &[note, vel]) => NoteOff(note.into(), vel),
+            ("NoteOn", &[note, vel]) => NoteOn(note.into(), vel),
+            ("PolyphonicPressure", &[note, pressure]) => PolyphonicPressure(note.into(), pressure),
+            ("Controller", &[controller, value]) => Controller(controller, value),
+            ("ProgramChange", &[program]) => ProgramChange(program),
+            ("ChannelPressure", &[pressure]) => ChannelPressure(pressure),
+            ("PitchBend", &[lsb, msb]) => PitchBend(lsb, msb),
+            _ => return None,
+        })
+    }
 }
 
 // TODO: Can you wire this? How would that work?
@@ -235,25 +326,58 @@ impl<Kind> Param for Option<crate::context::FileId<Kind>> {
     {
         *self
     }
+
+    fn serialize(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            None => write!(f, "None"),
+            Some(id) => write!(f, "File({})", id.index()),
+        }
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        if text.trim() == "None" {
+            return Some(None);
+        }
+
+        match parse_call(text)? {
+            ("File", args) if args.len() == 1 => {
+                Some(Some(crate::context::FileId::new(args[0].parse().ok()?)))
+            }
+            _ => None,
+        }
+    }
 }
 
-fn access_value<Ctx>(val: Value, wire: Option<&ParamWire>, ctx: &Ctx) -> Value
+/// The average of all channels of an output this tick, which is how outputs are read when
+/// they're wired into a param. An output with no channels this tick counts as 0, the same as one
+/// that isn't wired.
+pub(crate) fn average_output<I>(output: Option<I>) -> Value
 where
-    Ctx: crate::components::anycomponent::AnyContext,
+    I: PossiblyIter<Value>,
 {
-    if let Some(ParamWire { src, cv }) = wire {
-        let amount = ctx.read_wire(*src);
+    output
+        .map(|output| {
+            let iter = PossiblyIter::<Value>::try_iter(output).unwrap_or_else(|_| unimplemented!());
 
-        let average_output_this_tick: Value = amount
-            .map(|amount| {
-                let mut iter =
-                    PossiblyIter::<Value>::try_iter(amount).unwrap_or_else(|_| unimplemented!());
-                let len = iter.len();
-                iter.sum::<Value>() / len as f64
-            })
-            .unwrap_or_default();
+            match iter.len() {
+                0 => 0.,
+                len => iter.sum::<Value>() / len as f64,
+            }
+        })
+        .unwrap_or_default()
+}
+
+/// The value of a param given its natural value and wire, where `read_wire` gets the average
+/// output of a wire this tick.
+pub(crate) fn access_value(
+    val: Value,
+    wire: Option<&ParamWire>,
+    read_wire: &impl Fn(WireSrc) -> Value,
+) -> Value {
+    if let Some(ParamWire { src, cv }) = wire {
+        let average_output_this_tick = read_wire(*src);
 
-        let cv = access_value(cv.natural_value, cv.wire.as_ref().map(|w| &**w), ctx);
+        let cv = access_value(cv.natural_value, cv.wire.as_ref().map(|w| &**w), read_wire);
 
         val + cv * average_output_this_tick
     } else {
@@ -268,7 +392,27 @@ impl Param for crate::Value {
     where
         Ctx: crate::components::anycomponent::AnyContext,
     {
-        access_value(*self, wire.as_ref(), ctx)
+        access_value(*self, wire.as_ref(), &|src| {
+            average_output(ctx.read_wire(src))
+        })
+    }
+
+    fn access_with_natural<Ctx>(&self, natural: Value, wire: &Self::Extra, ctx: &Ctx) -> Self
+    where
+        Ctx: crate::components::anycomponent::AnyContext,
+    {
+        access_value(natural, wire.as_ref(), &|src| {
+            average_output(ctx.read_wire(src))
+        })
+    }
+
+    fn serialize(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // `Debug` always prints enough digits to get back the same value
+        write!(f, "{:?}", self)
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        text.trim().parse().ok()
     }
 }
 
@@ -307,6 +451,14 @@ impl ParamStorage for EmptyParamStorage {
     fn get_mut(&mut self, _: &Self::Specifier) -> (&mut dyn Any, &mut dyn Any) {
         unreachable!()
     }
+
+    fn serialize(&self, _: &Self::Specifier, _: &mut fmt::Formatter) -> fmt::Result {
+        unreachable!()
+    }
+
+    fn parse(&mut self, _: &Self::Specifier, _: &str) -> bool {
+        unreachable!()
+    }
 }
 
 impl<V> Storage for EmptyStorage<V> {
@@ -329,6 +481,8 @@ where
     V: Key,
     V::Value: Param,
 {
+    /// The ID of the specifier for `V`
+    fn id() -> crate::SpecId;
     fn get(&self) -> (&V::Value, &<V::Value as Param>::Extra);
     fn get_mut(&mut self) -> (&mut V::Value, &mut <V::Value as Param>::Extra);
 }
@@ -342,9 +496,18 @@ pub trait Key {
     type Value;
 }
 
+/// Declares the specifiers of the inputs, outputs or params of a component, as `Key: Type`. The
+/// value type of each is `Type`'s `HasValueType::VALUE_TYPE` unless it's given explicitly, as in
+/// `Key: Type = ValueType::continuous()` for an output whose number of channels can change.
 #[macro_export]
 macro_rules! specs {
-    ($( $v:vis mod $modname:ident { $($key:ident : $value:ty),* } )*) => {
+    (@value_type $value:ty) => {
+        <$value as $crate::components::HasValueType>::VALUE_TYPE
+    };
+    (@value_type $value:ty, $value_type:expr) => {
+        $value_type
+    };
+    ($( $v:vis mod $modname:ident { $($key:ident : $value:ty $(= $value_type:expr)?),* } )*) => {
         $(
             $v mod $modname {
                 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -511,7 +674,7 @@ macro_rules! specs {
 
                     fn value_type(&self) -> $crate::ValueType {
                         [
-                            $( (stringify!($key), $crate::ValueType::mono()).1 ),*
+                            $( $crate::specs!(@value_type $value $(, $value_type)?) ),*
                         ][self.id()]
                     }
                 }
@@ -628,6 +791,10 @@ macro_rules! specs {
 
                 $(
                     impl $crate::params::ParamStorageGet<$key> for ParamsWithExtra {
+                        fn id() -> $crate::SpecId {
+                            $crate::RefRuntimeSpecifier::id(&Specifier::$key)
+                        }
+
                         fn get(&self) -> (
                             &<$key as $crate::params::Key>::Value,
                             &<<$key as $crate::params::Key>::Value as $crate::params::Param>::Extra
@@ -676,6 +843,32 @@ macro_rules! specs {
                             )*
                         }
                     }
+
+                    fn serialize(&self, spec: &Self::Specifier, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                        match spec {
+                            $(
+                                Specifier::$key => {
+                                    <$value as $crate::params::Param>::serialize(&self.params.$key, f)
+                                },
+                            )*
+                        }
+                    }
+
+                    fn parse(&mut self, spec: &Self::Specifier, text: &str) -> bool {
+                        match spec {
+                            $(
+                                Specifier::$key => {
+                                    match <$value as $crate::params::Param>::parse(text) {
+                                        Some(val) => {
+                                            self.params.$key = val;
+                                            true
+                                        }
+                                        None => false,
+                                    }
+                                },
+                            )*
+                        }
+                    }
                 }
             }
         )*
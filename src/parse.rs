@@ -0,0 +1,1163 @@
+use crate::{
+    components::{
+        anycomponent::{AllComponents, AnyUiElement},
+        EnumerateValues,
+    },
+    params::HasStorage,
+    rack::{
+        AsParam, ComponentId, DefsAndFuncHelperMut, FuncDef, FuncId, FuncInstanceMut, InternalWire,
+        Meta, Param,
+    },
+    scene::SceneId,
+    AnyComponent, AnyInputSpec, AnyOutputSpec, AnyParamSpec, Rack, RuntimeSpecifier, SpecId, Uid,
+    Value, ValueKind, WireDst, WireError, WireSrc,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The line that the error is on, starting at 1.
+    pub line: usize,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    /// The line isn't a valid definition, statement, param, input or output.
+    Syntax,
+    /// The text ended in the middle of a function.
+    UnexpectedEnd,
+    /// There's no `def Main` or there's more than one, or there's a `def Main`, a scene or a
+    /// crossfader in text that should only contain functions.
+    Main,
+    /// The same ID is defined more than once.
+    DuplicateId(String),
+    /// There's no component with this name in the component set.
+    UnknownComponent(String),
+    /// A function is called but never defined.
+    UnknownFunction(String),
+    /// A function calls itself, either directly or through other functions.
+    RecursiveFunction(String),
+    /// A wire reads from an ID that isn't a statement in this function.
+    UnknownId(String),
+    /// An input, output or param name doesn't exist.
+    UnknownName(String),
+    /// The value of a param isn't valid for the type of that param.
+    InvalidValue(String),
+    /// A function param is declared in `Main`, is declared twice or forwards to a param that
+    /// can't be forwarded to.
+    InvalidFuncParam(String),
+    /// Linked params have different types.
+    InvalidLink,
+    Wire(WireError),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+
+        match &self.kind {
+            ParseErrorKind::Syntax => write!(f, "syntax error"),
+            ParseErrorKind::UnexpectedEnd => write!(f, "unexpected end of text"),
+            ParseErrorKind::Main => write!(f, "unexpected or missing `def Main`"),
+            ParseErrorKind::DuplicateId(id) => write!(f, "{} is defined more than once", id),
+            ParseErrorKind::UnknownComponent(name) => write!(f, "unknown component {}", name),
+            ParseErrorKind::UnknownFunction(id) => write!(f, "{} is never defined", id),
+            ParseErrorKind::RecursiveFunction(id) => write!(f, "{} calls itself", id),
+            ParseErrorKind::UnknownId(id) => write!(f, "{} is not in this function", id),
+            ParseErrorKind::UnknownName(name) => {
+                write!(f, "unknown input, output or param {}", name)
+            }
+            ParseErrorKind::InvalidValue(value) => write!(f, "invalid value {}", value),
+            ParseErrorKind::InvalidFuncParam(name) => write!(f, "invalid function param {}", name),
+            ParseErrorKind::InvalidLink => write!(f, "linked params have different types"),
+            ParseErrorKind::Wire(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+enum WireText<'a> {
+    FuncInput(&'a str),
+    Output(ComponentId, &'a str),
+}
+
+struct CvText<'a> {
+    src: WireText<'a>,
+    value: Value,
+    cv: Option<Box<CvText<'a>>>,
+}
+
+struct ParamText<'a> {
+    line: usize,
+    name: &'a str,
+    value: &'a str,
+    wire: Option<CvText<'a>>,
+}
+
+struct FuncParamText<'a> {
+    line: usize,
+    name: &'a str,
+    targets: Vec<(ComponentId, &'a str)>,
+}
+
+struct LinkText<'a> {
+    line: usize,
+    params: Vec<(ComponentId, &'a str)>,
+}
+
+struct WireLine<'a> {
+    line: usize,
+    name: &'a str,
+    wire: Option<WireText<'a>>,
+}
+
+enum StatementKind<'a> {
+    Component(&'a str),
+    Call(FuncId),
+}
+
+struct StatementText<'a> {
+    line: usize,
+    id: ComponentId,
+    kind: StatementKind<'a>,
+    params: Vec<ParamText<'a>>,
+    inputs: Vec<WireLine<'a>>,
+}
+
+struct FuncText<'a> {
+    line: usize,
+    /// `None` for `Main`
+    id: Option<FuncId>,
+    params: Vec<FuncParamText<'a>>,
+    links: Vec<LinkText<'a>>,
+    statements: Vec<StatementText<'a>>,
+    returns: Vec<WireLine<'a>>,
+}
+
+struct SceneParamText<'a> {
+    line: usize,
+    id: ComponentId,
+    name: &'a str,
+    value: &'a str,
+}
+
+struct SceneText<'a> {
+    line: usize,
+    id: SceneId,
+    params: Vec<SceneParamText<'a>>,
+}
+
+/// The lines of `crossfader { .. }`, each of which is `Name = value`.
+struct CrossfaderText<'a> {
+    line: usize,
+    fields: Vec<(usize, &'a str, &'a str)>,
+}
+
+struct RackText<'a> {
+    funcs: Vec<FuncText<'a>>,
+    scenes: Vec<SceneText<'a>>,
+    crossfader: Option<CrossfaderText<'a>>,
+}
+
+fn parse_uid(hex: &str) -> Option<Uid> {
+    u32::from_str_radix(hex, 16).ok().map(Uid::new)
+}
+
+fn parse_func_id(text: &str) -> Option<FuncId> {
+    if text.starts_with("fn::") {
+        parse_uid(&text["fn::".len()..]).map(FuncId)
+    } else {
+        None
+    }
+}
+
+fn parse_scene_id(text: &str) -> Option<SceneId> {
+    if text.starts_with("scene::") {
+        parse_uid(&text["scene::".len()..]).map(SceneId)
+    } else {
+        None
+    }
+}
+
+fn parse_component_id(text: &str) -> Option<ComponentId> {
+    if text.starts_with('%') {
+        parse_uid(&text[1..]).map(ComponentId)
+    } else {
+        None
+    }
+}
+
+/// The ID of an input or output of a function, which are printed as `Input0`, `Output1` etc.
+fn any_spec_id(prefix: &str, name: &str) -> Option<SpecId> {
+    if name.starts_with(prefix) {
+        name[prefix.len()..].parse().ok()
+    } else {
+        None
+    }
+}
+
+fn strip_trailing_comma(text: &str) -> &str {
+    let text = text.trim();
+
+    if text.ends_with(',') {
+        &text[..text.len() - 1]
+    } else {
+        text
+    }
+}
+
+fn parse_def(line: &str) -> Option<Option<FuncId>> {
+    if !line.starts_with("def ") || !line.ends_with(':') {
+        return None;
+    }
+
+    match line["def ".len()..].split_whitespace().next()? {
+        "Main" => Some(None),
+        name => parse_func_id(name).map(Some),
+    }
+}
+
+/// Parse `%id: { outputs } = Name`, returning whether the statement is followed by a `{ .. }`
+/// block of params and inputs.
+fn parse_statement(line: usize, text: &str) -> Option<(StatementText<'_>, bool)> {
+    let colon = text.find(':')?;
+    let id = parse_component_id(text[..colon].trim())?;
+    let rest = &text[colon + 1..];
+    let name = rest[rest.find('=')? + 1..].trim();
+    let (name, has_body) = if name.ends_with('{') {
+        (name[..name.len() - 1].trim(), true)
+    } else {
+        (name, false)
+    };
+
+    if name.is_empty() {
+        return None;
+    }
+
+    let kind = match parse_func_id(name) {
+        Some(func_id) => StatementKind::Call(func_id),
+        None => StatementKind::Component(name),
+    };
+
+    Some((
+        StatementText {
+            line,
+            id,
+            kind,
+            params: vec![],
+            inputs: vec![],
+        },
+        has_body,
+    ))
+}
+
+fn parse_wire(text: &str) -> Option<WireText<'_>> {
+    if text.is_empty() || text.contains(char::is_whitespace) {
+        return None;
+    }
+
+    match text.find("->") {
+        Some(arrow) => Some(WireText::Output(
+            parse_component_id(&text[..arrow])?,
+            &text[arrow + "->".len()..],
+        )),
+        None => Some(WireText::FuncInput(text)),
+    }
+}
+
+/// Parse `Name = %id->Output`, `Name = Input` or `Name = NONE`.
+fn parse_wire_line(line: usize, text: &str) -> Option<WireLine<'_>> {
+    let text = strip_trailing_comma(text);
+    let eq = text.find('=')?;
+    let wire = match text[eq + 1..].trim() {
+        "NONE" => None,
+        wire => Some(parse_wire(wire)?),
+    };
+
+    Some(WireLine {
+        line,
+        name: text[..eq].trim(),
+        wire,
+    })
+}
+
+/// Parse `value` or `value + wire * cv`, where `cv` is either a number or another
+/// `(value + wire * cv)` in brackets.
+fn parse_param_expr(text: &str) -> Option<(&str, Option<CvText<'_>>)> {
+    let plus = match text.find(" + ") {
+        Some(plus) => plus,
+        None => return Some((text.trim(), None)),
+    };
+
+    let rest = &text[plus + " + ".len()..];
+    let star = rest.find(" * ")?;
+    let src = parse_wire(rest[..star].trim())?;
+    let cv = rest[star + " * ".len()..].trim();
+
+    let (value, cv) = if cv.starts_with('(') && cv.ends_with(')') {
+        let (value, cv) = parse_param_expr(&cv[1..cv.len() - 1])?;
+
+        (value.parse().ok()?, cv.map(Box::new))
+    } else {
+        (cv.parse().ok()?, None)
+    };
+
+    Some((text[..plus].trim(), Some(CvText { src, value, cv })))
+}
+
+/// Parse `$Name = expr`.
+fn parse_param(line: usize, text: &str) -> Option<ParamText<'_>> {
+    let text = strip_trailing_comma(&text[1..]);
+    let eq = text.find('=')?;
+    let (value, wire) = parse_param_expr(&text[eq + 1..])?;
+
+    Some(ParamText {
+        line,
+        name: text[..eq].trim(),
+        value,
+        wire,
+    })
+}
+
+/// Parse a list of params of statements, such as `%id.$Param, %id.$Param`.
+fn parse_param_list(text: &str) -> Option<Vec<(ComponentId, &str)>> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|target| !target.is_empty())
+        .map(|target| {
+            let dot = target.find('.')?;
+            let param = &target[dot + 1..];
+
+            if param.starts_with('$') {
+                Some((parse_component_id(&target[..dot])?, &param[1..]))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Parse `%id.$Param = value` in a scene.
+fn parse_scene_param(line: usize, text: &str) -> Option<SceneParamText<'_>> {
+    let text = strip_trailing_comma(text);
+    let eq = text.find('=')?;
+    let (id, name) = parse_param_list(&text[..eq])?.into_iter().next()?;
+
+    Some(SceneParamText {
+        line,
+        id,
+        name,
+        value: text[eq + 1..].trim(),
+    })
+}
+
+/// Parse `Name = value` in the crossfader.
+fn parse_field(line: usize, text: &str) -> Option<(usize, &str, &str)> {
+    let text = strip_trailing_comma(text);
+    let eq = text.find('=')?;
+
+    Some((line, text[..eq].trim(), text[eq + 1..].trim()))
+}
+
+/// Parse `$Name -> %id.$Param, %id.$Param`.
+fn parse_func_param(line: usize, text: &str) -> Option<FuncParamText<'_>> {
+    let text = &text[1..];
+    let arrow = text.find("->")?;
+
+    Some(FuncParamText {
+        line,
+        name: text[..arrow].trim(),
+        targets: parse_param_list(&text[arrow + "->".len()..])?,
+    })
+}
+
+fn parse_text(text: &str) -> Result<RackText<'_>, ParseError> {
+    enum State {
+        TopLevel,
+        Func,
+        Statement,
+        Return,
+        Scene,
+        Crossfader,
+    }
+
+    let mut funcs: Vec<FuncText<'_>> = vec![];
+    let mut scenes: Vec<SceneText<'_>> = vec![];
+    let mut crossfader: Option<CrossfaderText<'_>> = None;
+    let mut state = State::TopLevel;
+    let mut last_line = 0;
+
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let syntax_error = || ParseError {
+            line: line_no,
+            kind: ParseErrorKind::Syntax,
+        };
+
+        // Comments are only used for the human-readable version of param values
+        let line = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        }
+        .trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        last_line = line_no;
+
+        match state {
+            State::TopLevel if line.starts_with("scene ") && line.ends_with('{') => {
+                let id = parse_scene_id(line["scene ".len()..line.len() - 1].trim())
+                    .ok_or_else(syntax_error)?;
+
+                scenes.push(SceneText {
+                    line: line_no,
+                    id,
+                    params: vec![],
+                });
+                state = State::Scene;
+            }
+            State::TopLevel if line == "crossfader {" && crossfader.is_none() => {
+                crossfader = Some(CrossfaderText {
+                    line: line_no,
+                    fields: vec![],
+                });
+                state = State::Crossfader;
+            }
+            State::TopLevel => {
+                let id = parse_def(line).ok_or_else(syntax_error)?;
+
+                funcs.push(FuncText {
+                    line: line_no,
+                    id,
+                    params: vec![],
+                    links: vec![],
+                    statements: vec![],
+                    returns: vec![],
+                });
+                state = State::Func;
+            }
+            State::Func => {
+                let func = funcs.last_mut().unwrap();
+
+                if line == "return {" {
+                    state = State::Return;
+                } else if line.starts_with('$') {
+                    func.params
+                        .push(parse_func_param(line_no, line).ok_or_else(syntax_error)?);
+                } else if line.starts_with("link ") {
+                    func.links.push(LinkText {
+                        line: line_no,
+                        params: parse_param_list(&line["link ".len()..])
+                            .ok_or_else(syntax_error)?,
+                    });
+                } else {
+                    let (statement, has_body) =
+                        parse_statement(line_no, line).ok_or_else(syntax_error)?;
+
+                    func.statements.push(statement);
+                    if has_body {
+                        state = State::Statement;
+                    }
+                }
+            }
+            State::Statement => {
+                let statement = funcs.last_mut().unwrap().statements.last_mut().unwrap();
+
+                if line == "}" {
+                    state = State::Func;
+                } else if line.starts_with('$') {
+                    statement
+                        .params
+                        .push(parse_param(line_no, line).ok_or_else(syntax_error)?);
+                } else {
+                    statement
+                        .inputs
+                        .push(parse_wire_line(line_no, line).ok_or_else(syntax_error)?);
+                }
+            }
+            State::Return => {
+                if line == "}" {
+                    state = State::TopLevel;
+                } else {
+                    funcs
+                        .last_mut()
+                        .unwrap()
+                        .returns
+                        .push(parse_wire_line(line_no, line).ok_or_else(syntax_error)?);
+                }
+            }
+            State::Scene => {
+                if line == "}" {
+                    state = State::TopLevel;
+                } else {
+                    scenes
+                        .last_mut()
+                        .unwrap()
+                        .params
+                        .push(parse_scene_param(line_no, line).ok_or_else(syntax_error)?);
+                }
+            }
+            State::Crossfader => {
+                if line == "}" {
+                    state = State::TopLevel;
+                } else {
+                    crossfader
+                        .as_mut()
+                        .unwrap()
+                        .fields
+                        .push(parse_field(line_no, line).ok_or_else(syntax_error)?);
+                }
+            }
+        }
+    }
+
+    match state {
+        State::TopLevel => Ok(RackText {
+            funcs,
+            scenes,
+            crossfader,
+        }),
+        _ => Err(ParseError {
+            line: last_line,
+            kind: ParseErrorKind::UnexpectedEnd,
+        }),
+    }
+}
+
+/// The index of the param called `name` of the statement `id`, which is either a param of a
+/// component or a function param of a call.
+fn param_index<C, Def>(
+    func: &FuncInstanceMut<'_, C, Def>,
+    names: &HashMap<ComponentId, Option<[Vec<String>; 3]>>,
+    id: ComponentId,
+    name: &str,
+) -> Option<SpecId>
+where
+    C: AnyComponent,
+    Def: DefsAndFuncHelperMut,
+{
+    match names.get(&id)? {
+        Some(names) => names[2].iter().position(|n| n == name),
+        None => match func.meta_storage.get(id.0)? {
+            Meta::Function { func_id, .. } => func
+                .defs_and_func
+                .get(*func_id)
+                .params
+                .iter()
+                .position(|param| param.name() == name),
+            Meta::Component(_) => None,
+        },
+    }
+}
+
+fn wire_cv<P>(
+    mut param: P,
+    cv: &CvText<'_>,
+    resolve: &impl Fn(&WireText<'_>) -> Result<WireSrc, ParseError>,
+) -> Result<(), ParseError>
+where
+    P: Param<Value>,
+{
+    param.wire(resolve(&cv.src)?, cv.value);
+
+    match &cv.cv {
+        Some(inner) => wire_cv(param.cv().unwrap(), inner, resolve),
+        None => Ok(()),
+    }
+}
+
+/// Add the statements and wires in `text` to `func`, which should be empty. Every function that
+/// `text` calls must already have been built. File params are left empty unless `keep_files` is
+/// set, since they're indices into the files of the rack that the text was written from.
+fn build_func<C, InputSpec, OutputSpec, Def>(
+    mut func: FuncInstanceMut<'_, C, Def>,
+    text: &FuncText<'_>,
+    input_id: impl Fn(&str) -> Option<SpecId>,
+    output_id: impl Fn(&str) -> Option<SpecId>,
+    keep_files: bool,
+) -> Result<(), ParseError>
+where
+    InputSpec: RuntimeSpecifier,
+    OutputSpec: RuntimeSpecifier + HasStorage<InternalWire>,
+    C: AnyComponent + AllComponents + Clone,
+    for<'any> &'any C: AnyUiElement<'any>,
+    Def: DefsAndFuncHelperMut<FuncDef = FuncDef<InputSpec, OutputSpec>>,
+{
+    // The input, output and param names of each component, or `None` for function calls. We
+    // need to add every statement before we can wire anything, since wires can read from
+    // statements later in the function.
+    let mut names = HashMap::<ComponentId, Option<[Vec<String>; 3]>>::new();
+
+    for statement in &text.statements {
+        let err = |kind| ParseError {
+            line: statement.line,
+            kind,
+        };
+
+        if names.contains_key(&statement.id) || func.meta_storage.get(statement.id.0).is_some() {
+            return Err(err(ParseErrorKind::DuplicateId(statement.id.to_string())));
+        }
+
+        match statement.kind {
+            StatementKind::Component(name) => {
+                let component = C::all_components()
+                    .find(|component| component.name() == name)
+                    .ok_or_else(|| err(ParseErrorKind::UnknownComponent(name.to_string())))?;
+                let component_names = [
+                    component.input_names().map(|n| n.to_string()).collect(),
+                    component.output_names().map(|n| n.to_string()).collect(),
+                    component.param_names().map(|n| n.to_string()).collect(),
+                ];
+
+                names.insert(statement.id, Some(component_names));
+                func.push_component_with_id(statement.id, component);
+            }
+            StatementKind::Call(func_id) => {
+                names.insert(statement.id, None);
+                func.push_function_call_with_id(statement.id, func_id);
+            }
+        }
+    }
+
+    for param in &text.params {
+        let err = |kind| ParseError {
+            line: param.line,
+            kind,
+        };
+        let targets = param
+            .targets
+            .iter()
+            .map(|&(id, name)| {
+                let component_names = names
+                    .get(&id)
+                    .ok_or_else(|| err(ParseErrorKind::UnknownId(id.to_string())))?;
+
+                match component_names {
+                    Some(names) => names[2].iter().position(|n| n == name),
+                    None => None,
+                }
+                .map(|index| (id, AnyParamSpec(index)))
+                .ok_or_else(|| err(ParseErrorKind::UnknownName(name.to_string())))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        func.add_param(param.name, targets)
+            .ok_or_else(|| err(ParseErrorKind::InvalidFuncParam(param.name.to_string())))?;
+    }
+
+    let find =
+        |names: &Option<[Vec<String>; 3]>, which: usize, prefix: &str, name: &str| match names {
+            Some(names) => names[which].iter().position(|n| n == name),
+            None => any_spec_id(prefix, name),
+        };
+
+    let resolve = |line: usize, wire: &WireText<'_>| -> Result<WireSrc, ParseError> {
+        let err = |kind| ParseError { line, kind };
+
+        match *wire {
+            WireText::FuncInput(name) => input_id(name)
+                .map(|input| WireSrc::func_input(AnyInputSpec(input)))
+                .ok_or_else(|| err(ParseErrorKind::UnknownName(name.to_string()))),
+            WireText::Output(id, name) => {
+                let component_names = names
+                    .get(&id)
+                    .ok_or_else(|| err(ParseErrorKind::UnknownId(id.to_string())))?;
+
+                find(component_names, 1, "Output", name)
+                    .map(|output| WireSrc::component_output(id, AnyOutputSpec(output)))
+                    .ok_or_else(|| err(ParseErrorKind::UnknownName(name.to_string())))
+            }
+        }
+    };
+
+    for statement in &text.statements {
+        let component_names = &names[&statement.id];
+
+        for param in &statement.params {
+            let err = |kind| ParseError {
+                line: param.line,
+                kind,
+            };
+            let index = param_index(&func, &names, statement.id, param.name)
+                .ok_or_else(|| err(ParseErrorKind::UnknownName(param.name.to_string())))?;
+            let is_file = func
+                .param_type((statement.id, index))
+                .map_or(false, |(_, ty)| ty.kind == ValueKind::File);
+
+            if is_file && !keep_files {
+                continue;
+            }
+
+            if !func.parse_param(statement.id, AnyParamSpec(index), param.value) {
+                return Err(err(ParseErrorKind::InvalidValue(param.value.to_string())));
+            }
+
+            if let Some(cv) = &param.wire {
+                func.wire(
+                    resolve(param.line, &cv.src)?,
+                    WireDst::component_param(statement.id, AnyParamSpec(index), cv.value),
+                )
+                .map_err(|e| err(ParseErrorKind::Wire(e)))?;
+
+                if let Some(inner) = &cv.cv {
+                    let cv_param = func
+                        .param::<_, Value>(statement.id, AnyParamSpec(index))
+                        .as_param()
+                        .and_then(|param| param.cv())
+                        .unwrap();
+
+                    wire_cv(cv_param, inner, &|wire| resolve(param.line, wire))?;
+                }
+            }
+        }
+
+        for input in &statement.inputs {
+            let index =
+                find(component_names, 0, "Input", input.name).ok_or_else(|| ParseError {
+                    line: input.line,
+                    kind: ParseErrorKind::UnknownName(input.name.to_string()),
+                })?;
+
+            if let Some(wire) = &input.wire {
+                func.wire(
+                    resolve(input.line, wire)?,
+                    WireDst::component_input(statement.id, AnyInputSpec(index)),
+                )
+                .map_err(|e| ParseError {
+                    line: input.line,
+                    kind: ParseErrorKind::Wire(e),
+                })?;
+            }
+        }
+    }
+
+    // Linked params are written with the same value and wire, so we don't need to copy anything
+    for link in &text.links {
+        let err = |kind| ParseError {
+            line: link.line,
+            kind,
+        };
+        let params = link
+            .params
+            .iter()
+            .map(|&(id, name)| {
+                if !names.contains_key(&id) {
+                    return Err(err(ParseErrorKind::UnknownId(id.to_string())));
+                }
+
+                param_index(&func, &names, id, name)
+                    .map(|index| (id, index))
+                    .ok_or_else(|| err(ParseErrorKind::UnknownName(name.to_string())))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for &other in params.iter().skip(1) {
+            if !func.add_link(params[0], other) {
+                return Err(err(ParseErrorKind::InvalidLink));
+            }
+        }
+    }
+
+    for output in &text.returns {
+        let index = output_id(output.name).ok_or_else(|| ParseError {
+            line: output.line,
+            kind: ParseErrorKind::UnknownName(output.name.to_string()),
+        })?;
+
+        if let Some(wire) = &output.wire {
+            func.wire(
+                resolve(output.line, wire)?,
+                WireDst::func_output(AnyOutputSpec(index)),
+            )
+            .map_err(|e| ParseError {
+                line: output.line,
+                kind: ParseErrorKind::Wire(e),
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Build `id` after building every function that it calls.
+fn build_func_and_callees<C, InputSpec, OutputSpec>(
+    rack: &mut Rack<C, InputSpec, OutputSpec>,
+    id: FuncId,
+    defs: &HashMap<FuncId, &FuncText<'_>>,
+    built: &mut HashSet<FuncId>,
+    calling: &mut Vec<FuncId>,
+    keep_files: bool,
+) -> Result<(), ParseError>
+where
+    C: AnyComponent + AllComponents + Clone,
+    for<'any> &'any C: AnyUiElement<'any>,
+    OutputSpec: HasStorage<InternalWire>,
+{
+    if built.contains(&id) {
+        return Ok(());
+    }
+
+    let text = defs[&id];
+
+    calling.push(id);
+    for statement in &text.statements {
+        if let StatementKind::Call(callee) = statement.kind {
+            if calling.contains(&callee) {
+                return Err(ParseError {
+                    line: statement.line,
+                    kind: ParseErrorKind::RecursiveFunction(callee.to_string()),
+                });
+            }
+
+            build_func_and_callees(rack, callee, defs, built, calling, keep_files)?;
+        }
+    }
+    calling.pop();
+
+    build_func(
+        rack.func_mut(id),
+        text,
+        |name| any_spec_id("Input", name),
+        |name| any_spec_id("Output", name),
+        keep_files,
+    )?;
+    built.insert(id);
+
+    Ok(())
+}
+
+/// Add every function defined in `funcs` to `rack`, after checking that every function that
+/// they call is also defined in `funcs`. `Main` is skipped.
+fn build_funcs<C, InputSpec, OutputSpec>(
+    rack: &mut Rack<C, InputSpec, OutputSpec>,
+    funcs: &[FuncText<'_>],
+    keep_files: bool,
+) -> Result<(), ParseError>
+where
+    C: AnyComponent + AllComponents + Clone,
+    for<'any> &'any C: AnyUiElement<'any>,
+    OutputSpec: HasStorage<InternalWire>,
+{
+    let mut defs = HashMap::new();
+
+    for func in funcs {
+        if let Some(id) = func.id {
+            if defs.insert(id, func).is_some() {
+                return Err(ParseError {
+                    line: func.line,
+                    kind: ParseErrorKind::DuplicateId(id.to_string()),
+                });
+            }
+        }
+    }
+
+    for statement in funcs.iter().flat_map(|func| &func.statements) {
+        match statement.kind {
+            StatementKind::Call(callee) if !defs.contains_key(&callee) => {
+                return Err(ParseError {
+                    line: statement.line,
+                    kind: ParseErrorKind::UnknownFunction(callee.to_string()),
+                })
+            }
+            _ => {}
+        }
+    }
+
+    for &id in defs.keys() {
+        rack.new_func_with_id(id);
+    }
+
+    let mut built = HashSet::new();
+
+    for id in funcs.iter().filter_map(|func| func.id) {
+        build_func_and_callees(rack, id, &defs, &mut built, &mut vec![], keep_files)?;
+    }
+
+    Ok(())
+}
+
+impl FuncText<'_> {
+    /// Replace every function and statement ID defined or used in this function.
+    fn map_ids(&mut self, mut map: impl FnMut(Uid) -> Uid) {
+        let mut map_wire = |wire: &mut WireText<'_>| {
+            if let WireText::Output(id, _) = wire {
+                id.0 = map(id.0);
+            }
+        };
+
+        for statement in &mut self.statements {
+            for param in &mut statement.params {
+                let mut cv = param.wire.as_mut();
+
+                while let Some(wire) = cv {
+                    map_wire(&mut wire.src);
+                    cv = wire.cv.as_mut().map(|cv| &mut **cv);
+                }
+            }
+
+            for input in &mut statement.inputs {
+                if let Some(wire) = &mut input.wire {
+                    map_wire(wire);
+                }
+            }
+        }
+
+        for output in &mut self.returns {
+            if let Some(wire) = &mut output.wire {
+                map_wire(wire);
+            }
+        }
+
+        for param in &mut self.params {
+            for (id, _) in &mut param.targets {
+                id.0 = map(id.0);
+            }
+        }
+
+        for link in &mut self.links {
+            for (id, _) in &mut link.params {
+                id.0 = map(id.0);
+            }
+        }
+
+        for statement in &mut self.statements {
+            statement.id.0 = map(statement.id.0);
+
+            if let StatementKind::Call(func_id) = &mut statement.kind {
+                func_id.0 = map(func_id.0);
+            }
+        }
+
+        if let Some(id) = &mut self.id {
+            id.0 = map(id.0);
+        }
+    }
+}
+
+impl<C, InputSpec, OutputSpec> Rack<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent + AllComponents + Clone,
+    for<'any> &'any C: AnyUiElement<'any>,
+    OutputSpec: RuntimeSpecifier + HasStorage<InternalWire>,
+{
+    /// Load functions saved with `export_func` into this rack, returning the ID of the function
+    /// that was exported. Every function and component is given a new ID, so the same file can be
+    /// imported more than once. File params are left empty, since the files they chose belong to
+    /// the rack that the function was exported from. If the text can't be loaded then the rack is
+    /// left unchanged.
+    pub fn import_func(&mut self, text: &str) -> Result<FuncId, ParseError> {
+        let RackText {
+            mut funcs,
+            scenes,
+            crossfader,
+        } = parse_text(text)?;
+        let rack_only = funcs
+            .iter()
+            .filter(|func| func.id.is_none())
+            .map(|main| main.line)
+            .chain(scenes.iter().map(|scene| scene.line))
+            .chain(crossfader.iter().map(|crossfader| crossfader.line))
+            .min();
+
+        if let Some(line) = rack_only {
+            return Err(ParseError {
+                line,
+                kind: ParseErrorKind::Main,
+            });
+        }
+
+        let mut ids = HashMap::new();
+        for func in &mut funcs {
+            func.map_ids(|uid| *ids.entry(uid).or_insert_with(|| self.uid_gen.next()));
+        }
+
+        // The exported function is always written last
+        let root = funcs.last().and_then(|func| func.id).ok_or(ParseError {
+            line: 1,
+            kind: ParseErrorKind::Main,
+        })?;
+
+        // Loading the functions is a single edit, so that it can be undone as a whole and so that
+        // we can roll it back if it fails partway through.
+        self.history.begin();
+        if let Err(err) = build_funcs(self, &funcs, false) {
+            self.abort_edit();
+            return Err(err);
+        }
+        self.history.end();
+
+        Ok(root)
+    }
+}
+
+/// Add the scenes in `scenes` to `rack`, keeping their IDs. Every statement whose params they
+/// store must already have been built.
+fn build_scenes<C, InputSpec, OutputSpec>(
+    rack: &mut Rack<C, InputSpec, OutputSpec>,
+    scenes: &[SceneText<'_>],
+) -> Result<(), ParseError>
+where
+    C: AnyComponent,
+    for<'any> &'any C: AnyUiElement<'any>,
+    OutputSpec: HasStorage<InternalWire>,
+{
+    for scene in scenes {
+        if !rack.new_scene_with_id(scene.id) {
+            return Err(ParseError {
+                line: scene.line,
+                kind: ParseErrorKind::DuplicateId(scene.id.to_string()),
+            });
+        }
+
+        for param in &scene.params {
+            let err = |kind| ParseError {
+                line: param.line,
+                kind,
+            };
+            let index = match rack.meta_storage.get(param.id.0) {
+                Some(Meta::Component(_)) => rack.state_storage[&param.id.0]
+                    .param_names()
+                    .position(|name| name.to_string() == param.name),
+                Some(Meta::Function { func_id, .. }) => rack.funcs[func_id.0]
+                    .params
+                    .iter()
+                    .position(|func_param| func_param.name() == param.name),
+                None => return Err(err(ParseErrorKind::UnknownId(param.id.to_string()))),
+            }
+            .ok_or_else(|| err(ParseErrorKind::UnknownName(param.name.to_string())))?;
+            let set = param.value.parse().ok().map_or(false, |value| {
+                rack.set_scene_param(scene.id, param.id, AnyParamSpec(index), value)
+            });
+
+            if !set {
+                return Err(err(ParseErrorKind::InvalidValue(param.value.to_string())));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Assign scenes to the crossfader and set its position and input. The scenes must already have
+/// been built.
+fn build_crossfader<C, InputSpec, OutputSpec>(
+    rack: &mut Rack<C, InputSpec, OutputSpec>,
+    text: &CrossfaderText<'_>,
+) -> Result<(), ParseError>
+where
+    C: AnyComponent,
+    InputSpec: EnumerateValues,
+    OutputSpec: HasStorage<InternalWire>,
+{
+    for &(line, name, value) in &text.fields {
+        let err = |kind| ParseError { line, kind };
+        let invalid = || err(ParseErrorKind::InvalidValue(value.to_string()));
+
+        match name {
+            "A" | "B" => {
+                let scene = match value {
+                    "NONE" => None,
+                    _ => Some(parse_scene_id(value).ok_or_else(invalid)?),
+                };
+                let assigned = if name == "A" {
+                    rack.set_scene_a(scene)
+                } else {
+                    rack.set_scene_b(scene)
+                };
+
+                if !assigned {
+                    return Err(err(ParseErrorKind::UnknownId(value.to_string())));
+                }
+            }
+            "$Position" => rack.set_crossfader(value.parse().map_err(|_| invalid())?),
+            "Input" => {
+                let input = match value {
+                    "NONE" => None,
+                    _ => Some(
+                        InputSpec::values()
+                            .find(|spec| spec.to_string() == value)
+                            .map(|spec| InputSpec::from_id(spec.id()))
+                            .ok_or_else(|| err(ParseErrorKind::UnknownName(value.to_string())))?,
+                    ),
+                };
+
+                rack.wire_crossfader(input)
+                    .map_err(|e| err(ParseErrorKind::Wire(e)))?;
+            }
+            _ => return Err(err(ParseErrorKind::UnknownName(name.to_string()))),
+        }
+    }
+
+    Ok(())
+}
+
+/// Load a rack from the format printed by its `Display` implementation. IDs are kept as they
+/// are in the text, so printing the loaded rack gives back the same text. The rack's files aren't
+/// part of the text, and file params are saved as the index of their file in `Rack::files`, so
+/// the files need to be added to the loaded rack in the same order as they were to the original.
+impl<C, InputSpec, OutputSpec> FromStr for Rack<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent + AllComponents + Clone,
+    for<'any> &'any C: AnyUiElement<'any>,
+    InputSpec: EnumerateValues,
+    OutputSpec: EnumerateValues + HasStorage<InternalWire>,
+    OutputSpec::Storage: Default,
+{
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let RackText {
+            funcs,
+            scenes,
+            crossfader,
+        } = parse_text(text)?;
+        let mut mains = funcs.iter().filter(|func| func.id.is_none());
+
+        let main = mains.next().ok_or(ParseError {
+            line: 1,
+            kind: ParseErrorKind::Main,
+        })?;
+
+        if let Some(other) = mains.next() {
+            return Err(ParseError {
+                line: other.line,
+                kind: ParseErrorKind::Main,
+            });
+        }
+
+        let mut rack = Rack::new();
+
+        build_funcs(&mut rack, &funcs, true)?;
+        build_func(
+            rack.main_mut(),
+            main,
+            |name| {
+                InputSpec::values()
+                    .find(|spec| spec.to_string() == name)
+                    .map(|spec| spec.id())
+            },
+            |name| {
+                OutputSpec::values()
+                    .find(|spec| spec.to_string() == name)
+                    .map(|spec| spec.id())
+            },
+            true,
+        )?;
+        build_scenes(&mut rack, &scenes)?;
+        if let Some(crossfader) = &crossfader {
+            build_crossfader(&mut rack, crossfader)?;
+        }
+        rack.clear_history();
+
+        Ok(rack)
+    }
+}
@@ -0,0 +1,372 @@
+//! Edits that wait for a beat boundary before taking effect, so that a change made while the
+//! rack is playing lands in time with the music. Queued edits are applied at the start of the
+//! first tick that falls on or after their boundary, and go into the undo history like any other
+//! edit. Since beats are counted by the transport, queued edits wait while it's stopped.
+
+use crate::{
+    params::HasStorage,
+    rack::{
+        ComponentId, DefsAndFuncHelperMut, FuncDef, FuncId, FuncInstanceMut, InternalWire, Meta,
+    },
+    AnyComponent, AnyParamSpec, Rack, RuntimeSpecifier, Value, ValueType, WireDst, WireError,
+    WireSrc,
+};
+use std::{any::TypeId, mem, num::NonZeroU32};
+
+/// When edits made with `Rack::edit_quantised` take effect.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Quantise {
+    /// Edits take effect immediately.
+    Off,
+    /// The next edit waits for the next multiple of this many beats, and edits after that take
+    /// effect immediately.
+    Next(NonZeroU32),
+    /// Every edit waits for the next multiple of this many beats.
+    All(NonZeroU32),
+}
+
+impl Default for Quantise {
+    fn default() -> Self {
+        Quantise::Off
+    }
+}
+
+/// An edit that can be queued to take effect on a beat boundary.
+pub enum QuantisedEdit<C> {
+    /// Wire `src` into `dst`, or disconnect `dst` if `src` is `None`.
+    Wire { dst: WireDst, src: Option<WireSrc> },
+    /// Set the natural value of a `Value` param, as with `FuncInstanceMut::set_param`.
+    SetParam {
+        component: ComponentId,
+        param: AnyParamSpec,
+        value: Value,
+    },
+    /// Replace a component, as with `FuncInstanceMut::replace_component`.
+    ReplaceComponent { component: ComponentId, new: C },
+}
+
+impl<C> QuantisedEdit<C>
+where
+    C: AnyComponent + Clone,
+{
+    /// Check that this edit can be made to `func`.
+    fn check<InputSpec, OutputSpec, Def>(
+        &self,
+        func: &FuncInstanceMut<'_, C, Def>,
+    ) -> Result<(), WireError>
+    where
+        InputSpec: RuntimeSpecifier,
+        OutputSpec: RuntimeSpecifier + HasStorage<InternalWire>,
+        Def: DefsAndFuncHelperMut<FuncDef = FuncDef<InputSpec, OutputSpec>>,
+    {
+        match self {
+            QuantisedEdit::Wire { dst, src } => func.check_wire(*src, dst),
+            QuantisedEdit::SetParam {
+                component, param, ..
+            } => match func.param_type((*component, param.0)) {
+                Some((type_id, _)) if type_id == TypeId::of::<Value>() => Ok(()),
+                Some((_, dst)) => Err(WireError::TypeMismatch {
+                    src: ValueType::continuous(),
+                    dst,
+                }),
+                None if func.def().statements.contains(component) => {
+                    Err(WireError::UnknownSpecifier(param.0))
+                }
+                None => Err(WireError::UnknownComponent(*component)),
+            },
+            QuantisedEdit::ReplaceComponent { component, .. } => {
+                match func.meta_storage.get(component.0) {
+                    Some(Meta::Component(_)) if func.def().statements.contains(component) => Ok(()),
+                    _ => Err(WireError::UnknownComponent(*component)),
+                }
+            }
+        }
+    }
+
+    /// Make this edit to `func`, or do nothing if it can no longer be made because the function
+    /// was changed since the edit was queued.
+    fn apply<InputSpec, OutputSpec, Def>(self, mut func: FuncInstanceMut<'_, C, Def>)
+    where
+        InputSpec: RuntimeSpecifier,
+        OutputSpec: RuntimeSpecifier + HasStorage<InternalWire>,
+        Def: DefsAndFuncHelperMut<FuncDef = FuncDef<InputSpec, OutputSpec>>,
+    {
+        if self.check(&func).is_err() {
+            return;
+        }
+
+        match self {
+            QuantisedEdit::Wire {
+                dst,
+                src: Some(src),
+            } => {
+                let _ = func.wire(src, dst);
+            }
+            QuantisedEdit::Wire { dst, src: None } => {
+                let _ = func.unwire(dst);
+            }
+            QuantisedEdit::SetParam {
+                component,
+                param,
+                value,
+            } => func.set_param(component, param, value),
+            QuantisedEdit::ReplaceComponent { component, new } => {
+                func.replace_component(component, new);
+            }
+        }
+    }
+}
+
+/// An edit to a function, or to `Main` if `func` is `None`, that's waiting for its beat.
+struct Pending<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent,
+    OutputSpec: HasStorage<InternalWire>,
+{
+    beat: Value,
+    func: Option<FuncId>,
+    edit: QuantisedEdit<C>,
+    /// `Rack::update` can't name the bounds that editing a function needs, so we get them when
+    /// the edit is queued instead.
+    apply: fn(&mut Rack<C, InputSpec, OutputSpec>, Option<FuncId>, QuantisedEdit<C>),
+}
+
+pub struct Quantiser<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent,
+    OutputSpec: HasStorage<InternalWire>,
+{
+    mode: Quantise,
+    /// The beat position of the last tick, which the next boundary is counted from.
+    beat: Value,
+    pending: Vec<Pending<C, InputSpec, OutputSpec>>,
+}
+
+impl<C, InputSpec, OutputSpec> Default for Quantiser<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent,
+    OutputSpec: HasStorage<InternalWire>,
+{
+    fn default() -> Self {
+        Quantiser {
+            mode: Quantise::Off,
+            beat: 0.,
+            pending: vec![],
+        }
+    }
+}
+
+fn apply_edit<C, InputSpec, OutputSpec>(
+    rack: &mut Rack<C, InputSpec, OutputSpec>,
+    func: Option<FuncId>,
+    edit: QuantisedEdit<C>,
+) where
+    C: AnyComponent + Clone,
+    InputSpec: RuntimeSpecifier,
+    OutputSpec: RuntimeSpecifier + HasStorage<InternalWire>,
+{
+    match func {
+        None => edit.apply(rack.main_mut()),
+        // The function may have been removed by undoing its creation
+        Some(id) if rack.funcs.get(id.0).is_some() => edit.apply(rack.func_mut(id)),
+        Some(_) => {}
+    }
+}
+
+impl<C, InputSpec, OutputSpec> Rack<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent,
+    OutputSpec: HasStorage<InternalWire>,
+{
+    #[inline]
+    pub fn set_quantise(&mut self, mode: Quantise) {
+        self.quantiser.mode = mode;
+    }
+
+    #[inline]
+    pub fn quantise(&self) -> Quantise {
+        self.quantiser.mode
+    }
+
+    /// The number of edits waiting for their beat.
+    #[inline]
+    pub fn queued_edits(&self) -> usize {
+        self.quantiser.pending.len()
+    }
+
+    /// Drop every edit that's waiting for its beat.
+    #[inline]
+    pub fn clear_queued_edits(&mut self) {
+        self.quantiser.pending.clear();
+    }
+
+    /// Whether any queued edit is due by `beat`.
+    pub(crate) fn edits_due(&self, beat: Value) -> bool {
+        self.quantiser.pending.iter().any(|edit| edit.beat <= beat)
+    }
+
+    /// Apply the queued edits whose beat is at or before `beat`, in the order they were queued.
+    pub(crate) fn apply_due_edits(&mut self, beat: Value) {
+        self.quantiser.beat = beat;
+
+        if !self.edits_due(beat) {
+            return;
+        }
+
+        let (due, pending) = mem::take(&mut self.quantiser.pending)
+            .into_iter()
+            .partition::<Vec<_>, _>(|edit| edit.beat <= beat);
+        self.quantiser.pending = pending;
+
+        for edit in due {
+            (edit.apply)(self, edit.func, edit.edit);
+        }
+    }
+}
+
+impl<C, InputSpec, OutputSpec> Rack<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent + Clone,
+    InputSpec: RuntimeSpecifier,
+    OutputSpec: RuntimeSpecifier + HasStorage<InternalWire>,
+{
+    /// Make an edit to a function, or to `Main` if `func` is `None`, either immediately or at the
+    /// next beat boundary depending on the current `Quantise` mode. Returns an error without
+    /// queueing anything if the edit can't be made. If a queued edit can no longer be made by
+    /// the time its beat comes, for example because its component was removed, it's dropped.
+    pub fn edit_quantised(
+        &mut self,
+        func: Option<FuncId>,
+        edit: QuantisedEdit<C>,
+    ) -> Result<(), WireError> {
+        match func {
+            None => edit.check(&self.main_mut())?,
+            Some(id) => edit.check(&self.func_mut(id))?,
+        }
+
+        let every = match self.quantiser.mode {
+            Quantise::Off => {
+                apply_edit(self, func, edit);
+                return Ok(());
+            }
+            Quantise::Next(every) => {
+                self.quantiser.mode = Quantise::Off;
+                every
+            }
+            Quantise::All(every) => every,
+        };
+        let every = every.get() as Value;
+
+        self.quantiser.pending.push(Pending {
+            beat: ((self.quantiser.beat / every).floor() + 1.) * every,
+            func,
+            edit,
+            apply: apply_edit::<C, InputSpec, OutputSpec>,
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Quantise, QuantisedEdit};
+    use crate::{
+        components::ValueIterImplHelper,
+        context::{ContextMeta, GetFunctionParam, Transport},
+        octahack_components::{
+            amplifier::{params::Specifier::Amount, Amplifier},
+            OctahackComponent,
+        },
+        AnyInputSpec, AnyOutputSpec, AnyParamSpec, Rack, RefRuntimeSpecifier, Value,
+    };
+    use std::num::NonZeroU32;
+
+    type TestRack = Rack<OctahackComponent, AnyInputSpec, AnyOutputSpec>;
+
+    /// Four samples per second at 120 BPM, so each beat is two samples long.
+    struct TestCtx(u64);
+
+    impl ContextMeta for TestCtx {
+        fn sample_rate(&self) -> u32 {
+            4
+        }
+
+        fn transport(&self) -> Transport {
+            let mut transport = Transport::default();
+
+            transport.seek(self.0, self.sample_rate());
+            transport
+        }
+    }
+
+    impl GetFunctionParam for TestCtx {
+        type InputSpec = AnyInputSpec;
+        type Iter = <Value as ValueIterImplHelper<std::vec::IntoIter<Value>>>::AnyIter;
+
+        fn input(&self, _: AnyInputSpec) -> Option<Self::Iter> {
+            None
+        }
+    }
+
+    #[test]
+    fn quantised_edits() {
+        let mut rack = TestRack::new();
+        let amp = rack.main_mut().push_component(Amplifier);
+        let amount = |rack: &mut TestRack| {
+            *rack
+                .main_mut()
+                .param::<_, Value>(amp, Amount)
+                .as_mut()
+                .value()
+        };
+        let set = |value| QuantisedEdit::SetParam {
+            component: amp,
+            param: AnyParamSpec(Amount.id()),
+            value,
+        };
+        let four = NonZeroU32::new(4).unwrap();
+
+        rack.edit_quantised(None, set(0.25)).unwrap();
+        assert_eq!(amount(&mut rack), 0.25);
+
+        // Beat 1.5, so the next 4-beat boundary is at beat 4, sample 8
+        rack.update(&TestCtx(3));
+        rack.set_quantise(Quantise::Next(four));
+        rack.edit_quantised(None, set(0.5)).unwrap();
+        assert_eq!(rack.quantise(), Quantise::Off);
+        rack.edit_quantised(None, set(0.75)).unwrap();
+        assert_eq!(amount(&mut rack), 0.75);
+
+        for position in 4..8 {
+            rack.update(&TestCtx(position));
+            assert_eq!(amount(&mut rack), 0.75);
+        }
+        rack.update(&TestCtx(8));
+        assert_eq!(amount(&mut rack), 0.5);
+        assert_eq!(rack.queued_edits(), 0);
+
+        // Exactly on a boundary, so the edits wait for the next one
+        rack.set_quantise(Quantise::All(four));
+        rack.edit_quantised(None, set(1.)).unwrap();
+        rack.edit_quantised(None, set(0.)).unwrap();
+        assert_eq!(rack.queued_edits(), 2);
+        rack.update(&TestCtx(15));
+        assert_eq!(amount(&mut rack), 0.5);
+        rack.update(&TestCtx(16));
+        assert_eq!(amount(&mut rack), 0.);
+
+        assert!(rack.undo());
+        assert_eq!(amount(&mut rack), 1.);
+
+        // Edits that can no longer be made by the time their beat comes are dropped
+        let replace = QuantisedEdit::ReplaceComponent {
+            component: amp,
+            new: Amplifier.into(),
+        };
+        rack.edit_quantised(None, replace).unwrap();
+        rack.main_mut().remove_component(amp);
+        rack.update(&TestCtx(24));
+        assert_eq!(rack.queued_edits(), 0);
+    }
+}
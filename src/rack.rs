@@ -1,17 +1,30 @@
 use crate::{
+    block::{BlockIter, BlockOutputs},
     components::{
-        anycomponent::{AnyContext, AnyMeta, AnyUiElement, AnyUiElementDisplayParamValue},
+        anycomponent::{
+            AnyBlockContext, AnyContext, AnyMeta, AnyUiElement, AnyUiElementDisplayParamValue,
+        },
         EnumerateValues, PossiblyEither, PossiblyIter,
     },
-    context::{ContextMeta, GetFunctionParam},
-    params::{EitherStorage, HasStorage, Key, ParamStorage, Storage, StorageMut},
+    context::{ContextMeta, ContextMetaExt, GetFunctionParam, Transport},
+    display::FnDisplay,
+    files::Files,
+    history::{Change, History},
+    params::{
+        access_value, average_output, EitherStorage, HasStorage, Key, ParamStorage, Storage,
+        StorageMut,
+    },
+    quantise::Quantiser,
+    scene::{SceneMix, Scenes},
     AnyComponent, AnyInputSpec, AnyOutputSpec, AnyParamSpec, MidiValue, RefRuntimeSpecifier,
-    RuntimeSpecifier, SpecId, Uid, UidGen, UidMap, Value, XOrHasher,
+    RuntimeSpecifier, SpecId, Types, Uid, UidGen, UidMap, Value, ValueKind, ValueType, XOrHasher,
 };
 use itertools::Either;
 use std::{
+    any::{Any, TypeId},
     fmt,
     marker::PhantomData,
+    mem,
     ops::{Deref, DerefMut, Index, IndexMut},
 };
 
@@ -143,7 +156,6 @@ pub struct ParamWire<V = Value> {
     pub cv: ParamValue<V, Box<ParamWire<V>>>,
 }
 
-// TODO: Scenes
 #[derive(Debug, Clone, PartialEq)]
 pub struct ParamValue<V = Value, P = ParamWire<V>> {
     pub natural_value: V,
@@ -177,7 +189,9 @@ where
     Component(ComponentMeta<C>),
     Function {
         func_id: FuncId,
-        // params: TODO
+        /// The value of each of the function's params for this call, in the same order as
+        /// `FuncDef::params`.
+        params: Vec<ParamValue>,
         inputs: <AnyInputSpec as HasStorage<InternalWire>>::Storage,
     },
 }
@@ -193,7 +207,7 @@ where
         }
     }
 
-    fn inputs_mut(
+    pub(crate) fn inputs_mut(
         &mut self,
     ) -> impl StorageMut<Specifier = AnyInputSpec, Inner = InternalWire> + '_ {
         match self {
@@ -220,6 +234,74 @@ where
             _ => None,
         }
     }
+
+    /// The natural value and wire of a param of a component, or of a function param of a call.
+    pub(crate) fn param_mut(
+        &mut self,
+        spec: &AnyParamSpec,
+    ) -> Option<(&mut dyn Any, &mut dyn Any)> {
+        match self {
+            Self::Component(cmeta) => Some(cmeta.params.get_mut(spec)),
+            Self::Function { params, .. } => params.get_mut(spec.0).map(|param| {
+                (
+                    &mut param.natural_value as &mut dyn Any,
+                    &mut param.wire as &mut dyn Any,
+                )
+            }),
+        }
+    }
+
+    /// The natural value of a param, if it's a `Value` param.
+    pub(crate) fn param_value(&self, spec: SpecId) -> Option<Value> {
+        match self {
+            Self::Component(cmeta) => cmeta
+                .params
+                .get(&AnyParamSpec(spec))
+                .0
+                .downcast_ref::<Value>()
+                .copied(),
+            Self::Function { params, .. } => params.get(spec).map(|param| param.natural_value),
+        }
+    }
+}
+
+/// A param of a function, which can be set and wired on each call to the function and which
+/// forwards its value to params of components inside the function. A forwarded param uses the
+/// value from the call in place of its natural value, but keeps its own wire.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncParam {
+    pub(crate) name: String,
+    pub(crate) targets: Vec<(ComponentId, SpecId)>,
+}
+
+impl FuncParam {
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn targets(&self) -> impl ExactSizeIterator<Item = (ComponentId, AnyParamSpec)> + '_ {
+        self.targets
+            .iter()
+            .map(|&(id, spec)| (id, AnyParamSpec(spec)))
+    }
+}
+
+/// A group of params in the same function which are locked together, so that setting or wiring
+/// any one of them sets or wires all of them. A group always has at least two params.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamLink {
+    pub(crate) params: Vec<(ComponentId, SpecId)>,
+}
+
+impl ParamLink {
+    #[inline]
+    pub fn params(&self) -> impl ExactSizeIterator<Item = (ComponentId, AnyParamSpec)> + '_ {
+        self.params
+            .iter()
+            .map(|&(id, spec)| (id, AnyParamSpec(spec)))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -236,6 +318,16 @@ impl<M> MapWithPathGen<M> {
             map,
         }
     }
+
+    /// The key in the underlying map of the value for `uid` at this path.
+    #[inline]
+    fn key(&self, uid: Uid) -> Uid {
+        use std::hash::{Hash, Hasher};
+
+        let mut new_path = self.path.clone();
+        uid.hash(&mut new_path);
+        Uid::new(new_path.finish() as u32)
+    }
 }
 
 pub type MapWithPath<'a, T> = MapWithPathGen<&'a UidMap<T>>;
@@ -308,11 +400,37 @@ impl<'a, T> MapWithPathMut<'a, T> {
 impl<T> MapWithPathMut<'_, T> {
     #[inline]
     fn insert(&mut self, uid: Uid, val: T) -> Option<T> {
-        use std::hash::{Hash, Hasher};
+        let key = self.key(uid);
+        self.map.insert(key, val)
+    }
 
-        let mut new_path = self.path.clone();
-        uid.hash(&mut new_path);
-        self.map.insert(Uid::new(new_path.finish() as u32), val)
+    #[inline]
+    fn remove(&mut self, uid: Uid) -> Option<T> {
+        let key = self.key(uid);
+        self.map.remove(key)
+    }
+}
+
+impl<C> MapWithPathMut<'_, C>
+where
+    C: AnyComponent,
+{
+    /// Insert the state of a component, returning the change that undoes it.
+    #[inline]
+    fn insert_saved(&mut self, uid: Uid, val: C) -> Change<C> {
+        Change::State {
+            key: self.key(uid),
+            state: self.insert(uid, val),
+        }
+    }
+
+    /// Remove the state of a component, returning the change that undoes it.
+    #[inline]
+    fn remove_saved(&mut self, uid: Uid) -> Change<C> {
+        Change::State {
+            key: self.key(uid),
+            state: self.remove(uid),
+        }
     }
 }
 
@@ -403,6 +521,9 @@ impl fmt::Display for FuncId {
 pub trait DefsAndFuncHelper {
     type FuncDef;
 
+    /// The ID of this function, or `None` if this is `Main`.
+    fn func_id(&self) -> Option<FuncId>;
+
     fn get<'a, M: Index<Uid, Output = FuncDef<AnyInputSpec, AnyOutputSpec>> + ?Sized>(
         &'a self,
         map: &'a M,
@@ -423,6 +544,11 @@ pub trait DefsAndFuncHelperMut: DefsAndFuncHelper {
 impl DefsAndFuncHelper for FuncId {
     type FuncDef = FuncDef<AnyInputSpec, AnyOutputSpec>;
 
+    #[inline]
+    fn func_id(&self) -> Option<FuncId> {
+        Some(*self)
+    }
+
     #[inline]
     fn get<'a, M: Index<Uid, Output = FuncDef<AnyInputSpec, AnyOutputSpec>> + ?Sized>(
         &'a self,
@@ -456,6 +582,11 @@ where
 {
     type FuncDef = F::Target;
 
+    #[inline]
+    fn func_id(&self) -> Option<FuncId> {
+        None
+    }
+
     #[inline]
     fn get<'a, M: Index<Uid, Output = FuncDef<AnyInputSpec, AnyOutputSpec>> + ?Sized>(
         &'a self,
@@ -532,13 +663,23 @@ where
     C: AnyComponent,
     OutputSpec: HasStorage<InternalWire>,
 {
-    uid_gen: UidGen,
-    main: FuncDef<InputSpec, OutputSpec>,
+    pub(crate) uid_gen: UidGen,
+    pub(crate) main: FuncDef<InputSpec, OutputSpec>,
     pub(crate) funcs: Funcs,
     pub(crate) meta_storage: UidMap<Meta<C>>,
     // TODO: Maybe only initialise storage when we actually use it, although this would need some
     //       way of specifying components without creating them.
     pub(crate) state_storage: UidMap<C>,
+    pub(crate) scenes: Scenes,
+    pub(crate) history: History<C>,
+    pub(crate) quantiser: Quantiser<C, InputSpec, OutputSpec>,
+    pub(crate) files: Files,
+    /// The outputs of each component of `Main` over the last block run by `process_block`, kept
+    /// so that their memory can be reused.
+    pub(crate) blocks: UidMap<BlockOutputs>,
+    /// The position of the crossfader for each frame of the last block run by `process_block`,
+    /// kept so that its memory can be reused.
+    pub(crate) crossfader: Vec<Value>,
 }
 
 impl<C, InputSpec, OutputSpec> Rack<C, InputSpec, OutputSpec>
@@ -555,6 +696,12 @@ where
             funcs: Default::default(),
             meta_storage: Default::default(),
             state_storage: Default::default(),
+            scenes: Default::default(),
+            history: Default::default(),
+            quantiser: Default::default(),
+            files: Default::default(),
+            blocks: Default::default(),
+            crossfader: Default::default(),
         }
     }
 }
@@ -567,7 +714,7 @@ where
     #[inline]
     pub fn main(&self) -> FuncInstanceRef<'_, C, &FuncDef<InputSpec, OutputSpec>> {
         FuncInstanceRef {
-            uid_gen: (),
+            edit: (),
             defs_and_func: DefsAndFunc {
                 defs: &self.funcs,
                 def: &self.main,
@@ -580,7 +727,12 @@ where
     #[inline]
     pub fn main_mut(&mut self) -> FuncInstanceMut<'_, C, &mut FuncDef<InputSpec, OutputSpec>> {
         FuncInstanceMut {
-            uid_gen: &mut self.uid_gen,
+            edit: EditState {
+                uid_gen: &mut self.uid_gen,
+                main_statements: None,
+                history: &mut self.history,
+                scenes: &mut self.scenes,
+            },
             defs_and_func: DefsAndFunc {
                 defs: &mut self.funcs,
                 def: &mut self.main,
@@ -592,15 +744,29 @@ where
 
     #[inline]
     pub fn new_func(&mut self) -> FuncId {
-        let id = self.uid_gen.next();
-        self.funcs.insert(id, FuncDef::new());
-        FuncId(id)
+        let id = FuncId(self.uid_gen.next());
+        self.insert_func(id);
+        id
+    }
+
+    /// Create a new, empty function with a specific ID, for when we're loading a saved rack.
+    pub(crate) fn new_func_with_id(&mut self, id: FuncId) {
+        self.uid_gen.reserve(id.0);
+        self.insert_func(id);
+    }
+
+    fn insert_func(&mut self, id: FuncId) {
+        let def = self.funcs.insert(id.0, FuncDef::new());
+
+        self.history.begin();
+        self.history.record(Change::Func { id, def });
+        self.history.end();
     }
 
     #[inline]
     pub fn func(&self, id: FuncId) -> FuncInstanceRef<'_, C, FuncId> {
         FuncInstanceRef {
-            uid_gen: (),
+            edit: (),
             defs_and_func: DefsAndFunc {
                 defs: &self.funcs,
                 def: id,
@@ -613,7 +779,12 @@ where
     #[inline]
     pub fn func_mut(&mut self, id: FuncId) -> FuncInstanceMut<'_, C, FuncId> {
         FuncInstanceMut {
-            uid_gen: &mut self.uid_gen,
+            edit: EditState {
+                uid_gen: &mut self.uid_gen,
+                main_statements: Some(&self.main.statements[..]),
+                history: &mut self.history,
+                scenes: &mut self.scenes,
+            },
             defs_and_func: DefsAndFunc {
                 defs: &mut self.funcs,
                 def: id,
@@ -631,12 +802,23 @@ where
     InputSpec: RuntimeSpecifier + 'static,
     OutputSpec: RuntimeSpecifier + 'static,
 {
+    /// Unlike updating `main_mut()` directly, this applies the scenes assigned to the crossfader
+    /// and any quantised edits that are due by this tick.
     #[inline]
     pub fn update<Ctx>(&mut self, ctx: &Ctx)
     where
         Ctx: GetFunctionParam<InputSpec = InputSpec> + ContextMeta,
     {
-        self.main_mut().update(ctx)
+        self.apply_due_edits(ctx.beat_position());
+
+        TopLevelContext {
+            ctx,
+            meta: &self.meta_storage,
+            state: MapWithPathMut::new(&mut self.state_storage),
+            scenes: Some(self.scenes.mix(ctx)),
+            files: Some(&self.files),
+        }
+        .update(&self.funcs, &self.main.statements)
     }
 
     /// Get a specific output of this rack.
@@ -649,7 +831,170 @@ where
     where
         Ctx: GetFunctionParam<InputSpec = InputSpec> + ContextMeta,
     {
-        self.main().output(spec, ctx)
+        let wire = (*self.main.out_wires.get(&spec))?;
+
+        TopLevelContext {
+            ctx,
+            state: MapWithPath::new(&self.state_storage),
+            meta: &self.meta_storage,
+            scenes: Some(self.scenes.mix(ctx)),
+            files: Some(&self.files),
+        }
+        .read_wire(&self.funcs, wire)
+    }
+}
+
+impl<C, InputSpec, OutputSpec> Rack<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent,
+    InputSpec: RuntimeSpecifier + 'static,
+    OutputSpec: EnumerateValues + HasStorage<InternalWire>,
+{
+    /// Run the rack for a block of frames, where `frames` has the context of each frame, and
+    /// write every output of the rack for each frame to `outputs`. This gives the same results as
+    /// calling `update` and reading every output once per frame, but each component is run for
+    /// the whole block before the next one and reads its inputs from the outputs of the
+    /// components before it, instead of the graph being walked from every output for every
+    /// frame.
+    ///
+    /// This only works while every wire in `Main` comes from a component before the one that it
+    /// goes into, and while `Main` doesn't call any functions. Otherwise, the rack is run one
+    /// frame at a time as with `update`.
+    pub fn process_block<Ctx>(&mut self, frames: &[Ctx], outputs: &mut BlockOutputs)
+    where
+        Ctx: GetFunctionParam<InputSpec = InputSpec> + ContextMeta,
+    {
+        outputs.reset(OutputSpec::values().map(|spec| spec.value_type()));
+
+        let mut start = 0;
+
+        while start < frames.len() {
+            self.apply_due_edits(frames[start].beat_position());
+
+            // Queued edits can only be applied between blocks, so we split the block at each
+            // frame that an edit is due on
+            let end = (start + 1..frames.len())
+                .find(|&i| self.edits_due(frames[i].beat_position()))
+                .unwrap_or(frames.len());
+
+            if self.can_process_block() {
+                self.process_frames(&frames[start..end], outputs);
+            } else {
+                for ctx in &frames[start..end] {
+                    self.update(ctx);
+
+                    for spec in OutputSpec::values() {
+                        match self.output(OutputSpec::from_id(spec.id()), ctx) {
+                            Some(iter) => outputs.push(spec.id(), iter),
+                            None => outputs.push_empty(spec.id()),
+                        }
+                    }
+                }
+            }
+
+            start = end;
+        }
+
+        if let Some(last) = frames.last() {
+            // Every edit that's due has been applied already, but this moves the beat that the
+            // next boundary is counted from on to the last frame, as `update` would
+            self.apply_due_edits(last.beat_position());
+        }
+    }
+
+    /// Whether `Main` can be run a block at a time, see `process_block`.
+    fn can_process_block(&self) -> bool {
+        let statements = &self.main.statements;
+        let state = MapWithPath::new(&self.state_storage);
+
+        statements
+            .iter()
+            .enumerate()
+            .all(|(i, id)| match &self.meta_storage[&id.0] {
+                Meta::Component(meta) => {
+                    let is_before = |Wire(src): &WireSrc| match src.element() {
+                        ElementSpecifier::Component { id } => statements[..i].contains(&id),
+                        ElementSpecifier::FuncInputs => true,
+                    };
+                    let types = state[&id.0].types();
+
+                    (0..types.input_types().len()).all(|j| {
+                        meta.inputs
+                            .get(&AnyInputSpec(j))
+                            .as_ref()
+                            .map_or(true, |src| is_before(src))
+                    }) && (0..types.param_types().len()).all(|j| {
+                        meta.params
+                            .get(&AnyParamSpec(j))
+                            .1
+                            .downcast_ref::<InternalParamWire>()
+                            .and_then(Option::as_ref)
+                            .map_or(true, |wire| param_wire_all(wire, &is_before))
+                    })
+                }
+                Meta::Function { .. } => false,
+            })
+    }
+
+    /// Run each component of `Main` for the whole of `frames` in turn, and then copy the outputs
+    /// of `Main` to `outputs`.
+    fn process_frames<Ctx>(&mut self, frames: &[Ctx], outputs: &mut BlockOutputs)
+    where
+        Ctx: GetFunctionParam<InputSpec = InputSpec> + ContextMeta,
+    {
+        let scenes = &self.scenes;
+
+        self.crossfader.clear();
+        self.crossfader
+            .extend(frames.iter().map(|ctx| scenes.crossfader_position(ctx)));
+
+        let mut state = MapWithPathMut::new(&mut self.state_storage);
+        let mut last = mem::take(&mut self.blocks);
+
+        for id in &self.main.statements {
+            let cur_meta = match &self.meta_storage[&id.0] {
+                Meta::Component(meta) => meta,
+                Meta::Function { .. } => unreachable!(),
+            };
+            let mut own = last.remove(id.0).unwrap_or_default();
+            let new = state[&id.0].process_block(
+                &BlockComponentCtx {
+                    block: &BlockState {
+                        frames,
+                        scenes: &self.scenes,
+                        crossfader: &self.crossfader,
+                        files: &self.files,
+                        outputs: &self.blocks,
+                    },
+                    id: *id,
+                    cur_meta,
+                },
+                &mut own,
+            );
+
+            state.insert(id.0, new);
+            self.blocks.insert(id.0, own);
+        }
+
+        for spec in OutputSpec::values() {
+            let wire = *self.main.out_wires.get(spec);
+
+            for (i, ctx) in frames.iter().enumerate() {
+                let iter = wire.and_then(|Wire(wire)| match wire.element() {
+                    ElementSpecifier::Component { id } => Some(PossiblyEither::Left(
+                        self.blocks[&id.0].read(wire.output_id().0, i),
+                    )),
+                    ElementSpecifier::FuncInputs => ctx
+                        .input(InputSpec::from_id(wire.io_index))
+                        .map(PossiblyEither::Right),
+                });
+
+                match iter {
+                    Some(iter) => outputs.push(spec.id(), iter),
+                    None => outputs.push_empty(spec.id()),
+                }
+            }
+        }
     }
 }
 
@@ -660,13 +1005,15 @@ where
     OutputSpec: HasStorage<InternalWire>,
 {
     pub(crate) statements: Vec<ComponentId>,
+    pub(crate) params: Vec<FuncParam>,
+    pub(crate) links: Vec<ParamLink>,
     pub(crate) out_wires: OutputSpec::Storage,
     _marker: PhantomData<(InputSpec, OutputSpec)>,
 }
 
 #[derive(Clone, Debug)]
 pub struct FuncInstanceGen<U, D, MS, SS> {
-    uid_gen: U,
+    edit: U,
     pub(crate) defs_and_func: D,
     pub(crate) meta_storage: MS,
     pub(crate) state_storage: SS,
@@ -699,13 +1046,38 @@ where
 pub type FuncInstanceRef<'a, C, Def> =
     FuncInstanceGen<(), DefsAndFunc<&'a Funcs, Def>, &'a UidMap<Meta<C>>, MapWithPath<'a, C>>;
 
+/// The parts of the rack outside of the function definitions that we need when editing a
+/// function.
+pub struct EditState<'a, C>
+where
+    C: AnyComponent,
+{
+    uid_gen: &'a mut UidGen,
+    /// The statements of `Main`, or `None` if `Main` is the function being edited.
+    main_statements: Option<&'a [ComponentId]>,
+    history: &'a mut History<C>,
+    scenes: &'a mut Scenes,
+}
+
 pub type FuncInstanceMut<'a, C, Def> = FuncInstanceGen<
-    &'a mut UidGen,
+    EditState<'a, C>,
     DefsAndFunc<&'a mut Funcs, Def>,
     &'a mut UidMap<Meta<C>>,
     MapWithPathMut<'a, C>,
 >;
 
+impl<InputSpec, OutputSpec> FuncDef<InputSpec, OutputSpec>
+where
+    OutputSpec: HasStorage<InternalWire>,
+{
+    /// The group of params that this param is linked to, if any.
+    pub(crate) fn link(&self, id: ComponentId, param: SpecId) -> Option<&ParamLink> {
+        self.links
+            .iter()
+            .find(|link| link.params.contains(&(id, param)))
+    }
+}
+
 impl<InputSpec, OutputSpec> FuncDef<InputSpec, OutputSpec>
 where
     OutputSpec: HasStorage<InternalWire>,
@@ -715,6 +1087,8 @@ where
     fn new() -> Self {
         FuncDef {
             statements: Default::default(),
+            params: Default::default(),
+            links: Default::default(),
             out_wires: Default::default(),
             _marker: PhantomData,
         }
@@ -735,6 +1109,11 @@ pub trait FuncContext: ContextMeta {
             <Self::MainCtx as GetFunctionParam>::Iter,
         >,
     >;
+    /// The value of the function param of the current call that forwards to `param` of
+    /// component `id`, if there is one.
+    fn func_param(&self, functions: &Funcs, id: ComponentId, param: AnyParamSpec) -> Option<Value>;
+    /// The scenes that are being crossfaded between, if this is running as part of a `Rack`.
+    fn scenes(&self) -> Option<&SceneMix<'_>>;
     fn state(&self) -> MapWithPath<'_, Self::Component>;
     fn meta(&self) -> &UidMap<Meta<Self::Component>>;
 }
@@ -759,6 +1138,7 @@ where
                     let new = self.state()[&id.0].update(&SingleComponentCtx {
                         ctx: &*self,
                         functions,
+                        id: *id,
                         cur_meta,
                     });
 
@@ -791,6 +1171,9 @@ where
     ctx: &'a Ctx,
     state: State,
     meta: &'a UidMap<Meta<Component>>,
+    scenes: Option<SceneMix<'a>>,
+    /// The files of the rack, or `None` to use the files of `ctx`.
+    files: Option<&'a Files>,
 }
 
 struct RecurseContext<Inner> {
@@ -806,6 +1189,14 @@ where
     fn sample_rate(&self) -> u32 {
         self.ctx.sample_rate()
     }
+
+    fn transport(&self) -> Transport {
+        self.ctx.transport()
+    }
+
+    fn files(&self) -> &Files {
+        self.files.unwrap_or_else(|| self.ctx.files())
+    }
 }
 
 impl<Ctx, Component, M> FuncContext for TopLevelContext<'_, Ctx, Component, MapWithPathGen<M>>
@@ -839,6 +1230,7 @@ where
                             &SingleComponentCtx {
                                 ctx: &*self,
                                 functions,
+                                id,
                                 cur_meta,
                             },
                         )))
@@ -869,6 +1261,15 @@ where
         }
     }
 
+    fn func_param(&self, _: &Funcs, _: ComponentId, _: AnyParamSpec) -> Option<Value> {
+        // `Main` is never called, so it has no params
+        None
+    }
+
+    fn scenes(&self) -> Option<&SceneMix<'_>> {
+        self.scenes.as_ref()
+    }
+
     fn state(&self) -> MapWithPath<'_, Self::Component> {
         self.state.as_ref()
     }
@@ -898,6 +1299,14 @@ where
     fn sample_rate(&self) -> u32 {
         self.inner.sample_rate()
     }
+
+    fn transport(&self) -> Transport {
+        self.inner.transport()
+    }
+
+    fn files(&self) -> &Files {
+        self.inner.files()
+    }
 }
 
 impl<Inner> FuncContext for RecurseContext<Inner>
@@ -928,6 +1337,7 @@ where
                         &SingleComponentCtx {
                             ctx: &*self,
                             functions,
+                            id,
                             cur_meta,
                         },
                     )))
@@ -960,6 +1370,34 @@ where
         }
     }
 
+    fn func_param(&self, functions: &Funcs, id: ComponentId, param: AnyParamSpec) -> Option<Value> {
+        let (func_id, params) = match &self.inner.meta()[self.path.0] {
+            Meta::Function {
+                func_id, params, ..
+            } => (func_id, params),
+            _ => unreachable!(),
+        };
+        let index = functions[func_id.0]
+            .params
+            .iter()
+            .position(|func_param| func_param.targets.contains(&(id, param.0)))?;
+        let value = &params[index];
+        let natural = self
+            .inner
+            .scenes()
+            .and_then(|scenes| scenes.natural_value(self.path, index, value.natural_value))
+            .unwrap_or(value.natural_value);
+
+        // The wires of the call's params are in the function containing the call
+        Some(access_value(natural, value.wire.as_ref(), &|src| {
+            average_output(self.inner.read_wire(functions, src))
+        }))
+    }
+
+    fn scenes(&self) -> Option<&SceneMix<'_>> {
+        self.inner.scenes()
+    }
+
     fn state(&self) -> MapWithPath<'_, Self::Component> {
         self.inner.state().append_path(self.path.0)
     }
@@ -1019,218 +1457,1517 @@ pub trait Param<V>: AsMut<V> {
     fn cv(self) -> Option<Self::CV>;
 }
 
-impl<C, InputSpec, OutputSpec, Def> FuncInstanceMut<'_, C, Def>
+#[derive(Debug, Clone, PartialEq)]
+pub enum WireError {
+    /// The component isn't a statement in the function being edited.
+    UnknownComponent(ComponentId),
+    /// The element has no output, input or parameter with this index.
+    UnknownSpecifier(SpecId),
+    /// The output's type can't be converted to the destination's type.
+    TypeMismatch { src: ValueType, dst: ValueType },
+    /// The parameter can't be controlled by an output, e.g. a file parameter.
+    UnwirableParam(SpecId),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownComponent(id) => write!(f, "{} is not in this function", id),
+            Self::UnknownSpecifier(id) => write!(f, "no input, output or param with id {}", id),
+            Self::TypeMismatch { src, dst } => write!(f, "cannot wire {} into {}", src, dst),
+            Self::UnwirableParam(id) => write!(f, "param {} cannot be wired", id),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+/// The type of a function's inputs or outputs. Functions other than `Main` have polymorphic
+/// inputs and outputs, which we represent as `None`.
+trait SpecType {
+    fn spec_type(&self) -> Option<ValueType>;
+}
+
+impl<S> SpecType for S
 where
-    InputSpec: RuntimeSpecifier,
-    OutputSpec: RuntimeSpecifier + HasStorage<InternalWire>,
-    C: AnyComponent,
-    Def: DefsAndFuncHelperMut<FuncDef = FuncDef<InputSpec, OutputSpec>>,
+    S: RefRuntimeSpecifier,
 {
-    // TODO: Return a result
-    // #[inline]
-    pub fn wire(&mut self, src: WireSrc, dst: WireDst) {
-        match dst.0 {
-            WireDstInner::Input(dst) => match dst.element() {
-                ElementSpecifier::Component { id } => {
-                    self.meta_storage[&id.0]
-                        .inputs_mut()
-                        .set(&dst.input_id(), Some(src));
-                }
-                ElementSpecifier::FuncInputs => self
-                    .def_mut()
-                    .out_wires
-                    .set(&OutputSpec::from_id(dst.input_id().0), Some(src)),
-            },
-            WireDstInner::Param(natural_value, dst) => match dst.element() {
-                ElementSpecifier::Component { id } => {
-                    // TODO: Allow functions to have parameters
-                    *self.meta_storage[&id.0]
-                        .component_mut()
-                        .unwrap()
-                        .params
-                        .get_mut(&dst.param_id())
-                        .1
-                        .downcast_mut::<InternalParamWire>()
-                        .unwrap() = Some(ParamWire {
-                        cv: ParamValue {
-                            natural_value,
-                            wire: None,
-                        },
-                        src,
-                    })
-                }
-                ElementSpecifier::FuncInputs => unimplemented!(),
-            },
-        }
+    default fn spec_type(&self) -> Option<ValueType> {
+        Some(self.value_type())
     }
+}
 
-    #[inline]
-    pub fn update<Ctx>(&mut self, ctx: &Ctx)
-    where
-        Ctx: GetFunctionParam<InputSpec = InputSpec> + ContextMeta,
-    {
-        TopLevelContext {
-            ctx,
-            meta: &mut *self.meta_storage,
-            state: self.state_storage.as_mut(),
+impl SpecType for AnyInputSpec {
+    fn spec_type(&self) -> Option<ValueType> {
+        None
+    }
+}
+
+impl SpecType for AnyOutputSpec {
+    fn spec_type(&self) -> Option<ValueType> {
+        None
+    }
+}
+
+fn check_types(src: Option<ValueType>, dst: Option<ValueType>) -> Result<(), WireError> {
+    match (src, dst) {
+        (Some(src), Some(dst)) if !src.can_wire_to(dst) => {
+            Err(WireError::TypeMismatch { src, dst })
         }
-        .update(
-            &self.defs_and_func.defs,
-            &self.defs_and_func.def().statements,
-        )
+        _ => Ok(()),
     }
+}
 
-    #[inline]
-    pub fn set_param<S: RuntimeSpecifier, V>(&mut self, component: ComponentId, param: S, value: V)
-    where
-        V: 'static,
-    {
-        self.param::<S, V>(component, param).set(value);
+/// The type of an output of a component or function call, looking through function calls to
+/// the output that they return. `None` means that the output can be of any type.
+fn output_type<C>(
+    funcs: &Funcs,
+    meta: &UidMap<Meta<C>>,
+    state: &MapWithPath<'_, C>,
+    id: ComponentId,
+    output: AnyOutputSpec,
+) -> Result<Option<ValueType>, WireError>
+where
+    C: AnyComponent,
+{
+    match &meta[id.0] {
+        Meta::Component(_) => state[&id.0]
+            .types()
+            .output_types()
+            .nth(output.0)
+            .map(Some)
+            .ok_or(WireError::UnknownSpecifier(output.0)),
+        Meta::Function { func_id, .. } => match funcs[func_id.0].out_wires.get(&output) {
+            Some(Wire(GenericWire {
+                element: ElementSpecifier::Component { id },
+                io_index,
+                ..
+            })) => output_type(funcs, meta, state, *id, AnyOutputSpec(*io_index)),
+            _ => Ok(None),
+        },
     }
+}
 
-    #[inline]
-    pub fn param<S: RuntimeSpecifier, V>(
-        &mut self,
-        component: ComponentId,
-        param: S,
-    ) -> impl AsParam<V> + '_
+impl<C, InputSpec, OutputSpec, Def> FuncInstanceMut<'_, C, Def>
+where
+    InputSpec: RuntimeSpecifier,
+    OutputSpec: RuntimeSpecifier + HasStorage<InternalWire>,
+    C: AnyComponent,
+    Def: DefsAndFuncHelperMut<FuncDef = FuncDef<InputSpec, OutputSpec>>,
+{
+    /// Lock two params in this function together, so that setting or wiring either of them
+    /// through `param`, `set_param` or `wire` does the same to the other. Either param can
+    /// already be linked to others, in which case the two groups are merged. `b` and the params
+    /// linked to it take the natural value and wire of `a`. Returns `false` if either param isn't
+    /// a `V` param of a statement in this function or if the two params have different types.
+    pub fn link_params<V, A, B>(&mut self, a: (ComponentId, A), b: (ComponentId, B)) -> bool
     where
-        V: 'static,
+        V: Clone + 'static,
+        A: RefRuntimeSpecifier,
+        B: RefRuntimeSpecifier,
     {
-        use std::borrow::BorrowMut;
+        let (a, b) = ((a.0, a.1.id()), (b.0, b.1.id()));
 
-        struct BorrowParam<'a, V, P> {
-            value: &'a mut V,
-            wire: &'a mut Option<P>,
+        match self.param_type(a) {
+            Some((type_id, _)) if type_id == TypeId::of::<V>() => {}
+            _ => return false,
         }
 
-        struct AsMutWrapper<'a, T>(&'a mut T);
+        self.edit.history.begin();
 
-        impl<T> AsMut<T> for AsMutWrapper<'_, T> {
-            fn as_mut(&mut self) -> &mut T {
-                self.0
+        let is_linked = self.add_link(a, b);
+        if is_linked {
+            self.save_linked::<V>(a);
+
+            if let Some(link) = self.def().link(a.0, a.1) {
+                let link = link.params.clone();
+                copy_to_linked::<C, V>(self.meta_storage, &link, a);
             }
         }
 
-        impl<'a, V, P> AsMut<V> for BorrowParam<'a, V, P> {
-            fn as_mut(&mut self) -> &mut V {
-                self.value
+        self.edit.history.end();
+
+        is_linked
+    }
+
+    /// Link two params without copying the value of one to the other, for when we're loading a
+    /// saved rack where linked params already have the same value.
+    pub(crate) fn add_link(&mut self, a: (ComponentId, SpecId), b: (ComponentId, SpecId)) -> bool {
+        match (self.param_type(a), self.param_type(b)) {
+            (Some(a_type), Some(b_type)) if a_type == b_type => {}
+            _ => return false,
+        }
+
+        self.edit.history.begin();
+        self.save_def();
+
+        let links = &mut self.def_mut().links;
+        let a_link = links.iter().position(|link| link.params.contains(&a));
+        let b_link = links.iter().position(|link| link.params.contains(&b));
+
+        match (a_link, b_link) {
+            (Some(a_link), Some(b_link)) if a_link == b_link => {}
+            (Some(a_link), Some(b_link)) => {
+                let merged = links.remove(b_link);
+                let a_link = if a_link > b_link { a_link - 1 } else { a_link };
+
+                links[a_link].params.extend(merged.params);
             }
+            (Some(link), None) => links[link].params.push(b),
+            (None, Some(link)) => links[link].params.push(a),
+            (None, None) if a == b => {}
+            (None, None) => links.push(ParamLink { params: vec![a, b] }),
         }
 
-        impl<'a, V, P> Param<V> for BorrowParam<'a, V, P>
-        where
-            P: BorrowMut<ParamWire<V>> + From<ParamWire<V>>,
+        self.edit.history.end();
+
+        true
+    }
+
+    /// Remove a param from the group of params it's linked to, returning `false` if it wasn't
+    /// linked to anything.
+    pub fn unlink_param<S: RefRuntimeSpecifier>(
+        &mut self,
+        component: ComponentId,
+        param: S,
+    ) -> bool {
+        let param = (component, param.id());
+        let index = match self
+            .def()
+            .links
+            .iter()
+            .position(|link| link.params.contains(&param))
         {
-            type CV = BorrowParam<'a, V, Box<ParamWire<V>>>;
+            Some(index) => index,
+            None => return false,
+        };
 
-            fn wire(&mut self, src: WireSrc, cv: V) {
-                *self.wire = Some(
-                    ParamWire {
-                        src,
-                        cv: ParamValue {
-                            natural_value: cv,
-                            wire: None,
-                        },
-                    }
-                    .into(),
-                );
-            }
+        self.edit.history.begin();
+        self.save_def();
 
-            fn cv(mut self) -> Option<Self::CV> {
-                self.wire
-                    .as_mut()
-                    .map(|w| w.borrow_mut())
-                    .map(|ParamWire { cv, .. }| BorrowParam {
-                        value: &mut cv.natural_value,
-                        wire: &mut cv.wire,
-                    })
-            }
+        let links = &mut self.def_mut().links;
+        links[index].params.retain(|&other| other != param);
+        if links[index].params.len() < 2 {
+            links.remove(index);
         }
 
-        let (v, wire) = self.meta_storage[&component.0]
-            .component_mut()
-            .unwrap()
-            .params
-            .get_mut(&AnyParamSpec(param.id()));
-        let value = v.downcast_mut::<V>().expect("Incorrect param type");
-        match wire.downcast_mut::<Option<ParamWire<V>>>() {
-            None => Either::Left(AsMutWrapper(value)),
-            Some(wire) => Either::Right(BorrowParam { value, wire }),
+        self.edit.history.end();
+
+        true
+    }
+
+    /// Unlink every param of a statement that's being removed or replaced.
+    fn unlink_statement(&mut self, id: ComponentId) {
+        let links = &mut self.def_mut().links;
+
+        for link in links.iter_mut() {
+            link.params.retain(|&(other, _)| other != id);
         }
+        links.retain(|link| link.params.len() >= 2);
     }
 
-    #[inline]
-    pub fn push_component(&mut self, component: impl Into<C>) -> ComponentId {
-        let component = component.into();
-        let params = component.param_default();
-        let inputs = component.input_default();
+    /// Stop the scenes storing values for the params of a statement that's being removed or
+    /// replaced, since they're meant for the params that it had before.
+    fn clear_scenes(&mut self, id: ComponentId) {
+        let mut values = vec![];
 
-        let uid = self.uid_gen.next();
+        self.edit.scenes.swap_component(id, &mut values);
+        if !values.is_empty() {
+            self.edit.history.record(Change::Scenes { id, values });
+        }
+    }
 
-        self.meta_storage
-            .insert(uid, Meta::Component(ComponentMeta { inputs, params }));
-        self.state_storage.insert(uid, component);
-        let cid = ComponentId(uid);
-        self.def_mut().statements.push(cid);
+    /// Record the statements, params and links of this function so that they can be restored
+    /// by undoing the current edit.
+    fn save_def(&mut self) {
+        let def = self.def();
+        let change = Change::Def {
+            func: self.defs_and_func.def.func_id(),
+            statements: def.statements.clone(),
+            params: def.params.clone(),
+            links: def.links.clone(),
+        };
 
-        cid
+        self.edit.history.record(change);
     }
-}
 
-impl<C, InputSpec, OutputSpec, Def> FuncInstanceMut<'_, C, Def>
-where
-    InputSpec: RuntimeSpecifier,
+    /// Record the natural value and wire of a `V` param and of every param linked to it.
+    fn save_linked<V: Clone + 'static>(&mut self, param: (ComponentId, SpecId)) {
+        let params = self
+            .def()
+            .link(param.0, param.1)
+            .map_or_else(|| vec![param], |link| link.params.clone());
+
+        for param in params {
+            let change = save_param::<C, V>(self.meta_storage, param);
+            self.edit.history.record(change);
+        }
+    }
+
+    /// The Rust type and the value type of a param of a statement in this function.
+    pub(crate) fn param_type(
+        &self,
+        (id, spec): (ComponentId, SpecId),
+    ) -> Option<(TypeId, ValueType)> {
+        if !self.def().statements.contains(&id) {
+            return None;
+        }
+
+        match &self.meta_storage[id.0] {
+            Meta::Component(meta) => {
+                let ty = self.state_storage[&id.0].types().param_types().nth(spec)?;
+
+                Some((Any::type_id(meta.params.get(&AnyParamSpec(spec)).0), ty))
+            }
+            Meta::Function { params, .. } => params
+                .get(spec)
+                .map(|_| (TypeId::of::<Value>(), ValueType::continuous())),
+        }
+    }
+
+    /// Wire `src` into `dst`, returning the wire that was previously connected to `dst`. If the
+    /// wire can't be made then the function is left unchanged.
+    pub fn wire(&mut self, src: WireSrc, dst: WireDst) -> Result<Option<WireSrc>, WireError> {
+        check_types(self.src_type(src)?, self.dst_type(&dst)?)?;
+
+        self.edit.history.begin();
+        let old = self.replace_wire(dst, Some(src));
+        self.edit.history.end();
+
+        Ok(old)
+    }
+
+    /// Check that `src` can be wired into `dst`, or that `dst` exists if `src` is `None`,
+    /// without changing anything.
+    pub(crate) fn check_wire(&self, src: Option<WireSrc>, dst: &WireDst) -> Result<(), WireError> {
+        let src = match src {
+            Some(src) => self.src_type(src)?,
+            None => None,
+        };
+
+        check_types(src, self.dst_type(dst)?)
+    }
+
+    /// Disconnect whatever is wired into `dst`, returning the wire that was removed.
+    pub fn unwire(&mut self, dst: WireDst) -> Result<Option<WireSrc>, WireError> {
+        self.dst_type(&dst)?;
+
+        self.edit.history.begin();
+        let old = self.replace_wire(dst, None);
+        self.edit.history.end();
+
+        Ok(old)
+    }
+
+    /// Set the wire going into `dst`, which must already have been checked with `dst_type`.
+    fn replace_wire(&mut self, dst: WireDst, src: Option<WireSrc>) -> Option<WireSrc> {
+        match dst.0 {
+            WireDstInner::Input(dst) => match dst.element() {
+                ElementSpecifier::Component { id } => {
+                    let mut inputs = self.meta_storage[&id.0].inputs_mut();
+                    let old = *inputs.get(&dst.input_id());
+                    inputs.set(&dst.input_id(), src);
+
+                    self.edit.history.record(Change::Input {
+                        id,
+                        spec: dst.io_index,
+                        wire: old,
+                    });
+
+                    old
+                }
+                ElementSpecifier::FuncInputs => {
+                    let func = self.defs_and_func.def.func_id();
+                    let spec = OutputSpec::from_id(dst.input_id().0);
+                    let out_wires = &mut self.def_mut().out_wires;
+                    let old = *out_wires.get(&spec);
+                    out_wires.set(&spec, src);
+
+                    self.edit.history.record(Change::Output {
+                        func,
+                        spec: dst.io_index,
+                        wire: old,
+                    });
+
+                    old
+                }
+            },
+            WireDstInner::Param(natural_value, dst) => {
+                let id = match dst.element() {
+                    ElementSpecifier::Component { id } => id,
+                    ElementSpecifier::FuncInputs => unreachable!(),
+                };
+                self.save_linked::<Value>((id, dst.io_index));
+
+                let wire = self.meta_storage[&id.0]
+                    .param_mut(&dst.param_id())
+                    .unwrap()
+                    .1
+                    .downcast_mut::<InternalParamWire>()
+                    .unwrap();
+
+                let old = std::mem::replace(
+                    wire,
+                    src.map(|src| ParamWire {
+                        cv: ParamValue {
+                            natural_value,
+                            wire: None,
+                        },
+                        src,
+                    }),
+                )
+                .map(|wire| wire.src);
+
+                if let Some(link) = self.def().link(id, dst.io_index) {
+                    let link = link.params.clone();
+                    copy_to_linked::<_, Value>(self.meta_storage, &link, (id, dst.io_index));
+                }
+
+                old
+            }
+        }
+    }
+
+    /// Calls `func` with the state of every call to this function in the rack. This doesn't
+    /// include the state of the definition itself, which is copied into new calls.
+    fn for_each_call(
+        &mut self,
+        mut func: impl FnMut(&Funcs, &UidMap<Meta<C>>, MapWithPathMut<'_, C>),
+    ) {
+        let target = match self.defs_and_func.def.func_id() {
+            Some(target) => target,
+            // `Main` can't be called
+            None => return,
+        };
+        let funcs = &*self.defs_and_func.defs;
+        let meta = &*self.meta_storage;
+
+        for_each_call(
+            funcs,
+            meta,
+            self.state_storage.as_mut(),
+            self.edit.main_statements.unwrap_or_default(),
+            target,
+            &mut func,
+        );
+
+        for (_, def) in funcs {
+            for_each_call(
+                funcs,
+                meta,
+                self.state_storage.as_mut(),
+                &def.statements,
+                target,
+                &mut func,
+            );
+        }
+    }
+
+    fn check_statement(&self, id: ComponentId) -> Result<(), WireError> {
+        if self.def().statements.contains(&id) {
+            Ok(())
+        } else {
+            Err(WireError::UnknownComponent(id))
+        }
+    }
+
+    fn src_type(&self, Wire(src): WireSrc) -> Result<Option<ValueType>, WireError> {
+        match src.element() {
+            ElementSpecifier::Component { id } => {
+                self.check_statement(id)?;
+
+                output_type(
+                    &*self.defs_and_func.defs,
+                    &*self.meta_storage,
+                    &self.state_storage.as_ref(),
+                    id,
+                    src.output_id(),
+                )
+            }
+            ElementSpecifier::FuncInputs => Ok(InputSpec::from_id(src.io_index).spec_type()),
+        }
+    }
+
+    fn dst_type(&self, dst: &WireDst) -> Result<Option<ValueType>, WireError> {
+        match &dst.0 {
+            WireDstInner::Input(dst) => match dst.element() {
+                ElementSpecifier::Component { id } => {
+                    self.check_statement(id)?;
+
+                    match &self.meta_storage[&id.0] {
+                        Meta::Component(_) => self.state_storage[&id.0]
+                            .types()
+                            .input_types()
+                            .nth(dst.io_index)
+                            .map(Some)
+                            .ok_or(WireError::UnknownSpecifier(dst.io_index)),
+                        // Function inputs can be any type
+                        Meta::Function { .. } => Ok(None),
+                    }
+                }
+                ElementSpecifier::FuncInputs => Ok(OutputSpec::from_id(dst.io_index).spec_type()),
+            },
+            WireDstInner::Param(_, dst) => match dst.element() {
+                ElementSpecifier::Component { id } => {
+                    self.check_statement(id)?;
+
+                    match &self.meta_storage[&id.0] {
+                        Meta::Component(meta) => {
+                            let param_type = self.state_storage[&id.0]
+                                .types()
+                                .param_types()
+                                .nth(dst.io_index)
+                                .ok_or(WireError::UnknownSpecifier(dst.io_index))?;
+
+                            if param_type.kind == ValueKind::File
+                                || !meta.params.get(&dst.param_id()).1.is::<InternalParamWire>()
+                            {
+                                return Err(WireError::UnwirableParam(dst.io_index));
+                            }
+
+                            // Params take the average of all channels of the output, so any
+                            // number of channels is fine.
+                            Ok(Some(ValueType {
+                                channels: None,
+                                ..param_type
+                            }))
+                        }
+                        Meta::Function { func_id, .. } => self
+                            .defs_and_func
+                            .get(*func_id)
+                            .params
+                            .get(dst.io_index)
+                            .map(|_| Some(ValueType::continuous()))
+                            .ok_or(WireError::UnknownSpecifier(dst.io_index)),
+                    }
+                }
+                ElementSpecifier::FuncInputs => Err(WireError::UnknownSpecifier(dst.io_index)),
+            },
+        }
+    }
+
+    #[inline]
+    pub fn update<Ctx>(&mut self, ctx: &Ctx)
+    where
+        Ctx: GetFunctionParam<InputSpec = InputSpec> + ContextMeta,
+    {
+        TopLevelContext {
+            ctx,
+            meta: &mut *self.meta_storage,
+            state: self.state_storage.as_mut(),
+            scenes: None,
+            files: None,
+        }
+        .update(
+            &self.defs_and_func.defs,
+            &self.defs_and_func.def().statements,
+        )
+    }
+
+    /// Set the natural value of a param from its serialized form, returning `false` if the
+    /// value isn't valid for this param.
+    pub(crate) fn parse_param(
+        &mut self,
+        component: ComponentId,
+        param: AnyParamSpec,
+        text: &str,
+    ) -> bool {
+        match &mut self.meta_storage[&component.0] {
+            Meta::Component(meta) => meta.params.parse(&param, text),
+            Meta::Function { params, .. } => {
+                match (
+                    params.get_mut(param.0),
+                    <Value as crate::params::Param>::parse(text),
+                ) {
+                    (Some(param), Some(value)) => {
+                        param.natural_value = value;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+        }
+    }
+
+    #[inline]
+    pub fn set_param<S: RuntimeSpecifier, V>(&mut self, component: ComponentId, param: S, value: V)
+    where
+        V: Clone + 'static,
+    {
+        self.param::<S, V>(component, param).set(value);
+    }
+
+    /// Edit the natural value of a param or, if the param can be wired, its wire and CV. Any
+    /// changes are copied to the params linked to this one once the returned value is dropped,
+    /// and are undone together.
+    #[inline]
+    pub fn param<S: RuntimeSpecifier, V>(
+        &mut self,
+        component: ComponentId,
+        param: S,
+    ) -> impl AsParam<V> + '_
+    where
+        V: Clone + 'static,
+    {
+        let param = (component, param.id());
+        let links = self
+            .def()
+            .link(component, param.1)
+            .map(|link| link.params.clone())
+            .unwrap_or_default();
+        let (value, wire) = self.meta_storage[&component.0]
+            .param_mut(&AnyParamSpec(param.1))
+            .expect("No such param");
+
+        assert!(value.is::<V>(), "Incorrect param type");
+        let is_wirable = wire.is::<Option<ParamWire<V>>>();
+        let edit = LinkedParam {
+            meta: &mut *self.meta_storage,
+            history: &mut *self.edit.history,
+            links,
+            param,
+            depth: 0,
+            saved: None,
+            _marker: PhantomData,
+        };
+
+        if is_wirable {
+            Either::Right(edit)
+        } else {
+            Either::Left(edit)
+        }
+    }
+
+    #[inline]
+    pub fn push_component(&mut self, component: impl Into<C>) -> ComponentId {
+        let end = self.def().statements.len();
+        let id = ComponentId(self.edit.uid_gen.next());
+
+        self.insert_component(end, id, component.into());
+
+        id
+    }
+
+    /// Push a component with a specific ID, for when we're loading a saved rack.
+    pub(crate) fn push_component_with_id(&mut self, id: ComponentId, component: C) {
+        let end = self.def().statements.len();
+
+        self.edit.uid_gen.reserve(id.0);
+        self.insert_component(end, id, component);
+    }
+
+    /// Insert a component so that it's updated immediately before `existing`. Returns `None` if
+    /// `existing` isn't in this function.
+    #[inline]
+    pub fn insert_component_before(
+        &mut self,
+        existing: ComponentId,
+        component: impl Into<C>,
+    ) -> Option<ComponentId> {
+        let index = self
+            .def()
+            .statements
+            .iter()
+            .position(|&id| id == existing)?;
+        let id = ComponentId(self.edit.uid_gen.next());
+
+        self.insert_component(index, id, component.into());
+
+        Some(id)
+    }
+
+    /// Insert a component so that it's updated immediately after `existing`. Returns `None` if
+    /// `existing` isn't in this function.
+    #[inline]
+    pub fn insert_component_after(
+        &mut self,
+        existing: ComponentId,
+        component: impl Into<C>,
+    ) -> Option<ComponentId> {
+        let index = self
+            .def()
+            .statements
+            .iter()
+            .position(|&id| id == existing)?;
+        let id = ComponentId(self.edit.uid_gen.next());
+
+        self.insert_component(index + 1, id, component.into());
+
+        Some(id)
+    }
+
+    fn insert_component(&mut self, index: usize, id: ComponentId, component: C) {
+        let params = component.param_default();
+        let inputs = component.input_default();
+
+        let uid = id.0;
+        let mut changes = vec![];
+
+        self.edit.history.begin();
+        self.save_def();
+
+        let meta = self
+            .meta_storage
+            .insert(uid, Meta::Component(ComponentMeta { inputs, params }));
+        self.edit.history.record(Change::Meta { id, meta });
+        self.for_each_call(|_, _, mut state| {
+            // Functions can only be called if `C: Clone`, see `push_function_call`
+            let component = component
+                .maybe_clone()
+                .expect("Components of called functions must be `Clone`");
+
+            changes.push(state.insert_saved(uid, component));
+        });
+        changes.push(self.state_storage.insert_saved(uid, component));
+        self.def_mut().statements.insert(index, id);
+
+        self.edit.history.extend(changes);
+        self.edit.history.end();
+    }
+}
+
+impl<C, InputSpec, OutputSpec, Def> FuncInstanceMut<'_, C, Def>
+where
+    InputSpec: RuntimeSpecifier,
     OutputSpec: RuntimeSpecifier + HasStorage<InternalWire>,
     C: AnyComponent + Clone,
     Def: DefsAndFuncHelperMut<FuncDef = FuncDef<InputSpec, OutputSpec>>,
 {
     #[inline]
     pub fn push_function_call(&mut self, fid: FuncId) -> ComponentId {
+        let id = ComponentId(self.edit.uid_gen.next());
+
+        self.insert_function_call(id, fid);
+
+        id
+    }
+
+    /// Push a call to `fid` with a specific ID, for when we're loading a saved rack.
+    pub(crate) fn push_function_call_with_id(&mut self, id: ComponentId, fid: FuncId) {
+        self.edit.uid_gen.reserve(id.0);
+        self.insert_function_call(id, fid);
+    }
+
+    fn insert_function_call(&mut self, id: ComponentId, fid: FuncId) {
         fn add_function_state<C>(
             defs: &Funcs,
             meta: &UidMap<Meta<C>>,
             mut state: MapWithPathMut<'_, C>,
             statements: &[ComponentId],
+            changes: &mut Vec<Change<C>>,
         ) where
             C: AnyComponent + Clone,
         {
             for id in statements {
                 match &meta[id.0] {
                     Meta::Component { .. } => {
-                        state.insert(id.0, state.original_map()[&id.0].clone());
+                        let component = state.original_map()[&id.0].clone();
+                        changes.push(state.insert_saved(id.0, component));
                     }
                     Meta::Function { func_id, .. } => add_function_state(
                         defs,
                         meta,
                         state.as_mut().append_path(id.0),
                         &defs[func_id.0].statements,
+                        changes,
                     ),
                 }
             }
         }
 
-        let new_id = self.uid_gen.next();
-        self.meta_storage.insert(
+        let new_id = id.0;
+        let mut changes = vec![];
+
+        self.edit.history.begin();
+        self.save_def();
+
+        let params = self
+            .defs_and_func
+            .get(fid)
+            .params
+            .iter()
+            .map(|param| ParamValue {
+                natural_value: param
+                    .targets
+                    .first()
+                    .and_then(|&(target, spec)| self.meta_storage.get(target.0)?.param_value(spec))
+                    .unwrap_or_default(),
+                wire: None,
+            })
+            .collect();
+
+        let meta = self.meta_storage.insert(
             new_id,
             Meta::Function {
                 func_id: fid,
+                params,
                 inputs: Default::default(),
             },
         );
-        self.def_mut().statements.push(ComponentId(new_id));
-
+        self.edit.history.record(Change::Meta { id, meta });
+        self.def_mut().statements.push(id);
+
+        self.for_each_call(|funcs, meta, state| {
+            add_function_state(
+                funcs,
+                meta,
+                state.append_path(new_id),
+                &funcs[fid.0].statements,
+                &mut changes,
+            )
+        });
         add_function_state(
             &*self.defs_and_func.defs,
             self.meta_storage,
             self.state_storage.as_mut().append_path(new_id),
             &self.defs_and_func.get(fid).statements,
+            &mut changes,
         );
 
-        ComponentId(new_id)
+        self.edit.history.extend(changes);
+        self.edit.history.end();
     }
 }
 
+impl<C, InputSpec, OutputSpec, Def> FuncInstanceMut<'_, C, Def>
+where
+    InputSpec: RuntimeSpecifier,
+    OutputSpec: RuntimeSpecifier + HasStorage<InternalWire>,
+    for<'any> &'any OutputSpec::Storage: IntoIterator<Item = (OutputSpec, &'any InternalWire)>,
+    C: AnyComponent,
+    Def: DefsAndFuncHelperMut<FuncDef = FuncDef<InputSpec, OutputSpec>>,
+{
+    /// Remove a component from this function, disconnecting every wire that reads from it and
+    /// removing its state from every call to this function. Returns `None` if `id` isn't a
+    /// component in this function.
+    pub fn remove_component(&mut self, id: ComponentId) -> Option<C>
+    where
+        C: Clone,
+    {
+        match self.meta_storage.get(id.0) {
+            Some(Meta::Component(_)) if self.def().statements.contains(&id) => {}
+            _ => return None,
+        }
+
+        let mut changes = vec![];
+
+        self.edit.history.begin();
+        self.save_def();
+
+        self.def_mut()
+            .statements
+            .retain(|&statement| statement != id);
+        self.retain_wires(|Wire(wire)| wire.element != ElementSpecifier::Component { id });
+        for param in &mut self.def_mut().params {
+            param.targets.retain(|&(target, _)| target != id);
+        }
+        self.unlink_statement(id);
+        self.clear_scenes(id);
+        self.for_each_call(|_, _, mut state| {
+            changes.push(state.remove_saved(id.0));
+        });
+        self.edit.history.extend(changes);
+
+        let meta = self.meta_storage.remove(id.0);
+        self.edit.history.record(Change::Meta { id, meta });
+
+        let key = self.state_storage.key(id.0);
+        let state = self.state_storage.remove(id.0);
+        self.edit.history.record(Change::State {
+            key,
+            state: state.clone(),
+        });
+        self.edit.history.end();
+
+        state
+    }
+
+    /// Remove a function call from this function, disconnecting every wire that reads from it
+    /// and removing the state of the call from every call to this function. Returns the
+    /// function that was being called, or `None` if `id` isn't a function call in this function.
+    pub fn remove_function_call(&mut self, id: ComponentId) -> Option<FuncId> {
+        let func_id = match self.meta_storage.get(id.0) {
+            Some(Meta::Function { func_id, .. }) if self.def().statements.contains(&id) => *func_id,
+            _ => return None,
+        };
+        let mut changes = vec![];
+
+        self.edit.history.begin();
+        self.save_def();
+
+        self.def_mut()
+            .statements
+            .retain(|&statement| statement != id);
+        self.retain_wires(|Wire(wire)| wire.element != ElementSpecifier::Component { id });
+        self.unlink_statement(id);
+        self.clear_scenes(id);
+        self.for_each_call(|funcs, meta, state| {
+            remove_function_state(
+                funcs,
+                meta,
+                state.append_path(id.0),
+                &funcs[func_id.0].statements,
+                &mut changes,
+            )
+        });
+
+        let funcs = &*self.defs_and_func.defs;
+        remove_function_state(
+            funcs,
+            &*self.meta_storage,
+            self.state_storage.as_mut().append_path(id.0),
+            &funcs[func_id.0].statements,
+            &mut changes,
+        );
+        self.edit.history.extend(changes);
+
+        let meta = self.meta_storage.remove(id.0);
+        self.edit.history.record(Change::Meta { id, meta });
+        self.edit.history.end();
+
+        Some(func_id)
+    }
+
+    /// Replace a component with a new one, keeping its ID. Wires into and out of the component
+    /// are kept if the new component has an input, output or parameter with the same ID and type,
+    /// and otherwise they're disconnected and returned. The natural values of params with the same
+    /// ID and type are kept too, and other params start at their defaults. Function params stop
+    /// forwarding to params that the new component doesn't have, and the component's params are
+    /// unlinked. Returns `None` if `id` isn't a component in this function.
+    pub fn replace_component(
+        &mut self,
+        id: ComponentId,
+        component: impl Into<C>,
+    ) -> Option<Vec<WireDst>>
+    where
+        C: Clone,
+    {
+        match self.meta_storage.get(id.0) {
+            Some(Meta::Component(_)) if self.def().statements.contains(&id) => {}
+            _ => return None,
+        }
+
+        let component = component.into();
+        let mut changes = vec![];
+
+        self.edit.history.begin();
+        self.save_def();
+
+        let old = match self.meta_storage.remove(id.0) {
+            Some(Meta::Component(meta)) => meta,
+            _ => unreachable!(),
+        };
+        let mut new = ComponentMeta {
+            params: component.param_default(),
+            inputs: component.input_default(),
+        };
+        let (old_types, new_types) = (self.state_storage[&id.0].types(), component.types());
+        let mut disconnected = vec![];
+
+        let new_inputs = new_types.input_types().collect::<Vec<_>>();
+        for (i, ty) in old_types.input_types().enumerate() {
+            let wire = *old.inputs.get(&AnyInputSpec(i));
+
+            if wire.is_none() {
+                continue;
+            }
+
+            if new_inputs.get(i) == Some(&ty) {
+                new.inputs.set(&AnyInputSpec(i), wire);
+            } else {
+                disconnected.push(WireDst::component_input(id, AnyInputSpec(i)));
+            }
+        }
+
+        let new_params = new_types.param_types().collect::<Vec<_>>();
+        let forwardable = (0..new_params.len())
+            .map(|i| {
+                new.params.get(&AnyParamSpec(i)).1.is::<InternalParamWire>()
+                    && new_params.get(i) == old_types.param_types().nth(i).as_ref()
+            })
+            .collect::<Vec<_>>();
+
+        for (i, ty) in old_types.param_types().enumerate() {
+            // Natural values can be of any type, so they're copied in the form they're saved in
+            if new_params.get(i) == Some(&ty) {
+                let value = FnDisplay(|f| old.params.serialize(&AnyParamSpec(i), f)).to_string();
+
+                new.params.parse(&AnyParamSpec(i), &value);
+            }
+
+            let wire = match old
+                .params
+                .get(&AnyParamSpec(i))
+                .1
+                .downcast_ref::<InternalParamWire>()
+                .and_then(|wire| wire.clone())
+            {
+                Some(wire) => wire,
+                None => continue,
+            };
+            let new_wire = if new_params.get(i) == Some(&ty) {
+                new.params
+                    .get_mut(&AnyParamSpec(i))
+                    .1
+                    .downcast_mut::<InternalParamWire>()
+            } else {
+                None
+            };
+
+            match new_wire {
+                Some(new_wire) => *new_wire = Some(wire),
+                None => disconnected.push(WireDst::component_param(
+                    id,
+                    AnyParamSpec(i),
+                    wire.cv.natural_value,
+                )),
+            }
+        }
+
+        let kept_outputs = old_types
+            .output_types()
+            .zip(new_types.output_types())
+            .map(|(old, new)| old == new)
+            .collect::<Vec<_>>();
+
+        for param in &mut self.def_mut().params {
+            param
+                .targets
+                .retain(|&(target, spec)| target != id || forwardable.get(spec) == Some(&true));
+        }
+        self.unlink_statement(id);
+        self.clear_scenes(id);
+
+        self.meta_storage.insert(id.0, Meta::Component(new));
+        self.edit.history.record(Change::Meta {
+            id,
+            meta: Some(Meta::Component(old)),
+        });
+        self.for_each_call(|_, _, mut state| {
+            changes.push(state.insert_saved(id.0, component.clone()));
+        });
+        changes.push(self.state_storage.insert_saved(id.0, component));
+        self.edit.history.extend(changes);
+
+        disconnected.extend(self.retain_wires(|Wire(wire)| {
+            wire.element != ElementSpecifier::Component { id }
+                || kept_outputs.get(wire.io_index) == Some(&true)
+        }));
+        self.edit.history.end();
+
+        Some(disconnected)
+    }
+
+    /// Add a param to this function which forwards to each of the given params of components in
+    /// this function. Like a new call, every existing call to this function gets a param whose
+    /// natural value is the current value of the first target, so that adding a param doesn't
+    /// change what the calls do. Returns `None` if this is `Main`, if the name is empty, contains
+    /// anything other than alphanumerics and underscores or is already used, or if a target isn't
+    /// a `Value` param of a component in this function that isn't already forwarded to.
+    pub fn add_param(
+        &mut self,
+        name: impl Into<String>,
+        targets: impl IntoIterator<Item = (ComponentId, AnyParamSpec)>,
+    ) -> Option<AnyParamSpec> {
+        let func_id = self.defs_and_func.def.func_id()?;
+        let name = name.into();
+
+        if name.is_empty()
+            || !name.chars().all(|c| c.is_alphanumeric() || c == '_')
+            || self.def().params.iter().any(|param| param.name == name)
+        {
+            return None;
+        }
+
+        let mut param = FuncParam {
+            name,
+            targets: vec![],
+        };
+
+        for (id, spec) in targets {
+            let is_valid = self.def().statements.contains(&id)
+                && !self
+                    .def()
+                    .params
+                    .iter()
+                    .chain(std::iter::once(&param))
+                    .any(|param| param.targets.contains(&(id, spec.0)))
+                && match self.meta_storage.get(id.0) {
+                    Some(Meta::Component(_)) => {
+                        spec.0 < self.state_storage[&id.0].types().param_types().len()
+                            && self.meta_storage[id.0].param_value(spec.0).is_some()
+                    }
+                    _ => false,
+                };
+
+            if !is_valid {
+                return None;
+            }
+
+            param.targets.push((id, spec.0));
+        }
+
+        let natural_value = param
+            .targets
+            .first()
+            .and_then(|&(target, spec)| self.meta_storage.get(target.0)?.param_value(spec))
+            .unwrap_or_default();
+
+        self.edit.history.begin();
+        self.save_def();
+
+        for (uid, meta) in self.meta_storage.iter_mut() {
+            if let Meta::Function {
+                func_id: call_func_id,
+                params,
+                ..
+            } = meta
+            {
+                if *call_func_id == func_id {
+                    self.edit.history.record(Change::CallParams {
+                        id: ComponentId(uid),
+                        params: params.clone(),
+                    });
+                    params.push(ParamValue {
+                        natural_value,
+                        wire: None,
+                    });
+                }
+            }
+        }
+
+        let params = &mut self.def_mut().params;
+        params.push(param);
+        let spec = AnyParamSpec(params.len() - 1);
+
+        self.edit.history.end();
+
+        Some(spec)
+    }
+
+    /// Disconnect every wire in this function whose source doesn't satisfy `keep`, returning the
+    /// destinations that were disconnected.
+    fn retain_wires(&mut self, keep: impl Fn(&WireSrc) -> bool) -> Vec<WireDst> {
+        let is_dangling = |wire: &InternalWire| wire.as_ref().map_or(false, |wire| !keep(wire));
+        let Self {
+            edit,
+            defs_and_func,
+            meta_storage,
+            state_storage,
+        } = self;
+        let history = &mut *edit.history;
+        let mut disconnected = vec![];
+
+        for &id in &defs_and_func.def().statements {
+            match &mut meta_storage[&id.0] {
+                Meta::Component(meta) => {
+                    let types = state_storage[&id.0].types();
+
+                    for i in 0..types.input_types().len() {
+                        let wire = *meta.inputs.get(&AnyInputSpec(i));
+
+                        if is_dangling(&wire) {
+                            meta.inputs.set(&AnyInputSpec(i), None);
+                            history.record(Change::Input { id, spec: i, wire });
+                            disconnected.push(WireDst::component_input(id, AnyInputSpec(i)));
+                        }
+                    }
+
+                    for i in 0..types.param_types().len() {
+                        let (value, wire) = meta.params.get_mut(&AnyParamSpec(i));
+
+                        if let Some(wire) = wire.downcast_mut::<InternalParamWire>() {
+                            let old = wire.clone();
+
+                            if let Some(removed) = retain_param_wire(wire, &keep) {
+                                disconnected.push(WireDst::component_param(
+                                    id,
+                                    AnyParamSpec(i),
+                                    removed.cv.natural_value,
+                                ));
+                            }
+
+                            if *wire != old {
+                                let value = *value.downcast_ref::<Value>().unwrap();
+                                history.record(Change::param((id, i), value, Some(old)));
+                            }
+                        }
+                    }
+                }
+                Meta::Function { inputs, params, .. } => {
+                    for (i, param) in params.iter_mut().enumerate() {
+                        let old = param.wire.clone();
+
+                        if let Some(removed) = retain_param_wire(&mut param.wire, &keep) {
+                            disconnected.push(WireDst::component_param(
+                                id,
+                                AnyParamSpec(i),
+                                removed.cv.natural_value,
+                            ));
+                        }
+
+                        if param.wire != old {
+                            history.record(Change::param((id, i), param.natural_value, Some(old)));
+                        }
+                    }
+
+                    let dangling = (&*inputs)
+                        .into_iter()
+                        .filter(|(_, wire)| is_dangling(*wire))
+                        .map(|(spec, wire)| (spec, *wire))
+                        .collect::<Vec<_>>();
+
+                    for (spec, wire) in dangling {
+                        inputs.set(&spec, None);
+                        history.record(Change::Input {
+                            id,
+                            spec: spec.0,
+                            wire,
+                        });
+                        disconnected.push(WireDst::component_input(id, spec));
+                    }
+                }
+            }
+        }
+
+        let func = defs_and_func.def.func_id();
+        let out_wires = &mut defs_and_func.def_mut().out_wires;
+        let dangling = (&*out_wires)
+            .into_iter()
+            .filter(|(_, wire)| is_dangling(*wire))
+            .map(|(spec, wire)| (spec, *wire))
+            .collect::<Vec<_>>();
+
+        for (spec, wire) in dangling {
+            out_wires.set(&spec, None);
+            history.record(Change::Output {
+                func,
+                spec: spec.id(),
+                wire,
+            });
+            disconnected.push(WireDst::func_output(spec));
+        }
+
+        disconnected
+    }
+}
+
+fn for_each_call<C>(
+    funcs: &Funcs,
+    meta: &UidMap<Meta<C>>,
+    mut state: MapWithPathMut<'_, C>,
+    statements: &[ComponentId],
+    target: FuncId,
+    func: &mut dyn FnMut(&Funcs, &UidMap<Meta<C>>, MapWithPathMut<'_, C>),
+) where
+    C: AnyComponent,
+{
+    for id in statements {
+        if let Meta::Function { func_id, .. } = &meta[id.0] {
+            let mut call_state = state.as_mut().append_path(id.0);
+
+            if *func_id == target {
+                func(funcs, meta, call_state.as_mut());
+            }
+
+            for_each_call(
+                funcs,
+                meta,
+                call_state,
+                &funcs[func_id.0].statements,
+                target,
+                func,
+            );
+        }
+    }
+}
+
+fn remove_function_state<C>(
+    funcs: &Funcs,
+    meta: &UidMap<Meta<C>>,
+    mut state: MapWithPathMut<'_, C>,
+    statements: &[ComponentId],
+    changes: &mut Vec<Change<C>>,
+) where
+    C: AnyComponent,
+{
+    for id in statements {
+        match &meta[id.0] {
+            Meta::Component(_) => changes.push(state.remove_saved(id.0)),
+            Meta::Function { func_id, .. } => remove_function_state(
+                funcs,
+                meta,
+                state.as_mut().append_path(id.0),
+                &funcs[func_id.0].statements,
+                changes,
+            ),
+        }
+    }
+}
+
+/// Copy the natural value and wire of `from` to every other param in `links`.
+fn copy_to_linked<C, V>(
+    meta: &mut UidMap<Meta<C>>,
+    links: &[(ComponentId, SpecId)],
+    from: (ComponentId, SpecId),
+) where
+    C: AnyComponent,
+    V: Clone + 'static,
+{
+    if links.len() < 2 {
+        return;
+    }
+
+    let (value, wire) = meta[from.0 .0].param_mut(&AnyParamSpec(from.1)).unwrap();
+    let value = value.downcast_ref::<V>().unwrap().clone();
+    let wire = wire.downcast_ref::<Option<ParamWire<V>>>().cloned();
+
+    for &(id, spec) in links {
+        if (id, spec) == from {
+            continue;
+        }
+
+        let (other_value, other_wire) = meta[id.0].param_mut(&AnyParamSpec(spec)).unwrap();
+        *other_value.downcast_mut::<V>().unwrap() = value.clone();
+        if let (Some(other_wire), Some(wire)) =
+            (other_wire.downcast_mut::<Option<ParamWire<V>>>(), &wire)
+        {
+            *other_wire = wire.clone();
+        }
+    }
+}
+
+/// The change that restores the current natural value and wire of a `V` param.
+fn save_param<C, V>(meta: &mut UidMap<Meta<C>>, (id, spec): (ComponentId, SpecId)) -> Change<C>
+where
+    C: AnyComponent,
+    V: Clone + 'static,
+{
+    let (value, wire) = meta[id.0].param_mut(&AnyParamSpec(spec)).unwrap();
+
+    Change::param(
+        (id, spec),
+        value.downcast_ref::<V>().unwrap().clone(),
+        wire.downcast_ref::<Option<ParamWire<V>>>().cloned(),
+    )
+}
+
+/// A param being edited through `FuncInstanceMut::param`, which copies any changes to the params
+/// linked to it when it's dropped.
+struct LinkedParam<'a, C, V>
+where
+    C: AnyComponent,
+    V: Clone + 'static,
+{
+    meta: &'a mut UidMap<Meta<C>>,
+    history: &'a mut History<C>,
+    links: Vec<(ComponentId, SpecId)>,
+    param: (ComponentId, SpecId),
+    /// How many levels of CV deep we're editing, where 0 is the param itself.
+    depth: usize,
+    /// The natural value and wire of the param before it was edited, which are added to the
+    /// history when this is dropped if the param has changed.
+    saved: Option<(V, Option<Option<ParamWire<V>>>)>,
+    _marker: PhantomData<V>,
+}
+
+/// Copy a value if its type allows it, so that components can be added to functions without
+/// requiring them to be `Clone` unless the function is called.
+trait MaybeClone: Sized {
+    /// A copy of the value, or `None` if it can't be copied.
+    fn maybe_clone(&self) -> Option<Self>;
+}
+
+impl<T> MaybeClone for T {
+    default fn maybe_clone(&self) -> Option<Self> {
+        None
+    }
+}
+
+impl<T> MaybeClone for T
+where
+    T: Clone,
+{
+    fn maybe_clone(&self) -> Option<Self> {
+        Some(self.clone())
+    }
+}
+
+/// Compare two values if their type allows it, so that we can skip adding edits that don't
+/// change anything to the history.
+trait MaybeEq {
+    /// Whether the values are equal, or `false` if they can't be compared.
+    fn maybe_eq(&self, other: &Self) -> bool;
+}
+
+impl<T> MaybeEq for T {
+    default fn maybe_eq(&self, _: &Self) -> bool {
+        false
+    }
+}
+
+impl<T> MaybeEq for T
+where
+    T: PartialEq,
+{
+    fn maybe_eq(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl<C, V> LinkedParam<'_, C, V>
+where
+    C: AnyComponent,
+    V: Clone + 'static,
+{
+    /// Keep the param as it was before being edited, if we haven't already, so that we can tell
+    /// whether it changed once we're dropped.
+    fn save(&mut self) {
+        if self.saved.is_none() {
+            let (value, wire) = self.storage();
+            let saved = (value.clone(), wire.map(|wire| wire.clone()));
+
+            self.saved = Some(saved);
+        }
+    }
+
+    fn storage(&mut self) -> (&mut V, Option<&mut Option<ParamWire<V>>>) {
+        let (value, wire) = self.meta[self.param.0 .0]
+            .param_mut(&AnyParamSpec(self.param.1))
+            .unwrap();
+
+        (value.downcast_mut().unwrap(), wire.downcast_mut())
+    }
+
+    /// The CV that we're editing, which is only valid if `depth` is at least 1.
+    fn cv_value(&mut self) -> &mut ParamValue<V, Box<ParamWire<V>>> {
+        let depth = self.depth;
+        let mut cv = &mut self.storage().1.unwrap().as_mut().unwrap().cv;
+
+        for _ in 1..depth {
+            cv = &mut cv.wire.as_mut().unwrap().cv;
+        }
+
+        cv
+    }
+}
+
+impl<C, V> AsMut<V> for LinkedParam<'_, C, V>
+where
+    C: AnyComponent,
+    V: Clone + 'static,
+{
+    fn as_mut(&mut self) -> &mut V {
+        self.save();
+
+        if self.depth == 0 {
+            self.storage().0
+        } else {
+            &mut self.cv_value().natural_value
+        }
+    }
+}
+
+impl<C, V> Param<V> for LinkedParam<'_, C, V>
+where
+    C: AnyComponent,
+    V: Clone + 'static,
+{
+    type CV = Self;
+
+    fn wire(&mut self, src: WireSrc, cv: V) {
+        self.save();
+
+        let wire = ParamWire {
+            src,
+            cv: ParamValue {
+                natural_value: cv,
+                wire: None,
+            },
+        };
+
+        if self.depth == 0 {
+            *self.storage().1.unwrap() = Some(wire);
+        } else {
+            self.cv_value().wire = Some(Box::new(wire));
+        }
+    }
+
+    fn cv(mut self) -> Option<Self::CV> {
+        let is_wired = if self.depth == 0 {
+            self.storage().1.map_or(false, |wire| wire.is_some())
+        } else {
+            self.cv_value().wire.is_some()
+        };
+
+        if is_wired {
+            self.depth += 1;
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+
+impl<C, V> Drop for LinkedParam<'_, C, V>
+where
+    C: AnyComponent,
+    V: Clone + 'static,
+{
+    fn drop(&mut self) {
+        copy_to_linked::<C, V>(self.meta, &self.links, self.param);
+
+        let (value, wire) = match self.saved.take() {
+            Some(saved) => saved,
+            None => return,
+        };
+        let (new_value, new_wire) = self.storage();
+        let is_unchanged = <V as MaybeEq>::maybe_eq(new_value, &value)
+            && match (new_wire, &wire) {
+                (Some(new_wire), Some(wire)) => {
+                    <Option<ParamWire<V>> as MaybeEq>::maybe_eq(new_wire, wire)
+                }
+                _ => true,
+            };
+
+        if is_unchanged {
+            return;
+        }
+
+        let params = if self.links.is_empty() {
+            vec![self.param]
+        } else {
+            self.links.clone()
+        };
+
+        self.history.begin();
+        for param in params {
+            self.history
+                .record(Change::param(param, value.clone(), wire.clone()));
+        }
+        self.history.end();
+    }
+}
+
+/// Disconnect a param wire if its source doesn't satisfy `keep`, returning the removed wire, or
+/// otherwise disconnect any of its CV wires that don't.
+fn retain_param_wire(
+    wire: &mut InternalParamWire,
+    keep: &impl Fn(&WireSrc) -> bool,
+) -> InternalParamWire {
+    if wire.as_ref().map_or(false, |wire| !keep(&wire.src)) {
+        return wire.take();
+    }
+
+    if let Some(ParamWire { cv, .. }) = wire {
+        let mut cv_wire = cv.wire.take().map(|cv_wire| *cv_wire);
+        retain_param_wire(&mut cv_wire, keep);
+        cv.wire = cv_wire.map(Box::new);
+    }
+
+    None
+}
+
 impl<C, InputSpec, OutputSpec, Def> FuncInstanceRef<'_, C, Def>
 where
     InputSpec: RuntimeSpecifier,
@@ -1254,6 +2991,8 @@ where
             ctx,
             state: self.state_storage.as_ref(),
             meta: self.meta_storage,
+            scenes: None,
+            files: None,
         }
         .read_wire(self.defs_and_func.defs, wire)
     }
@@ -1265,6 +3004,7 @@ where
 {
     ctx: &'a Ctx,
     functions: &'a Funcs,
+    id: ComponentId,
     cur_meta: &'a ComponentMeta<C>,
 }
 
@@ -1277,6 +3017,16 @@ where
     fn sample_rate(&self) -> u32 {
         self.ctx.sample_rate()
     }
+
+    #[inline]
+    fn transport(&self) -> Transport {
+        self.ctx.transport()
+    }
+
+    #[inline]
+    fn files(&self) -> &Files {
+        self.ctx.files()
+    }
 }
 
 impl<'a, Ctx, C> AnyMeta for SingleComponentCtx<'a, Ctx, C>
@@ -1308,4 +3058,195 @@ where
     fn read_wire(&self, wire: WireSrc) -> Option<Self::Iter> {
         self.ctx.read_wire(self.functions, wire)
     }
+
+    #[inline]
+    fn natural_value_override(&self, param: AnyParamSpec) -> Option<Value> {
+        self.ctx
+            .func_param(self.functions, self.id, AnyParamSpec(param.0))
+            .or_else(|| {
+                let natural = *self.cur_meta.params.get(&param).0.downcast_ref::<Value>()?;
+
+                self.ctx.scenes()?.natural_value(self.id, param.0, natural)
+            })
+    }
+}
+
+/// Whether `f` is true for the source of a param wire and of each of its CV wires.
+fn param_wire_all(wire: &ParamWire, f: &impl Fn(&WireSrc) -> bool) -> bool {
+    f(&wire.src)
+        && wire
+            .cv
+            .wire
+            .as_ref()
+            .map_or(true, |cv| param_wire_all(cv, f))
+}
+
+/// What's shared by every component of `Main` while it's run for a block.
+struct BlockState<'a, Ctx> {
+    frames: &'a [Ctx],
+    scenes: &'a Scenes,
+    /// The position of the crossfader for each frame
+    crossfader: &'a [Value],
+    files: &'a Files,
+    /// The outputs of the components that have been run for this block so far
+    outputs: &'a UidMap<BlockOutputs>,
+}
+
+/// The context of a component of `Main` while it's run for a block.
+struct BlockComponentCtx<'a, Ctx, C>
+where
+    C: AnyComponent,
+{
+    block: &'a BlockState<'a, Ctx>,
+    id: ComponentId,
+    cur_meta: &'a ComponentMeta<C>,
+}
+
+/// The context of a component of `Main` for one frame of a block.
+struct BlockFrameCtx<'a, Ctx, C>
+where
+    C: AnyComponent,
+{
+    block: &'a BlockState<'a, Ctx>,
+    id: ComponentId,
+    cur_meta: &'a ComponentMeta<C>,
+    frame: usize,
+}
+
+impl<'a, Ctx, C> ContextMeta for BlockComponentCtx<'a, Ctx, C>
+where
+    C: AnyComponent,
+    Ctx: ContextMeta,
+{
+    /// The sample rate at the start of the block
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.block.frames[0].sample_rate()
+    }
+
+    /// The transport at the start of the block
+    #[inline]
+    fn transport(&self) -> Transport {
+        self.block.frames[0].transport()
+    }
+
+    #[inline]
+    fn files(&self) -> &Files {
+        self.block.files
+    }
+}
+
+impl<'a, Ctx, C> AnyMeta for BlockComponentCtx<'a, Ctx, C>
+where
+    C: AnyComponent,
+{
+    type ParamStorage = C::ParamStorage;
+    type InputStorage = C::InputStorage;
+
+    #[inline]
+    fn params(&self) -> &Self::ParamStorage {
+        &self.cur_meta.params
+    }
+
+    #[inline]
+    fn inputs(&self) -> &Self::InputStorage {
+        &self.cur_meta.inputs
+    }
+}
+
+impl<'a, Ctx, C> AnyBlockContext for BlockComponentCtx<'a, Ctx, C>
+where
+    C: AnyComponent,
+    Ctx: GetFunctionParam + ContextMeta,
+    Ctx::InputSpec: RuntimeSpecifier,
+{
+    type Frame = BlockFrameCtx<'a, Ctx, C>;
+
+    #[inline]
+    fn frames(&self) -> usize {
+        self.block.frames.len()
+    }
+
+    #[inline]
+    fn frame(&self, i: usize) -> Self::Frame {
+        BlockFrameCtx {
+            block: self.block,
+            id: self.id,
+            cur_meta: self.cur_meta,
+            frame: i,
+        }
+    }
+}
+
+impl<'a, Ctx, C> ContextMeta for BlockFrameCtx<'a, Ctx, C>
+where
+    C: AnyComponent,
+    Ctx: ContextMeta,
+{
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.block.frames[self.frame].sample_rate()
+    }
+
+    #[inline]
+    fn transport(&self) -> Transport {
+        self.block.frames[self.frame].transport()
+    }
+
+    #[inline]
+    fn files(&self) -> &Files {
+        self.block.files
+    }
+}
+
+impl<'a, Ctx, C> AnyMeta for BlockFrameCtx<'a, Ctx, C>
+where
+    C: AnyComponent,
+{
+    type ParamStorage = C::ParamStorage;
+    type InputStorage = C::InputStorage;
+
+    #[inline]
+    fn params(&self) -> &Self::ParamStorage {
+        &self.cur_meta.params
+    }
+
+    #[inline]
+    fn inputs(&self) -> &Self::InputStorage {
+        &self.cur_meta.inputs
+    }
+}
+
+impl<'a, Ctx, C> AnyContext for BlockFrameCtx<'a, Ctx, C>
+where
+    C: AnyComponent,
+    Ctx: GetFunctionParam,
+    Ctx::InputSpec: RuntimeSpecifier,
+{
+    type Iter = PossiblyEither<BlockIter<'a>, Ctx::Iter>;
+
+    #[inline]
+    fn read_wire(&self, Wire(wire): WireSrc) -> Option<Self::Iter> {
+        let outputs: &'a UidMap<BlockOutputs> = self.block.outputs;
+
+        match wire.element() {
+            ElementSpecifier::Component { id } => Some(PossiblyEither::Left(
+                outputs[&id.0].read(wire.output_id().0, self.frame),
+            )),
+            ElementSpecifier::FuncInputs => self.block.frames[self.frame]
+                .input(Ctx::InputSpec::from_id(wire.io_index))
+                .map(PossiblyEither::Right),
+        }
+    }
+
+    #[inline]
+    fn natural_value_override(&self, param: AnyParamSpec) -> Option<Value> {
+        // `Main` is never called, so only the scenes can override a param
+        let natural = *self.cur_meta.params.get(&param).0.downcast_ref::<Value>()?;
+
+        self.block
+            .scenes
+            .mix_at(self.block.crossfader[self.frame])
+            .natural_value(self.id, param.0, natural)
+    }
 }
@@ -0,0 +1,481 @@
+//! Octatrack-style scenes. A scene stores alternate natural values for any number of params in
+//! a rack. Two scenes can be assigned to the A and B sides of the crossfader, and as the
+//! crossfader moves from A to B continuous params are interpolated between the two scenes while
+//! binary and discrete params switch over at the midpoint. Params that are only in one of the
+//! two scenes are crossfaded to or from their natural value.
+
+use crate::{
+    context::GetFunctionParam,
+    params::{average_output, HasStorage},
+    rack::{ComponentId, InternalWire, Meta},
+    AnyComponent, Rack, RefRuntimeSpecifier, RuntimeSpecifier, SpecId, Types, Uid, UidMap, Value,
+    ValueKind, ValueType, WireError,
+};
+use std::{collections::HashMap, fmt};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SceneId(pub(crate) Uid);
+
+impl fmt::Display for SceneId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "scene::{}", self.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct SceneValue {
+    value: Value,
+    kind: ValueKind,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Scene {
+    values: HashMap<(ComponentId, SpecId), SceneValue>,
+}
+
+impl Scene {
+    /// The number of params that this scene stores a value for.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The values that this scene stores along with their params, sorted by component and param
+    /// so that they're always written in the same order.
+    pub(crate) fn sorted_values(&self) -> Vec<(ComponentId, SpecId, Value)> {
+        let mut values = self
+            .values
+            .iter()
+            .map(|(&(id, param), value)| (id, param, value.value))
+            .collect::<Vec<_>>();
+
+        values.sort_by_key(|&(id, param, _)| (id.0, param));
+        values
+    }
+}
+
+/// The values that the scenes store for the params of one component, along with the scene and
+/// param of each.
+pub(crate) type ComponentScenes = Vec<(SceneId, SpecId, SceneValue)>;
+
+#[derive(Debug, Default)]
+pub struct Scenes {
+    scenes: UidMap<Scene>,
+    a: Option<SceneId>,
+    b: Option<SceneId>,
+    /// The position of the crossfader, from -1 (fully A) to 1 (fully B).
+    crossfader: Value,
+    /// The input of the rack that controls the crossfader, if any. If this is set then the
+    /// natural value of the crossfader is ignored.
+    crossfader_input: Option<SpecId>,
+}
+
+impl Scenes {
+    /// Read the crossfader for this tick.
+    pub(crate) fn mix<Ctx>(&self, ctx: &Ctx) -> SceneMix<'_>
+    where
+        Ctx: GetFunctionParam,
+        Ctx::InputSpec: RuntimeSpecifier,
+    {
+        self.mix_at(self.crossfader_position(ctx))
+    }
+
+    /// The position of the crossfader for this tick, from its input if it has one.
+    pub(crate) fn crossfader_position<Ctx>(&self, ctx: &Ctx) -> Value
+    where
+        Ctx: GetFunctionParam,
+        Ctx::InputSpec: RuntimeSpecifier,
+    {
+        match self.crossfader_input {
+            Some(input) => average_output(ctx.input(Ctx::InputSpec::from_id(input))),
+            None => self.crossfader,
+        }
+    }
+
+    /// Crossfade between the scenes with the crossfader at `position`.
+    pub(crate) fn mix_at(&self, position: Value) -> SceneMix<'_> {
+        SceneMix {
+            scenes: self,
+            position,
+        }
+    }
+
+    /// Swap the values that every scene stores for the params of `id` with `saved`, which takes
+    /// them out of the scenes when the component is removed or replaced and puts them back when
+    /// that's undone. Values for scenes that have since been removed are dropped.
+    pub(crate) fn swap_component(&mut self, id: ComponentId, saved: &mut ComponentScenes) {
+        let mut current = vec![];
+
+        for (scene_id, scene) in self.scenes.iter_mut() {
+            scene.values.retain(|&(component, param), value| {
+                if component == id {
+                    current.push((SceneId(scene_id), param, *value));
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+
+        for (scene, param, value) in saved.drain(..) {
+            if let Some(scene) = self.scenes.get_mut(scene.0) {
+                scene.values.insert((id, param), value);
+            }
+        }
+
+        *saved = current;
+    }
+
+    /// Every scene, sorted by ID so that they're always written in the same order.
+    pub(crate) fn sorted(&self) -> Vec<(SceneId, &Scene)> {
+        let mut scenes = (&self.scenes)
+            .into_iter()
+            .map(|(id, scene)| (SceneId(id), scene))
+            .collect::<Vec<_>>();
+
+        scenes.sort_by_key(|(id, _)| id.0);
+        scenes
+    }
+
+    fn scene_value(
+        &self,
+        scene: Option<SceneId>,
+        key: (ComponentId, SpecId),
+    ) -> Option<SceneValue> {
+        self.scenes.get(scene?.0)?.values.get(&key).copied()
+    }
+}
+
+/// The scenes of a rack along with the position of the crossfader for this tick.
+pub struct SceneMix<'a> {
+    scenes: &'a Scenes,
+    position: Value,
+}
+
+impl SceneMix<'_> {
+    /// The natural value of a param after crossfading between scenes A and B, or `None` if
+    /// neither scene stores a value for this param.
+    pub(crate) fn natural_value(
+        &self,
+        id: ComponentId,
+        param: SpecId,
+        natural: Value,
+    ) -> Option<Value> {
+        let a = self.scenes.scene_value(self.scenes.a, (id, param));
+        let b = self.scenes.scene_value(self.scenes.b, (id, param));
+        let kind = a.or(b)?.kind;
+        let (a, b) = (
+            a.map_or(natural, |a| a.value),
+            b.map_or(natural, |b| b.value),
+        );
+        let amount = ((self.position + 1.) / 2.).max(0.).min(1.);
+
+        Some(match kind {
+            ValueKind::Continuous => a + (b - a) * amount,
+            ValueKind::Binary | ValueKind::Discrete(_) | ValueKind::Midi | ValueKind::File => {
+                if amount < 0.5 {
+                    a
+                } else {
+                    b
+                }
+            }
+        })
+    }
+}
+
+impl<C, InputSpec, OutputSpec> Rack<C, InputSpec, OutputSpec>
+where
+    C: AnyComponent,
+    OutputSpec: HasStorage<InternalWire>,
+{
+    #[inline]
+    pub fn new_scene(&mut self) -> SceneId {
+        let id = self.uid_gen.next();
+        self.scenes.scenes.insert(id, Scene::default());
+        SceneId(id)
+    }
+
+    /// Remove a scene, unassigning it from the crossfader if necessary.
+    pub fn remove_scene(&mut self, id: SceneId) -> Option<Scene> {
+        let scenes = &mut self.scenes;
+
+        if scenes.a == Some(id) {
+            scenes.a = None;
+        }
+        if scenes.b == Some(id) {
+            scenes.b = None;
+        }
+
+        scenes.scenes.remove(id.0)
+    }
+
+    /// Add an empty scene with an ID that didn't come from this rack, for loading a saved rack.
+    /// Returns `false` if there's already a scene with this ID.
+    pub(crate) fn new_scene_with_id(&mut self, id: SceneId) -> bool {
+        if self.scenes.scenes.get(id.0).is_some() {
+            return false;
+        }
+
+        self.uid_gen.reserve(id.0);
+        self.scenes.scenes.insert(id.0, Scene::default());
+        true
+    }
+
+    #[inline]
+    pub fn scene(&self, id: SceneId) -> Option<&Scene> {
+        self.scenes.scenes.get(id.0)
+    }
+
+    /// Store a natural value for a param in a scene. This can be a `Value` param of any
+    /// component in the rack or a param of a function call. Returns `false` if the scene or the
+    /// param doesn't exist, or if the param isn't a `Value` param.
+    pub fn set_scene_param<S: RefRuntimeSpecifier>(
+        &mut self,
+        scene: SceneId,
+        component: ComponentId,
+        param: S,
+        value: Value,
+    ) -> bool {
+        let param = param.id();
+        let kind = match self.meta_storage.get(component.0) {
+            Some(meta) if meta.param_value(param).is_none() => return false,
+            Some(Meta::Component(_)) => {
+                match self.state_storage[&component.0]
+                    .types()
+                    .param_types()
+                    .nth(param)
+                {
+                    Some(ty) if ty.kind != ValueKind::File => ty.kind,
+                    _ => return false,
+                }
+            }
+            Some(Meta::Function { .. }) => ValueKind::Continuous,
+            None => return false,
+        };
+
+        match self.scenes.scenes.get_mut(scene.0) {
+            Some(scene) => {
+                scene
+                    .values
+                    .insert((component, param), SceneValue { value, kind });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stop storing a value for a param in a scene, returning the value that was stored.
+    pub fn clear_scene_param<S: RefRuntimeSpecifier>(
+        &mut self,
+        scene: SceneId,
+        component: ComponentId,
+        param: S,
+    ) -> Option<Value> {
+        self.scenes
+            .scenes
+            .get_mut(scene.0)?
+            .values
+            .remove(&(component, param.id()))
+            .map(|value| value.value)
+    }
+
+    #[inline]
+    pub fn scene_param<S: RefRuntimeSpecifier>(
+        &self,
+        scene: SceneId,
+        component: ComponentId,
+        param: S,
+    ) -> Option<Value> {
+        self.scenes
+            .scene_value(Some(scene), (component, param.id()))
+            .map(|value| value.value)
+    }
+
+    /// Assign a scene to the A side of the crossfader, returning `false` if the scene doesn't
+    /// exist.
+    pub fn set_scene_a(&mut self, scene: Option<SceneId>) -> bool {
+        if scene.map_or(false, |scene| self.scenes.scenes.get(scene.0).is_none()) {
+            return false;
+        }
+
+        self.scenes.a = scene;
+        true
+    }
+
+    /// Assign a scene to the B side of the crossfader, returning `false` if the scene doesn't
+    /// exist.
+    pub fn set_scene_b(&mut self, scene: Option<SceneId>) -> bool {
+        if scene.map_or(false, |scene| self.scenes.scenes.get(scene.0).is_none()) {
+            return false;
+        }
+
+        self.scenes.b = scene;
+        true
+    }
+
+    #[inline]
+    pub fn scene_a(&self) -> Option<SceneId> {
+        self.scenes.a
+    }
+
+    #[inline]
+    pub fn scene_b(&self) -> Option<SceneId> {
+        self.scenes.b
+    }
+
+    /// Set the position of the crossfader, from -1 (fully A) to 1 (fully B). This has no effect
+    /// while the crossfader is wired to an input.
+    #[inline]
+    pub fn set_crossfader(&mut self, value: Value) {
+        self.scenes.crossfader = value.max(-1.).min(1.);
+    }
+
+    #[inline]
+    pub fn crossfader(&self) -> Value {
+        self.scenes.crossfader
+    }
+
+    /// The input of the rack that controls the crossfader, if any.
+    #[inline]
+    pub fn crossfader_input(&self) -> Option<InputSpec>
+    where
+        InputSpec: RuntimeSpecifier,
+    {
+        self.scenes.crossfader_input.map(InputSpec::from_id)
+    }
+
+    /// Control the crossfader with an input of the rack, such as a slider, or go back to using
+    /// the value set with `set_crossfader` if `input` is `None`. Returns the input that was
+    /// previously controlling the crossfader, or an error if `input` isn't continuous.
+    pub fn wire_crossfader(
+        &mut self,
+        input: Option<InputSpec>,
+    ) -> Result<Option<InputSpec>, WireError>
+    where
+        InputSpec: RuntimeSpecifier,
+    {
+        if let Some(input) = &input {
+            let ty = input.value_type();
+
+            if ty.kind != ValueKind::Continuous {
+                return Err(WireError::TypeMismatch {
+                    src: ty,
+                    dst: ValueType::continuous(),
+                });
+            }
+        }
+
+        Ok(std::mem::replace(
+            &mut self.scenes.crossfader_input,
+            input.map(|input| input.id()),
+        )
+        .map(InputSpec::from_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SceneMix;
+    use crate::{
+        octahack_components::{
+            amplifier::{params::Specifier::Amount, Amplifier},
+            synth::{params::Specifier::Freq, Synth},
+            OctahackComponent,
+        },
+        AnyInputSpec, AnyOutputSpec, AnyParamSpec, Rack, RefRuntimeSpecifier, ValueType, WireError,
+    };
+
+    type TestRack = Rack<OctahackComponent, AnyInputSpec, AnyOutputSpec>;
+
+    crate::specs! {
+        mod inputs {
+            Fader: crate::Value,
+            Notes: crate::MidiValue
+        }
+    }
+
+    #[test]
+    fn crossfade() {
+        let mut rack = TestRack::new();
+        let mut main = rack.main_mut();
+        let amp = main.push_component(Amplifier);
+        let synth = main.push_component(Synth::new());
+        main.set_param(amp, Amount, 0.5);
+
+        let (a, b) = (rack.new_scene(), rack.new_scene());
+        assert!(rack.set_scene_param(a, amp, Amount, -1.));
+        assert!(rack.set_scene_param(b, amp, Amount, 1.));
+        assert!(rack.set_scene_param(b, synth, Freq, 0.25));
+        assert!(rack.set_scene_a(Some(a)));
+        assert!(rack.set_scene_b(Some(b)));
+
+        let at = |rack: &TestRack, position, id, param: AnyParamSpec| {
+            SceneMix {
+                scenes: &rack.scenes,
+                position,
+            }
+            .natural_value(id, param.0, 0.5)
+        };
+        let amount = AnyParamSpec(Amount.id());
+        let freq = AnyParamSpec(Freq.id());
+
+        assert_eq!(at(&rack, -1., amp, amount), Some(-1.));
+        assert_eq!(at(&rack, 0., amp, amount), Some(0.));
+        assert_eq!(at(&rack, 1., amp, amount), Some(1.));
+        // Only B has a value for the frequency, so A uses the natural value
+        assert_eq!(at(&rack, -1., synth, freq), Some(0.5));
+        assert_eq!(at(&rack, 0., synth, freq), Some(0.375));
+
+        assert_eq!(rack.clear_scene_param(b, synth, Freq), Some(0.25));
+        assert_eq!(at(&rack, 0., synth, freq), None);
+
+        rack.remove_scene(a);
+        assert_eq!(rack.scene_a(), None);
+        assert_eq!(at(&rack, -1., amp, amount), Some(0.5));
+    }
+
+    #[test]
+    fn scenes_follow_components() {
+        let mut rack = TestRack::new();
+        let amp = rack.main_mut().push_component(Amplifier);
+        let scene = rack.new_scene();
+        assert!(rack.set_scene_param(scene, amp, Amount, 1.));
+
+        // The synth's frequency has the same ID as the amount, but the value was meant for the
+        // amplifier
+        rack.main_mut()
+            .replace_component(amp, Synth::new())
+            .unwrap();
+        assert_eq!(rack.scene_param(scene, amp, Freq), None);
+        assert!(rack.undo());
+        assert_eq!(rack.scene_param(scene, amp, Amount), Some(1.));
+
+        rack.main_mut().remove_component(amp).unwrap();
+        assert!(rack.scene(scene).unwrap().is_empty());
+        assert!(rack.undo());
+        assert_eq!(rack.scene_param(scene, amp, Amount), Some(1.));
+    }
+
+    #[test]
+    fn crossfader_input() {
+        let mut rack = Rack::<OctahackComponent, inputs::Specifier, AnyOutputSpec>::new();
+
+        assert_eq!(
+            rack.wire_crossfader(Some(inputs::Specifier::Notes)).err(),
+            Some(WireError::TypeMismatch {
+                src: ValueType::midi(),
+                dst: ValueType::continuous(),
+            })
+        );
+        assert!(rack
+            .wire_crossfader(Some(inputs::Specifier::Fader))
+            .unwrap()
+            .is_none());
+        assert!(rack.wire_crossfader(None).unwrap() == Some(inputs::Specifier::Fader));
+    }
+}